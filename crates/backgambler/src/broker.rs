@@ -1,9 +1,16 @@
 use derive_builder::Builder;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 
 use crate::{
-    data::Bar,
+    data::{Bar, Symbol},
     errors::ErrorRepr,
-    order::{Fill, Order, OrderKind},
+    order::{Fill, Order, OrderKind, OrderTiming, TimeInForce},
+    units::{Price, Qty},
 };
 
 pub trait Broker {
@@ -11,58 +18,473 @@ pub trait Broker {
 
     /// just for backtest
     fn set_lastest_bar(&mut self, bar: &Bar);
+
+    /// forget the state of a previous run
+    fn reset(&mut self) {}
+
+    /// seed every random draw from now on, a no-op for deterministic brokers
+    fn set_seed(&mut self, _: u64) {}
+
+    /// check that the configuration makes sense, e.g. no negative cost
+    fn validate(&self) -> Result<(), ErrorRepr> {
+        Ok(())
+    }
+
+    /// outcomes of the orders executed so far, if tracked
+    fn stats(&self) -> BrokerStats {
+        BrokerStats::default()
+    }
 }
 
-#[derive(Clone)]
+/// counts of the outcomes of executed orders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BrokerStats {
+    pub filled: usize,
+    pub partial: usize,
+    /// conditions not met yet, the order is retried on a later bar
+    pub deferred: usize,
+    pub rejected: usize,
+    pub canceled: usize,
+    pub expired: usize,
+}
+
+impl BrokerStats {
+    pub fn record(&mut self, order: &Order, res: &Result<Fill, ErrorRepr>) {
+        let count = match res {
+            Ok(fill) if fill.qty == order.qty => &mut self.filled,
+            Ok(fill) if fill.qty.is_zero() => &mut self.canceled,
+            Ok(_) => &mut self.partial,
+            Err(ErrorRepr::NotSatisfied(_)) => &mut self.deferred,
+            Err(ErrorRepr::OrderExpired(_)) => &mut self.expired,
+            Err(ErrorRepr::OrderCanceled(_)) => &mut self.canceled,
+            Err(_) => &mut self.rejected,
+        };
+        *count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Cost {
     Ratio(f64),
     Fixed(f64),
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum Commission {
+    /// ratio of the notional
+    Ratio(f64),
+    /// per-share rate with an order minimum, capped at a ratio of the notional
+    PerShare { rate: f64, min: f64, max_pct: f64 },
+    /// ratio of the notional with an order minimum, e.g. on a-shares
+    RatioMin { ratio: f64, min: f64 },
+}
+
+impl Commission {
+    pub fn calc(&self, qty: Qty, price: Price) -> f64 {
+        self.calc_floored(qty, price, self.min())
+    }
+
+    /// commission with `floor` in place of the order minimum
+    pub fn calc_floored(&self, qty: Qty, price: Price, floor: f64) -> f64 {
+        let notional = (qty.abs() * price).0;
+        match *self {
+            Commission::Ratio(ratio) => notional * ratio,
+            Commission::PerShare { .. } if qty.is_zero() => 0.0,
+            Commission::PerShare { rate, max_pct, .. } => (f64::from(qty.abs()) * rate)
+                .max(floor)
+                .min(notional * max_pct),
+            Commission::RatioMin { .. } if qty.is_zero() => 0.0,
+            Commission::RatioMin { ratio, .. } => (notional * ratio).max(floor),
+        }
+    }
+
+    /// the order minimum, 0 if there is none
+    pub fn min(&self) -> f64 {
+        match *self {
+            Commission::Ratio(_) => 0.0,
+            Commission::PerShare { min, .. } | Commission::RatioMin { min, .. } => min,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match *self {
+            Commission::Ratio(r) => r >= 0.0,
+            Commission::PerShare { rate, min, max_pct } => {
+                rate >= 0.0 && min >= 0.0 && max_pct >= 0.0
+            }
+            Commission::RatioMin { ratio, min } => ratio >= 0.0 && min >= 0.0,
+        }
+    }
+}
+
+/// what the minimum commission applies to
+#[derive(Debug, Clone, Copy, Default)]
+pub enum MinCommissionScope {
+    /// every fill pays at least the minimum
+    #[default]
+    PerOrder,
+    /// a round trip from flat back to flat pays at least the minimum, topped up by the
+    /// fill closing it
+    PerRoundTrip,
+}
+
+impl From<f64> for Commission {
+    fn from(ratio: f64) -> Self {
+        Commission::Ratio(ratio)
+    }
+}
+
+/// what to do with an order when no bar has been seen yet
+#[derive(Debug, Clone, Copy, Default)]
+pub enum MissingBarPolicy {
+    /// keep the order until a bar arrives
+    #[default]
+    Defer,
+    Reject,
+}
+
+/// slippage of aggressive orders on top of `slippage`, as ratios of the price at an
+/// `Order::urgency` of 1, scaled linearly by the urgency
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UrgencySlippage {
+    pub market: f64,
+    /// limit orders, only paid when they execute
+    pub limit: f64,
+}
+
+impl UrgencySlippage {
+    pub fn ratio(&self, order: &Order) -> f64 {
+        let base = match order.kind {
+            OrderKind::Market => self.market,
+            OrderKind::Limit { .. } => self.limit,
+        };
+        base * order.urgency
+    }
+}
+
+/// what to do with a buy the cash cannot pay for any of, e.g. because another gambler
+/// sharing the portfolio spent it first
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ShortfallPolicy {
+    /// fill nothing, the order is canceled as unfilled
+    #[default]
+    Cancel,
+    /// defer it to the next bar like an unmet limit, until the cash is there
+    Defer,
+}
+
+/// fill price of a stop triggered by a bar opening beyond its level
+#[derive(Debug, Clone, Copy, Default)]
+pub enum GapPolicy {
+    /// at the stop level, optimistic
+    AtLevel,
+    /// at the reference price of the gapping bar, its open by default
+    #[default]
+    AtOpen,
+}
+
+/// field of the bar orders are executed at, before costs
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ReferencePrice {
+    #[default]
+    Open,
+    Close,
+    /// `(high + low) / 2`
+    Mid,
+    /// `(high + low + close) / 3`
+    Typical,
+    /// `(open + high + low + close) / 4`, a proxy of the volume weighted average price
+    Vwap,
+}
+
+impl ReferencePrice {
+    pub fn of(&self, bar: &Bar) -> f64 {
+        match self {
+            ReferencePrice::Open => bar.open,
+            ReferencePrice::Close => bar.close,
+            ReferencePrice::Mid => (bar.high + bar.low) / 2.0,
+            ReferencePrice::Typical => (bar.high + bar.low + bar.close) / 3.0,
+            ReferencePrice::Vwap => (bar.open + bar.high + bar.low + bar.close) / 4.0,
+        }
+    }
+}
+
+/// what the `vol` of a bar counts
+#[derive(Debug, Clone, Copy, Default)]
+pub enum VolumeUnit {
+    #[default]
+    Shares,
+    /// lots of this many shares, e.g. 100 for tushare's `vol`
+    Lots(u32),
+    /// traded value
+    Currency,
+}
+
+impl VolumeUnit {
+    /// `vol` as a number of shares traded at `price`
+    pub fn shares(&self, vol: f64, price: f64) -> f64 {
+        match *self {
+            VolumeUnit::Shares => vol,
+            VolumeUnit::Lots(n) => vol * n as f64,
+            VolumeUnit::Currency => vol / price,
+        }
+    }
+}
+
+/// computes the cost of filling an order at a price for a quantity
+pub type CommissionFn = Arc<dyn Fn(&Order, f64, i32) -> f64 + Send + Sync>;
+
+/// latest bar of every symbol, shared between the brokers of several gamblers
+pub type PriceMap = Arc<Mutex<HashMap<Symbol, Bar>>>;
+
 #[derive(Clone, Builder)]
 pub struct SimulatedBroker {
     #[builder(default)]
     pub latest: Option<Bar>,
+    #[builder(default = "Commission::Ratio(0.0)", setter(into))]
+    pub commission: Commission,
+    /// commission of the symbols ending with a suffix, e.g. `.SZ`, in place of
+    /// `commission`. the first matching suffix applies
+    #[builder(default)]
+    pub venue_commission: Vec<(String, Commission)>,
+    /// overrides `commission` for fee schedules it cannot express
+    #[builder(default, setter(custom))]
+    pub commission_fn: Option<CommissionFn>,
     #[builder(default)]
-    pub commission: f64,
-    // TODO implement
+    pub min_commission_scope: MinCommissionScope,
+    /// every partial fill of a resting order pays the minimum commission, otherwise the
+    /// minimum is charged once over all the fills of the order
+    #[builder(default = "true")]
+    pub commission_on_partials: bool,
+    /// commission paid since the position was last flat
+    #[builder(setter(skip))]
+    trip_cost: f64,
+    #[builder(setter(skip))]
+    stats: BrokerStats,
+    /// maximum slippage against the trader, each fill draws uniformly up to it
     #[builder(default = "Cost::Ratio(0.0)")]
     pub slippage: Cost,
     #[builder(default)]
-    position: i32,
+    pub urgency_slippage: UrgencySlippage,
+    /// probability that a limit order exactly at the traded price fills, modeling its
+    /// unknown place in the queue. 1 always fills
+    #[builder(default = "1.0")]
+    pub touch_fill_prob: f64,
+    /// base seed of the random draws, the stream of every symbol is derived from it
+    #[builder(default)]
+    pub seed: u64,
+    #[builder(setter(skip))]
+    rng: Option<StdRng>,
+    /// quoted spread around the reference price
+    #[builder(default)]
+    pub spread: f64,
+    #[builder(default)]
+    pub missing_bar: MissingBarPolicy,
+    #[builder(default)]
+    pub reference_price: ReferencePrice,
+    #[builder(default)]
+    pub gap_fill: GapPolicy,
+    /// haircut in basis points applied against the trader on every fill, on top of
+    /// any modeled cost
+    #[builder(default)]
+    pub conservatism: f64,
+    #[builder(default)]
+    pub shortfall: ShortfallPolicy,
+    /// where triggers of conditional orders are looked up
+    #[builder(default)]
+    pub prices: PriceMap,
+    #[builder(default)]
+    position: Qty,
+    /// number of bars averaged into the average daily volume, 0 to disable the cap
+    #[builder(default)]
+    pub adv_window: usize,
+    /// fills are capped at this fraction of the average daily volume
+    #[builder(default = "1.0")]
+    pub max_adv_pct: f64,
+    #[builder(setter(skip))]
+    vols: VecDeque<f64>,
+    /// limit orders fill at most this fraction of the bar volume, approximating the
+    /// depth available at the limit
+    #[builder(default)]
+    pub limit_participation: Option<f64>,
+    /// reject orders growing the position while the reference price is below this,
+    /// e.g. to keep out of penny stocks. reducing orders still execute
+    #[builder(default)]
+    pub min_price: f64,
+    /// unit of the bar volume, converted to shares before capping fills
+    #[builder(default)]
+    pub volume_unit: VolumeUnit,
+    /// limit orders trading through their limit fill at the better traded price,
+    /// otherwise exactly at the limit. makers are always filled at the limit
+    #[builder(default = "true")]
+    pub price_improvement: bool,
+    /// limit fills whose limit does not cross the spread provide liquidity and earn this
+    /// ratio of the notional in place of the commission, making their cost negative
+    #[builder(default)]
+    pub maker_rebate: f64,
 }
 
-impl Broker for SimulatedBroker {
-    fn exec_order(&mut self, order: &Order, wallet: &mut impl Wallet) -> Result<Fill, ErrorRepr> {
+/// random outcomes of one execution, uniform in `[0, 1)`
+#[derive(Debug, Clone, Copy)]
+struct Draws {
+    /// fraction of the maximum slippage
+    slip: f64,
+    /// queue position of a limit order at the touch
+    queue: f64,
+}
+
+impl SimulatedBrokerBuilder {
+    pub fn commission_fn<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Order, f64, i32) -> f64 + Send + Sync + 'static,
+    {
+        self.commission_fn = Some(Some(Arc::new(f)));
+        self
+    }
+
+    /// 0.1% commission and a 1-cent spread
+    pub fn retail_equity(&mut self) -> &mut Self {
+        self.commission(0.001).spread(0.01)
+    }
+
+    /// 0.1% commission and 5bps slippage
+    pub fn crypto(&mut self) -> &mut Self {
+        self.commission(0.001).slippage(Cost::Ratio(0.0005))
+    }
+}
+
+impl SimulatedBroker {
+    fn cost(&self, order: &Order, qty: Qty, price: Price) -> f64 {
+        if let Some(f) = &self.commission_fn {
+            return f(order, price.0, qty.0);
+        }
+        let commission = self.commission_of(&order.sym);
+        let min = commission.min();
+        let closing = !self.position.is_zero() && (self.position + qty).is_zero();
+        let floor = match self.min_commission_scope {
+            MinCommissionScope::PerOrder if !self.commission_on_partials => {
+                (min - order.cost).max(0.0)
+            }
+            MinCommissionScope::PerOrder => min,
+            MinCommissionScope::PerRoundTrip if closing => (min - self.trip_cost).max(0.0),
+            MinCommissionScope::PerRoundTrip => 0.0,
+        };
+        commission.calc_floored(qty, price, floor)
+    }
+
+    /// the commission schedule of the venue of `sym`
+    pub fn commission_of(&self, sym: &str) -> Commission {
+        self.venue_commission
+            .iter()
+            .find(|x| sym.ends_with(&x.0))
+            .map_or(self.commission, |x| x.1)
+    }
+
+    /// mean volume in shares of the last `adv_window` bars, including the latest
+    pub fn adv(&self) -> Option<f64> {
+        if self.adv_window == 0 || self.vols.is_empty() {
+            return None;
+        }
+        Some(self.vols.iter().sum::<f64>() / self.vols.len() as f64)
+    }
+
+    /// `price` made worse for the side of `qty` by `conservatism`, the `impact` ratio and
+    /// the `slip` fraction, in `[0, 1]`, of the maximum slippage
+    fn haircut(&self, price: f64, qty: Qty, slip: f64, impact: f64) -> Price {
+        let base = self.conservatism / 10_000.0 + impact;
+        let (ratio, fixed) = match self.slippage {
+            Cost::Ratio(r) => (base + r * slip, 0.0),
+            Cost::Fixed(f) => (base, f * slip),
+        };
+        Price(if qty > 0 {
+            price * (1.0 + ratio) + fixed
+        } else {
+            price * (1.0 - ratio) - fixed
+        })
+    }
+
+    /// the fill `exec_order` would make against `bar` for given draws, without mutating
+    /// anything. sells beyond the position open a short if `wallet` allows it
+    fn match_order(
+        &self,
+        order: &Order,
+        wallet: &impl Wallet,
+        bar: &Bar,
+        draws: Draws,
+    ) -> Result<Fill, ErrorRepr> {
         use OrderKind::*;
 
         if order.is_expired() {
             return Err(ErrorRepr::OrderExpired(format!("{:?}", order)));
         }
 
-        let bar = self
-            .latest
-            .as_ref()
-            .ok_or(ErrorRepr::NotExists("latest price"))?;
+        if let Some(trigger) = &order.trigger {
+            let prices = self.prices.lock();
+            if !prices
+                .get(&trigger.sym)
+                .is_some_and(|x| trigger.is_met(x.close))
+            {
+                return Err(ErrorRepr::NotSatisfied("trigger condition"));
+            }
+        }
 
-        let price = bar.open;
+        let reference = match order.timing {
+            OrderTiming::Immediate => self.reference_price.of(bar),
+            OrderTiming::OnOpen => bar.open,
+            OrderTiming::OnClose => bar.close,
+        };
+        let grows = (self.position + order.qty).abs() > self.position.abs();
+        if grows && reference < self.min_price {
+            return Err(ErrorRepr::OrderRejected(format!(
+                "price {} below the minimum {}: {:?}",
+                reference, self.min_price, order
+            )));
+        }
+        let impact = self.urgency_slippage.ratio(order);
+        let mut price = self.haircut(reference, order.qty, draws.slip, impact);
+        // a limit inside the spread rests on the book and is filled at the limit, only a
+        // taker can trade through it
+        let (bid, ask) = (reference - self.spread / 2.0, reference + self.spread / 2.0);
+        let maker = matches!(order.kind, Limit { limit, .. }
+            if (order.qty > 0 && limit < ask) || (order.qty < 0 && limit > bid));
+        if let (Limit { limit, .. }, true) = (order.kind, maker || !self.price_improvement) {
+            if (order.qty > 0 && price.0 < limit) || (order.qty < 0 && price.0 > limit) {
+                price = Price(limit);
+            }
+        }
         let mut qty = order.qty;
 
-        let cash = wallet.balance();
-
         if qty > 0 {
             // buy
-            let cost = qty.abs() as f64 * price * self.commission;
+            let cost = self.cost(order, qty, price);
+            let affordable = Qty(((wallet.balance() - cost) / price.0).floor() as i32);
+            if affordable <= 0 && matches!(self.shortfall, ShortfallPolicy::Defer) {
+                return Err(ErrorRepr::NotSatisfied("buying power"));
+            }
             qty = qty
-                .min(bar.vol.floor() as i32)
-                .min(((cash - cost) / price).floor() as i32);
-        } else {
+                .min(Qty(
+                    self.volume_unit.shares(bar.vol, reference).floor() as i32
+                ))
+                .min(affordable);
+        } else if !wallet.allows_short() {
             // sell
             qty = qty.max(-self.position);
         }
 
-        let cost = qty.abs() as f64 * price * self.commission;
-        let fill = Fill {
+        if let Some(adv) = self.adv() {
+            let cap = Qty((self.max_adv_pct * adv).floor() as i32);
+            qty = qty.min(cap).max(-cap);
+        }
+
+        if let (Limit { .. }, Some(pct)) = (order.kind, self.limit_participation) {
+            let cap = Qty::whole(pct * self.volume_unit.shares(bar.vol, reference));
+            qty = qty.min(cap).max(-cap);
+        }
+
+        let cost = self.cost(order, qty, price);
+        let mut fill = Fill {
+            order_id: order.id,
+            risk: order.stop_distance.map(|x| x * f64::from(qty.abs())),
             time: bar.time,
             qty,
             sym: order.sym.clone(),
@@ -70,42 +492,296 @@ impl Broker for SimulatedBroker {
             cost,
         };
 
-        let ok_fill = match order.kind {
+        match order.kind {
             Market => Ok(fill),
-            Limit { limit, stop, .. } => {
-                if qty < 0 && (price >= limit || Some(price) <= stop) {
-                    // sell
-                    return Ok(fill);
-                } else if qty > 0 && (price <= limit) {
-                    // buy
-                    Ok(fill)
-                } else {
-                    Err(ErrorRepr::NotSatisfied("limit order"))
+            Limit {
+                limit,
+                stop,
+                post_only,
+                tif,
+            } => {
+                let price = reference;
+                if post_only && ((qty > 0 && limit >= ask) || (qty < 0 && limit <= bid)) {
+                    return Err(ErrorRepr::OrderRejected(format!(
+                        "post-only order would cross the spread: {:?}",
+                        order
+                    )));
+                }
+
+                let stopped = qty < 0 && price < limit && Some(price) <= stop;
+                let behind_queue = price == limit && draws.queue >= self.touch_fill_prob;
+                let crossed = (qty < 0 && price >= limit) || (qty > 0 && price <= limit);
+                let satisfied = stopped || (crossed && !behind_queue);
+
+                if let (true, GapPolicy::AtLevel, Some(stop)) = (stopped, self.gap_fill, stop) {
+                    fill.price = self.haircut(stop, qty, draws.slip, impact);
+                    fill.cost = self.cost(order, qty, fill.price);
+                } else if maker && self.maker_rebate > 0.0 {
+                    fill.cost = -self.maker_rebate * (qty.abs() * fill.price).0;
+                }
+
+                match tif {
+                    TimeInForce::FOK if qty != order.qty => Err(ErrorRepr::OrderCanceled(format!(
+                        "FOK order cannot be filled fully: {:?}",
+                        order
+                    ))),
+                    _ if satisfied => Ok(fill),
+                    TimeInForce::IOC | TimeInForce::FOK => Err(ErrorRepr::OrderCanceled(format!(
+                        "{:?} order not satisfied: {:?}",
+                        tif, order
+                    ))),
+                    TimeInForce::Day | TimeInForce::GTC => {
+                        Err(ErrorRepr::NotSatisfied("limit order"))
+                    }
                 }
             }
+        }
+    }
+
+    /// dry run an order at the worst slippage, first in the queue, returning the quantity
+    /// that would be filled
+    pub fn validate_order(
+        &self,
+        order: &Order,
+        wallet: &impl Wallet,
+        bar: &Bar,
+    ) -> Result<Qty, ErrorRepr> {
+        let draws = Draws {
+            slip: 1.0,
+            queue: 0.0,
+        };
+        self.match_order(order, wallet, bar, draws).map(|x| x.qty)
+    }
+}
+
+impl SimulatedBroker {
+    fn execute(&mut self, order: &Order, wallet: &mut impl Wallet) -> Result<Fill, ErrorRepr> {
+        let Some(bar) = self.latest.as_ref() else {
+            return Err(match self.missing_bar {
+                MissingBarPolicy::Defer => ErrorRepr::NotSatisfied("latest price"),
+                MissingBarPolicy::Reject => {
+                    ErrorRepr::OrderRejected(format!("no latest price: {:?}", order))
+                }
+            });
         };
 
+        let rng = self
+            .rng
+            .get_or_insert_with(|| StdRng::seed_from_u64(symbol_seed(self.seed, &order.sym)));
+        let draws = Draws {
+            slip: rng.gen(),
+            queue: rng.gen(),
+        };
+        let ok_fill = self.match_order(order, wallet, bar, draws);
+
         if let Ok(Fill {
             qty, price, cost, ..
         }) = &ok_fill
         {
             wallet
-                .pay(*qty as f64 * price + cost)
+                .pay((*qty * *price).0 + cost)
                 .expect("should have enough money");
-            self.position += qty;
+            self.position += *qty;
+            self.trip_cost = if self.position.is_zero() {
+                0.0
+            } else {
+                self.trip_cost + cost
+            };
         }
 
         ok_fill
     }
+}
+
+impl Broker for SimulatedBroker {
+    fn exec_order(&mut self, order: &Order, wallet: &mut impl Wallet) -> Result<Fill, ErrorRepr> {
+        let res = self.execute(order, wallet);
+        self.stats.record(order, &res);
+        res
+    }
+
+    fn set_lastest_bar(&mut self, bar: &Bar) {
+        self.prices.lock().insert(bar.sym.clone(), bar.clone());
+        self.latest.replace(bar.clone());
+        if self.adv_window > 0 {
+            self.vols
+                .push_back(self.volume_unit.shares(bar.vol, bar.close));
+            while self.vols.len() > self.adv_window {
+                self.vols.pop_front();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.latest = None;
+        self.position = Qty(0);
+        self.trip_cost = 0.0;
+        self.stats = Default::default();
+        self.vols.clear();
+        self.rng = None;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = None;
+    }
+
+    fn stats(&self) -> BrokerStats {
+        self.stats
+    }
+
+    fn validate(&self) -> Result<(), ErrorRepr> {
+        let invalid = |msg: String| Err(ErrorRepr::InvalidConfig(msg));
+        let venues = self.venue_commission.iter().map(|x| &x.1);
+        if let Some(c) = std::iter::once(&self.commission)
+            .chain(venues)
+            .find(|x| !x.is_valid())
+        {
+            return invalid(format!("negative commission: {:?}", c));
+        }
+        match self.slippage {
+            Cost::Ratio(r) if !(0.0..1.0).contains(&r) => {
+                return invalid(format!("slippage ratio not in [0, 1): {}", r))
+            }
+            Cost::Fixed(f) if f < 0.0 => return invalid(format!("negative slippage: {}", f)),
+            _ => {}
+        }
+        if self.spread < 0.0 || self.conservatism < 0.0 {
+            return invalid(format!(
+                "negative spread {} or conservatism {}",
+                self.spread, self.conservatism
+            ));
+        }
+        let UrgencySlippage { market, limit } = self.urgency_slippage;
+        if market < 0.0 || limit < 0.0 {
+            return invalid(format!(
+                "negative urgency slippage: {:?}",
+                self.urgency_slippage
+            ));
+        }
+        if self.maker_rebate < 0.0 {
+            return invalid(format!("negative maker rebate: {}", self.maker_rebate));
+        }
+        if self.max_adv_pct <= 0.0 {
+            return invalid(format!("max adv pct not positive: {}", self.max_adv_pct));
+        }
+        if let Some(pct) = self
+            .limit_participation
+            .filter(|x| !(0.0..=1.0).contains(x))
+        {
+            return invalid(format!("limit participation not in [0, 1]: {}", pct));
+        }
+        if !(0.0..=1.0).contains(&self.touch_fill_prob) {
+            return invalid(format!(
+                "touch fill probability not in [0, 1]: {}",
+                self.touch_fill_prob
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// stable mix of a base seed and a symbol, FNV-1a over the symbol bytes
+fn symbol_seed(seed: u64, sym: &str) -> u64 {
+    sym.bytes().fold(seed ^ 0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// fills every order in full at the close without any cost, for signal-only backtests
+#[derive(Clone, Default)]
+pub struct PerfectBroker {
+    pub latest: Option<Bar>,
+}
+
+impl Broker for PerfectBroker {
+    fn exec_order(&mut self, order: &Order, wallet: &mut impl Wallet) -> Result<Fill, ErrorRepr> {
+        if order.is_expired() {
+            return Err(ErrorRepr::OrderExpired(format!("{:?}", order)));
+        }
+
+        let bar = self
+            .latest
+            .as_ref()
+            .ok_or(ErrorRepr::NotExists("latest price"))?;
+
+        let fill = Fill {
+            order_id: order.id,
+            risk: order.stop_distance.map(|x| x * f64::from(order.qty.abs())),
+            time: bar.time,
+            qty: order.qty,
+            sym: order.sym.clone(),
+            price: Price(bar.close),
+            cost: 0.0,
+        };
+        // no buying power check, the cash may go negative
+        wallet.set_balance(wallet.balance() - (fill.qty * fill.price).0);
+        Ok(fill)
+    }
 
     fn set_lastest_bar(&mut self, bar: &Bar) {
         self.latest.replace(bar.clone());
     }
+
+    fn reset(&mut self) {
+        self.latest = None;
+    }
+}
+
+pub type ExecRecord = (Order, Result<Fill, ErrorRepr>);
+
+/// wraps a broker and records every order it executes along with the result
+#[derive(Clone)]
+pub struct RecordingBroker<B> {
+    pub inner: B,
+    records: Arc<Mutex<Vec<ExecRecord>>>,
+}
+
+impl<B> RecordingBroker<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            records: Default::default(),
+        }
+    }
+
+    /// shared handle to the records, still readable once the broker is moved into a gambler
+    pub fn records(&self) -> Arc<Mutex<Vec<ExecRecord>>> {
+        Arc::clone(&self.records)
+    }
+}
+
+impl<B: Broker> Broker for RecordingBroker<B> {
+    fn exec_order(&mut self, order: &Order, wallet: &mut impl Wallet) -> Result<Fill, ErrorRepr> {
+        let res = self.inner.exec_order(order, wallet);
+        self.records.lock().push((order.clone(), res.clone()));
+        res
+    }
+
+    fn set_lastest_bar(&mut self, bar: &Bar) {
+        self.inner.set_lastest_bar(bar);
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    fn validate(&self) -> Result<(), ErrorRepr> {
+        self.inner.validate()
+    }
+
+    fn stats(&self) -> BrokerStats {
+        self.inner.stats()
+    }
 }
 
 pub trait Wallet {
     fn balance(&self) -> f64;
     fn set_balance(&mut self, money: f64);
+    /// sells beyond the position may open a short
+    fn allows_short(&self) -> bool {
+        false
+    }
     fn pay(&mut self, money: f64) -> Option<f64> {
         let rem = self.balance() - money;
         if rem < 0.0 {
@@ -121,7 +797,8 @@ pub trait Wallet {
 mod tests {
     use more_asserts::assert_lt;
 
-    use crate::order::{FixedSizeOrderManager, OrderBuilder};
+    use crate::data::DateTime;
+    use crate::order::{Comparison, FixedSizeOrderManager, OrderBuilder, TriggerCondition};
     use crate::portfolio::SimplePortfolioBuilder;
 
     use super::*;
@@ -132,17 +809,23 @@ mod tests {
             .commission(0.001)
             .build()
             .unwrap();
-        let mut bar = Bar::default();
-        bar.open = 10.0;
-        bar.vol = 10000.0;
+        let bar = Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        };
         bro.set_lastest_bar(&bar);
 
         let mut port = SimplePortfolioBuilder::default()
             .cash(1000.0)
-            .order_manager(FixedSizeOrderManager { size: 10 })
+            .order_manager(FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
             .build()
             .unwrap();
         let mut ord = OrderBuilder::default()
+            .time(DateTime::default())
             .sym("test".into())
             .qty(10)
             .build()
@@ -152,13 +835,13 @@ mod tests {
         assert_eq!(port.cash, 1000.0 - 10.0 * 10.0 * 1.001);
         assert_eq!(port.init_cash, 1000.0);
 
-        ord.qty = 1000;
+        ord.qty = Qty(1000);
         let fill = bro.exec_order(&ord, &mut port).unwrap();
         assert_eq!(fill.qty, 88);
         assert_eq!(fill.price, 10.0);
         assert_lt!((1000.0 - 98.0 * 10.0 * 1.001 - port.cash).abs(), 0.001);
 
-        ord.qty = -1000;
+        ord.qty = Qty(-1000);
         let fill = bro.exec_order(&ord, &mut port).unwrap();
         assert_eq!(fill.qty, -98);
         assert_eq!(fill.price, 10.0);
@@ -174,23 +857,31 @@ mod tests {
             .commission(0.001)
             .build()
             .unwrap();
-        let mut bar = Bar::default();
-        bar.open = 10.0;
-        bar.vol = 10000.0;
+        let mut bar = Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        };
         bro.set_lastest_bar(&bar);
 
         let mut port = SimplePortfolioBuilder::default()
             .cash(1000.0)
-            .order_manager(FixedSizeOrderManager { size: 10 })
+            .order_manager(FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
             .build()
             .unwrap();
 
         let mut ord = OrderBuilder::default()
+            .time(DateTime::default())
             .sym("test".into())
             .qty(10)
             .kind(OrderKind::Limit {
                 limit: 9.0,
                 stop: Some(12.0),
+                post_only: false,
+                tif: TimeInForce::GTC,
             })
             .build()
             .unwrap();
@@ -201,15 +892,20 @@ mod tests {
         bar.open = 8.0;
         bro.set_lastest_bar(&bar);
 
-        ord.qty = 1000;
+        ord.qty = Qty(1000);
 
         let fill = bro.exec_order(&ord, &mut port).unwrap();
         assert_eq!(fill.qty, 124);
         assert_eq!(fill.price, 8.0);
         assert_lt!((1000.0 - 124.0 * 8.0 * 1.001 - port.cash).abs(), 0.001);
 
-        ord.qty = -1000;
-        ord.kind = OrderKind::Limit { limit: 12.0, stop: Some(8.0) };
+        ord.qty = Qty(-1000);
+        ord.kind = OrderKind::Limit {
+            limit: 12.0,
+            stop: Some(8.0),
+            post_only: false,
+            tif: TimeInForce::GTC,
+        };
 
         bar.open = 12.0;
         bro.set_lastest_bar(&bar);
@@ -218,4 +914,956 @@ mod tests {
         assert_eq!(fill.qty, -124);
         assert_eq!(fill.price, 12.0);
     }
+
+    #[test]
+    fn test_broker_gap_fill() {
+        for (gap_fill, expected) in [(GapPolicy::AtOpen, 90.0), (GapPolicy::AtLevel, 95.0)] {
+            let mut bro = SimulatedBrokerBuilder::default()
+                .gap_fill(gap_fill)
+                .build()
+                .unwrap();
+            let mut bar = Bar {
+                open: 100.0,
+                vol: 10000.0,
+                ..Default::default()
+            };
+            bro.set_lastest_bar(&bar);
+
+            let mut port = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            let mut ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(10)
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port).unwrap();
+
+            ord.qty = Qty(-10);
+            ord.kind = OrderKind::Limit {
+                limit: 120.0,
+                stop: Some(95.0),
+                post_only: false,
+                tif: TimeInForce::GTC,
+            };
+            bar.open = 90.0;
+            bro.set_lastest_bar(&bar);
+
+            let fill = bro.exec_order(&ord, &mut port).unwrap();
+            assert_eq!(fill.qty, -10);
+            assert_eq!(fill.price, expected);
+            assert_eq!(port.cash, expected * 10.0);
+        }
+    }
+
+    #[test]
+    fn test_broker_trigger() {
+        let prices = PriceMap::default();
+        let mut bro = SimulatedBrokerBuilder::default()
+            .prices(Arc::clone(&prices))
+            .build()
+            .unwrap();
+        let mut other = SimulatedBrokerBuilder::default()
+            .prices(Arc::clone(&prices))
+            .build()
+            .unwrap();
+        let bar = |sym: &str, close| Bar {
+            sym: sym.into(),
+            open: 10.0,
+            close,
+            vol: 10000.0,
+            ..Default::default()
+        };
+
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("a".into())
+            .qty(10)
+            .trigger(TriggerCondition {
+                sym: "b".into(),
+                comparison: Comparison::Below,
+                level: 90.0,
+            })
+            .build()
+            .unwrap();
+
+        bro.set_lastest_bar(&bar("a", 10.0));
+        let err = bro.exec_order(&ord, &mut port).unwrap_err();
+        assert!(matches!(err, ErrorRepr::NotSatisfied(_)));
+
+        other.set_lastest_bar(&bar("b", 100.0));
+        let err = bro.exec_order(&ord, &mut port).unwrap_err();
+        assert!(matches!(err, ErrorRepr::NotSatisfied(_)));
+
+        other.set_lastest_bar(&bar("b", 85.0));
+        let fill = bro.exec_order(&ord, &mut port).unwrap();
+        assert_eq!(fill.qty, 10);
+        assert_eq!(fill.price, 10.0);
+    }
+
+    #[test]
+    fn test_broker_post_only() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .spread(0.2)
+            .build()
+            .unwrap();
+        let bar = Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        };
+        bro.set_lastest_bar(&bar);
+
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+
+        let mut ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(10)
+            .kind(OrderKind::Limit {
+                limit: 10.1,
+                stop: None,
+                post_only: true,
+                tif: TimeInForce::GTC,
+            })
+            .build()
+            .unwrap();
+
+        let err = bro.exec_order(&ord, &mut port).unwrap_err();
+        assert!(matches!(err, ErrorRepr::OrderRejected(_)));
+
+        ord.kind = OrderKind::Limit {
+            limit: 9.9,
+            stop: None,
+            post_only: true,
+            tif: TimeInForce::GTC,
+        };
+        let err = bro.exec_order(&ord, &mut port).unwrap_err();
+        assert!(matches!(err, ErrorRepr::NotSatisfied(_)));
+        assert_eq!(port.cash, port.init_cash);
+    }
+
+    #[test]
+    fn test_broker_maker_rebate() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .commission(0.002)
+            .spread(0.2)
+            .maker_rebate(0.001)
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let order = |limit, post_only| {
+            OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(10)
+                .kind(OrderKind::Limit {
+                    limit,
+                    stop: None,
+                    post_only,
+                    tif: TimeInForce::GTC,
+                })
+                .build()
+                .unwrap()
+        };
+
+        // below the ask of 10.1, the order is resting on the book and filled at its limit
+        let fill = bro.exec_order(&order(10.05, true), &mut port).unwrap();
+        assert_eq!(fill.price, 10.05);
+        assert_lt!((fill.cost + 0.1005).abs(), 1e-9);
+        assert_lt!((port.cash - 899.6005).abs(), 1e-9);
+
+        // crossing the spread as a taker pays the commission
+        let fill = bro.exec_order(&order(10.2, false), &mut port).unwrap();
+        assert_lt!((fill.cost - 0.2).abs(), 1e-9);
+    }
+
+    #[test]
+    fn test_venue_commission() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .commission(0.0003)
+            // the schedule of the practice run
+            .venue_commission(vec![
+                (
+                    ".SH".into(),
+                    Commission::RatioMin {
+                        ratio: 0.0003,
+                        min: 5.0,
+                    },
+                ),
+                (
+                    ".SZ".into(),
+                    Commission::RatioMin {
+                        ratio: 0.0003,
+                        min: 1.0,
+                    },
+                ),
+            ])
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let mut cost = |sym: &str| {
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym(sym.into())
+                .qty(100)
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port).unwrap().cost
+        };
+
+        // 0.3 on a notional of 1000, below both minimums
+        assert_eq!(cost("600000.SH"), 5.0);
+        assert_eq!(cost("000001.SZ"), 1.0);
+        assert_lt!((cost("AAPL") - 0.3).abs(), 1e-9);
+    }
+
+    #[test]
+    fn test_per_share_commission() {
+        let c = Commission::PerShare {
+            rate: 0.005,
+            min: 1.0,
+            max_pct: 0.01,
+        };
+        // per-share base
+        assert_lt!((c.calc(Qty(1000), Price(50.0)) - 5.0).abs(), 1e-9);
+        // minimum floor
+        assert_lt!((c.calc(Qty(-100), Price(50.0)) - 1.0).abs(), 1e-9);
+        // percentage cap
+        assert_lt!((c.calc(Qty(1000), Price(0.1)) - 1.0).abs(), 1e-9);
+        assert_lt!((c.calc(Qty(10), Price(0.5)) - 0.05).abs(), 1e-9);
+
+        let mut bro = SimulatedBrokerBuilder::default()
+            .commission(c)
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 50.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(100)
+            .build()
+            .unwrap();
+
+        let fill = bro.exec_order(&ord, &mut port).unwrap();
+        assert_eq!(fill.cost, 1.0);
+        assert_lt!((port.cash - (10000.0 - 5000.0 - 1.0)).abs(), 1e-9);
+    }
+
+    #[test]
+    fn test_commission_fn() {
+        // 1% of the first 1000 of notional, 0.5% above
+        let mut bro = SimulatedBrokerBuilder::default()
+            .commission_fn(|_, price, qty| {
+                let notional = price * qty.abs() as f64;
+                notional.min(1000.0) * 0.01 + (notional - 1000.0).max(0.0) * 0.005
+            })
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let mut ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(50)
+            .build()
+            .unwrap();
+
+        let fill = bro.exec_order(&ord, &mut port).unwrap();
+        assert_eq!(fill.cost, 5.0);
+
+        ord.qty = Qty(300);
+        let fill = bro.exec_order(&ord, &mut port).unwrap();
+        assert_eq!(fill.cost, 10.0 + 10.0);
+        assert_eq!(port.cash, 10000.0 - 3500.0 - 25.0);
+    }
+
+    #[test]
+    fn test_broker_conservatism() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .conservatism(10.0)
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let limit = |limit| OrderKind::Limit {
+            limit,
+            stop: None,
+            post_only: false,
+            tif: TimeInForce::GTC,
+        };
+
+        for (qty, kind) in [
+            (100, OrderKind::Market),
+            (100, limit(11.0)),
+            (-100, OrderKind::Market),
+            (-100, limit(9.0)),
+        ] {
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(qty)
+                .kind(kind)
+                .build()
+                .unwrap();
+            let fill = bro.exec_order(&ord, &mut port).unwrap();
+            let expected = if qty > 0 { 10.01 } else { 9.99 };
+            assert_lt!((fill.price.0 - expected).abs(), 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_broker_presets() {
+        let bro = SimulatedBrokerBuilder::default()
+            .retail_equity()
+            .build()
+            .unwrap();
+        assert!(matches!(bro.commission, Commission::Ratio(r) if r == 0.001));
+        assert_eq!(bro.spread, 0.01);
+
+        let bro = SimulatedBrokerBuilder::default().crypto().build().unwrap();
+        assert!(matches!(bro.commission, Commission::Ratio(r) if r == 0.001));
+        assert!(matches!(bro.slippage, Cost::Ratio(r) if r == 0.0005));
+        assert_eq!(bro.spread, 0.0);
+    }
+
+    #[test]
+    fn test_perfect_broker() {
+        let mut bro = PerfectBroker::default();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            close: 12.0,
+            vol: 10.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(100)
+            .build()
+            .unwrap();
+
+        let fill = bro.exec_order(&ord, &mut port).unwrap();
+        assert_eq!(fill.qty, 100);
+        assert_eq!(fill.price, 12.0);
+        assert_eq!(fill.cost, 0.0);
+        assert_eq!(port.cash, 1000.0 - 1200.0);
+    }
+
+    #[test]
+    fn test_broker_missing_bar() {
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(10)
+            .build()
+            .unwrap();
+
+        let mut bro = SimulatedBrokerBuilder::default().build().unwrap();
+        let err = bro.exec_order(&ord, &mut port).unwrap_err();
+        assert!(matches!(err, ErrorRepr::NotSatisfied(_)));
+
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        assert_eq!(bro.exec_order(&ord, &mut port).unwrap().qty, 10);
+
+        let mut bro = SimulatedBrokerBuilder::default()
+            .missing_bar(MissingBarPolicy::Reject)
+            .build()
+            .unwrap();
+        let err = bro.exec_order(&ord, &mut port).unwrap_err();
+        assert!(matches!(err, ErrorRepr::OrderRejected(_)));
+    }
+
+    #[test]
+    fn test_broker_time_in_force() {
+        let mut bro = SimulatedBrokerBuilder::default().build().unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 100.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let mut exec = |qty, limit, tif| {
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(qty)
+                .kind(OrderKind::Limit {
+                    limit,
+                    stop: None,
+                    post_only: false,
+                    tif,
+                })
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port)
+        };
+
+        use TimeInForce::*;
+        for tif in [Day, GTC] {
+            assert_eq!(exec(10, 10.0, tif).unwrap().qty, 10);
+            // partial, limited by the volume
+            assert_eq!(exec(200, 10.0, tif).unwrap().qty, 100);
+            let err = exec(10, 9.0, tif).unwrap_err();
+            assert!(matches!(err, ErrorRepr::NotSatisfied(_)));
+        }
+
+        assert_eq!(exec(10, 10.0, IOC).unwrap().qty, 10);
+        assert_eq!(exec(200, 10.0, IOC).unwrap().qty, 100);
+        let err = exec(10, 9.0, IOC).unwrap_err();
+        assert!(matches!(err, ErrorRepr::OrderCanceled(_)));
+
+        assert_eq!(exec(10, 10.0, FOK).unwrap().qty, 10);
+        let err = exec(200, 10.0, FOK).unwrap_err();
+        assert!(matches!(err, ErrorRepr::OrderCanceled(_)));
+        let err = exec(10, 9.0, FOK).unwrap_err();
+        assert!(matches!(err, ErrorRepr::OrderCanceled(_)));
+    }
+
+    #[test]
+    fn test_close_only_data() {
+        use crate::data::BarIterExt;
+
+        let mut bro = SimulatedBrokerBuilder::default().build().unwrap();
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let bars = [10.0, 11.0].map(|close| Bar {
+            close,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(10)
+            .build()
+            .unwrap();
+
+        for bar in bars.into_iter().fill_ohlc_from_close() {
+            bro.set_lastest_bar(&bar);
+            let fill = bro.exec_order(&ord, &mut port).unwrap();
+            assert_eq!(fill.price, bar.close);
+            assert_eq!(
+                (bar.open, bar.high, bar.low),
+                (bar.close, bar.close, bar.close)
+            );
+        }
+    }
+
+    #[test]
+    fn test_min_commission_scope() {
+        let round_trip = |scope| {
+            let mut bro = SimulatedBrokerBuilder::default()
+                .commission(Commission::PerShare {
+                    rate: 0.01,
+                    min: 5.0,
+                    max_pct: 1.0,
+                })
+                .min_commission_scope(scope)
+                .build()
+                .unwrap();
+            bro.set_lastest_bar(&Bar {
+                open: 10.0,
+                vol: 10000.0,
+                ..Default::default()
+            });
+            let mut port = SimplePortfolioBuilder::default()
+                .cash(10000.0)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            [10, -10].map(|qty| {
+                let ord = OrderBuilder::default()
+                    .time(DateTime::default())
+                    .sym("test".into())
+                    .qty(qty)
+                    .build()
+                    .unwrap();
+                bro.exec_order(&ord, &mut port).unwrap().cost
+            })
+        };
+
+        assert_eq!(round_trip(MinCommissionScope::PerOrder), [5.0, 5.0]);
+        let [entry, exit] = round_trip(MinCommissionScope::PerRoundTrip);
+        assert!((entry - 0.1).abs() < 1e-9);
+        assert!((entry + exit - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_broker_price_improvement() {
+        let fill = |price_improvement| {
+            let mut bro = SimulatedBrokerBuilder::default()
+                .price_improvement(price_improvement)
+                .build()
+                .unwrap();
+            bro.set_lastest_bar(&Bar {
+                open: 9.0,
+                vol: 10000.0,
+                ..Default::default()
+            });
+            let mut port = SimplePortfolioBuilder::default()
+                .cash(10000.0)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(10)
+                .kind(OrderKind::Limit {
+                    limit: 9.5,
+                    stop: None,
+                    post_only: false,
+                    tif: TimeInForce::GTC,
+                })
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port).unwrap().price
+        };
+
+        assert_eq!(fill(true), 9.0);
+        assert_eq!(fill(false), 9.5);
+    }
+
+    #[test]
+    fn test_broker_volume_unit() {
+        let fill = |volume_unit| {
+            let mut bro = SimulatedBrokerBuilder::default()
+                .volume_unit(volume_unit)
+                .build()
+                .unwrap();
+            bro.set_lastest_bar(&Bar {
+                open: 10.0,
+                close: 10.0,
+                vol: 5.0,
+                ..Default::default()
+            });
+            let mut port = SimplePortfolioBuilder::default()
+                .cash(100000.0)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(1000)
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port).unwrap().qty
+        };
+
+        assert_eq!(fill(VolumeUnit::Shares), 5);
+        // 5 lots of 100 shares
+        assert_eq!(fill(VolumeUnit::Lots(100)), 500);
+    }
+
+    #[test]
+    fn test_broker_slippage_seed() {
+        let prices = |seed, sym: &str| {
+            let mut bro = SimulatedBrokerBuilder::default()
+                .slippage(Cost::Ratio(0.01))
+                .seed(seed)
+                .build()
+                .unwrap();
+            let mut port = SimplePortfolioBuilder::default()
+                .cash(100000.0)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            bro.set_lastest_bar(&Bar {
+                open: 10.0,
+                vol: 10000.0,
+                ..Default::default()
+            });
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym(sym.into())
+                .qty(1)
+                .build()
+                .unwrap();
+            (0..5)
+                .map(|_| bro.exec_order(&ord, &mut port).unwrap().price.0)
+                .collect::<Vec<_>>()
+        };
+
+        let a = prices(42, "a");
+        assert!(a.iter().all(|x| (10.0..=10.1).contains(x)));
+        assert_eq!(a, prices(42, "a"));
+        assert_ne!(a, prices(43, "a"));
+        assert_ne!(a, prices(42, "b"));
+    }
+
+    #[test]
+    fn test_broker_reference_price() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .reference_price(ReferencePrice::Typical)
+            .build()
+            .unwrap();
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            high: 14.0,
+            low: 9.0,
+            close: 13.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(10)
+            .build()
+            .unwrap();
+        assert_eq!(bro.exec_order(&ord, &mut port).unwrap().price, 12.0);
+    }
+
+    #[test]
+    fn test_broker_touch_fill_prob() {
+        let fills = |prob, seed| {
+            let mut bro = SimulatedBrokerBuilder::default()
+                .touch_fill_prob(prob)
+                .seed(seed)
+                .build()
+                .unwrap();
+            let mut port = SimplePortfolioBuilder::default()
+                .cash(100000.0)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            bro.set_lastest_bar(&Bar {
+                open: 10.0,
+                vol: 10000.0,
+                ..Default::default()
+            });
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(1)
+                .kind(OrderKind::Limit {
+                    limit: 10.0,
+                    stop: None,
+                    post_only: false,
+                    tif: TimeInForce::GTC,
+                })
+                .build()
+                .unwrap();
+            (0..20)
+                .map(|_| bro.exec_order(&ord, &mut port).is_ok())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(fills(1.0, 7).iter().all(|x| *x));
+        assert!(fills(0.0, 7).iter().all(|x| !*x));
+        let half = fills(0.5, 7);
+        assert_eq!(half, fills(0.5, 7));
+        assert!(half.contains(&true) && half.contains(&false));
+    }
+
+    #[test]
+    fn test_broker_adv_cap() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .adv_window(2)
+            .max_adv_pct(0.1)
+            .build()
+            .unwrap();
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(100000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for vol in [9000.0, 1000.0, 3000.0] {
+            bro.set_lastest_bar(&Bar {
+                open: 10.0,
+                vol,
+                ..Default::default()
+            });
+        }
+        assert_eq!(bro.adv(), Some(2000.0));
+
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(500)
+            .build()
+            .unwrap();
+        // within the bar volume but beyond 10% of the adv
+        assert_eq!(bro.exec_order(&ord, &mut port).unwrap().qty, 200);
+    }
+
+    #[test]
+    fn test_broker_limit_participation() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .limit_participation(Some(0.1))
+            .price_improvement(false)
+            .build()
+            .unwrap();
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(100000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let limit = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(500)
+            .kind(OrderKind::Limit {
+                limit: 9.5,
+                stop: None,
+                post_only: false,
+                tif: TimeInForce::GTC,
+            })
+            .build()
+            .unwrap();
+        let market = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(500)
+            .build()
+            .unwrap();
+
+        for vol in [1000.0, 3000.0] {
+            bro.set_lastest_bar(&Bar {
+                open: 9.0,
+                vol,
+                ..Default::default()
+            });
+            // 10% of the bar at the limit, market orders take the whole volume
+            let fill = bro.exec_order(&limit, &mut port).unwrap();
+            assert_eq!(fill.qty, (vol * 0.1) as i32);
+            assert_eq!(fill.price, 9.5);
+            assert_eq!(bro.exec_order(&market, &mut port).unwrap().qty, 500);
+        }
+    }
+
+    #[test]
+    fn test_broker_order_timing() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .reference_price(ReferencePrice::Mid)
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            close: 12.0,
+            high: 13.0,
+            low: 9.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let mut price = |timing| {
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(10)
+                .timing(timing)
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port).unwrap().price
+        };
+
+        assert_eq!(price(OrderTiming::OnOpen), 10.0);
+        assert_eq!(price(OrderTiming::OnClose), 12.0);
+        assert_eq!(price(OrderTiming::Immediate), 11.0);
+    }
+
+    #[test]
+    fn test_broker_urgency_slippage() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .urgency_slippage(UrgencySlippage {
+                market: 0.01,
+                limit: 0.002,
+            })
+            .build()
+            .unwrap();
+        bro.set_lastest_bar(&Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        });
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let mut price = |kind, urgency| {
+            let ord = OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(10)
+                .kind(kind)
+                .urgency(urgency)
+                .build()
+                .unwrap();
+            bro.exec_order(&ord, &mut port).unwrap().price.0
+        };
+        let limit = OrderKind::Limit {
+            limit: 11.0,
+            stop: None,
+            post_only: false,
+            tif: TimeInForce::IOC,
+        };
+
+        let (urgent, patient) = (price(OrderKind::Market, 0.9), price(OrderKind::Market, 0.1));
+        assert!(urgent > patient);
+        assert_lt!((urgent - 10.09).abs(), 1e-9);
+        assert_lt!((patient - 10.01).abs(), 1e-9);
+        // a marketable limit at the same urgency pays less
+        assert_lt!((price(limit, 0.9) - 10.018).abs(), 1e-9);
+        assert_eq!(price(OrderKind::Market, 0.0), 10.0);
+    }
+
+    #[test]
+    fn test_broker_min_price() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .min_price(1.0)
+            .build()
+            .unwrap();
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let order = |qty| {
+            OrderBuilder::default()
+                .time(DateTime::default())
+                .sym("test".into())
+                .qty(qty)
+                .build()
+                .unwrap()
+        };
+        let bar = |open| Bar {
+            open,
+            vol: 10000.0,
+            ..Default::default()
+        };
+
+        bro.set_lastest_bar(&bar(0.5));
+        assert!(matches!(
+            bro.exec_order(&order(10), &mut port),
+            Err(ErrorRepr::OrderRejected(_))
+        ));
+        bro.set_lastest_bar(&bar(2.0));
+        assert_eq!(bro.exec_order(&order(10), &mut port).unwrap().qty, 10);
+
+        // a position can still be exited below the minimum
+        bro.set_lastest_bar(&bar(0.5));
+        assert_eq!(bro.exec_order(&order(-10), &mut port).unwrap().qty, -10);
+    }
+
+    #[test]
+    fn test_broker_validate_order() {
+        let mut bro = SimulatedBrokerBuilder::default()
+            .commission(0.001)
+            .build()
+            .unwrap();
+        let bar = Bar {
+            open: 10.0,
+            vol: 10000.0,
+            ..Default::default()
+        };
+        bro.set_lastest_bar(&bar);
+
+        let mut port = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let ord = OrderBuilder::default()
+            .time(DateTime::default())
+            .sym("test".into())
+            .qty(1000)
+            .build()
+            .unwrap();
+
+        let qty = bro.validate_order(&ord, &port, &bar).unwrap();
+        assert_eq!(qty, 99);
+        assert_eq!(port.cash, 1000.0);
+
+        let fill = bro.exec_order(&ord, &mut port).unwrap();
+        assert_eq!(fill.qty, qty);
+    }
 }
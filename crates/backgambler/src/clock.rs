@@ -0,0 +1,26 @@
+use crate::data::DateTime;
+
+/// source of the current time, for anything not driven by bar time
+pub trait Clock: Send {
+    fn now(&self) -> DateTime;
+}
+
+/// wall-clock time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime {
+        chrono::Utc::now()
+    }
+}
+
+/// always returns the same time, for reproducible tests
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime {
+        self.0
+    }
+}
@@ -1,5 +1,9 @@
 use chrono::Utc;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::errors::ErrorRepr;
 
 pub type DateTime = chrono::DateTime<Utc>;
 pub type Symbol = String;
@@ -14,8 +18,184 @@ pub struct Bar {
     pub high: f64,
     pub low: f64,
     pub vol: f64,
+    /// cash dividend per share paid to holders on this bar
+    pub dividend: f64,
+}
+
+/// simple returns of the closes of consecutive bars
+pub fn returns(bars: &[Bar]) -> Vec<f64> {
+    bars.windows(2)
+        .map(|w| w[1].close / w[0].close - 1.0)
+        .collect()
+}
+
+/// log returns of the closes of consecutive bars
+pub fn log_returns(bars: &[Bar]) -> Vec<f64> {
+    bars.windows(2)
+        .map(|w| (w[1].close / w[0].close).ln())
+        .collect()
+}
+
+/// a bar with its return versus the prior bar, `None` for the first one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnBar {
+    pub bar: Bar,
+    pub ret: Option<f64>,
+    pub log_ret: Option<f64>,
+}
+
+pub struct WithReturns<I> {
+    iter: I,
+    prev_close: Option<f64>,
+}
+
+impl<I: Iterator<Item = Bar>> Iterator for WithReturns<I> {
+    type Item = ReturnBar;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bar = self.iter.next()?;
+        let ratio = self.prev_close.replace(bar.close).map(|x| bar.close / x);
+        Some(ReturnBar {
+            bar,
+            ret: ratio.map(|x| x - 1.0),
+            log_ret: ratio.map(f64::ln),
+        })
+    }
+}
+
+/// set the open, high and low missing from close-only data, left at zero, to the close
+pub fn fill_ohlc_from_close(mut bar: Bar) -> Bar {
+    for x in [&mut bar.open, &mut bar.high, &mut bar.low] {
+        if *x == 0.0 || x.is_nan() {
+            *x = bar.close;
+        }
+    }
+    bar
 }
 
+/// a stream of bars transformed lazily by a chain of iterator adapters, e.g. validation
+/// then resampling, see `GamblerBuilder::transform`
+pub struct Pipeline(Box<dyn Iterator<Item = Bar> + Send>);
+
+impl Pipeline {
+    pub fn new(iter: impl Iterator<Item = Bar> + Send + 'static) -> Self {
+        Self(Box::new(iter))
+    }
+
+    /// append an adapter applied to the bars coming out of the current chain
+    pub fn then<I, F>(self, f: F) -> Self
+    where
+        I: Iterator<Item = Bar> + Send + 'static,
+        F: FnOnce(Box<dyn Iterator<Item = Bar> + Send>) -> I,
+    {
+        Self::new(f(self.0))
+    }
+}
+
+impl Iterator for Pipeline {
+    type Item = Bar;
+
+    fn next(&mut self) -> Option<Bar> {
+        self.0.next()
+    }
+}
+
+pub trait BarIterExt: Iterator<Item = Bar> + Sized {
+    /// annotate every bar with its return versus the prior bar
+    fn with_returns(self) -> WithReturns<Self> {
+        WithReturns {
+            iter: self,
+            prev_close: None,
+        }
+    }
+
+    /// see `fill_ohlc_from_close`
+    fn fill_ohlc_from_close(self) -> std::iter::Map<Self, fn(Bar) -> Bar> {
+        self.map(fill_ohlc_from_close)
+    }
+}
+
+impl<I: Iterator<Item = Bar>> BarIterExt for I {}
+
+/// what to do with a row of a fallible data source that failed to load
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BadRowPolicy {
+    /// log it and continue with the next row
+    #[default]
+    Skip,
+    /// log it and end the data there
+    Stop,
+    /// end the data there and keep the error for the caller, see `BadRows::error`
+    Fail,
+}
+
+/// the error a fallible data source failed with, shared with its consumer
+pub type DataError = Arc<Mutex<Option<ErrorRepr>>>;
+
+pub struct BadRows<I> {
+    iter: I,
+    policy: BadRowPolicy,
+    stopped: bool,
+    error: DataError,
+}
+
+impl<I> BadRows<I> {
+    /// set once a row fails under `BadRowPolicy::Fail`, still readable after the bars
+    /// are moved into a pipeline
+    pub fn error(&self) -> DataError {
+        Arc::clone(&self.error)
+    }
+}
+
+impl<I, E> Iterator for BadRows<I>
+where
+    I: Iterator<Item = Result<Bar, E>>,
+    E: std::fmt::Display,
+{
+    type Item = Bar;
+
+    fn next(&mut self) -> Option<Bar> {
+        if self.stopped {
+            return None;
+        }
+        loop {
+            let err = match self.iter.next()? {
+                Ok(bar) => return Some(bar),
+                Err(err) => err,
+            };
+            match self.policy {
+                BadRowPolicy::Skip => log::warn!("skip bad row: {}", err),
+                BadRowPolicy::Stop => {
+                    log::warn!("stop at bad row: {}", err);
+                    self.stopped = true;
+                    return None;
+                }
+                BadRowPolicy::Fail => {
+                    log::error!("fail at bad row: {}", err);
+                    self.stopped = true;
+                    *self.error.lock() = Some(ErrorRepr::InvalidData(err.to_string()));
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+pub trait TryBarIterExt<E>: Iterator<Item = Result<Bar, E>> + Sized {
+    /// the bars of a fallible source, e.g. a streaming csv reader, with the rows failing
+    /// to load handled by `policy`
+    fn bad_rows(self, policy: BadRowPolicy) -> BadRows<Self> {
+        BadRows {
+            iter: self,
+            policy,
+            stopped: false,
+            error: DataError::default(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Bar, E>>, E> TryBarIterExt<E> for I {}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -49,18 +229,73 @@ pub(crate) mod tests {
         pub vol: u32,
     }
 
-    impl Into<Bar> for TestBar {
-        fn into(self) -> Bar {
-            serde_json::from_str(&serde_json::to_string(&self).unwrap()).unwrap()
+    impl From<TestBar> for Bar {
+        fn from(val: TestBar) -> Self {
+            Bar {
+                sym: val.sym,
+                time: val.time,
+                open: val.open,
+                close: val.close,
+                high: val.high,
+                low: val.low,
+                vol: val.vol as f64,
+                dividend: 0.0,
+            }
+        }
+    }
+
+    #[test]
+    fn test_returns() {
+        let bars = [100.0, 110.0, 99.0].map(|close| Bar {
+            close,
+            ..Default::default()
+        });
+
+        let rets = returns(&bars);
+        assert!((rets[0] - 0.1).abs() < 1e-12);
+        assert!((rets[1] + 0.1).abs() < 1e-12);
+        assert!((log_returns(&bars)[0] - 1.1f64.ln()).abs() < 1e-12);
+
+        let annotated = bars.into_iter().with_returns().collect::<Vec<_>>();
+        assert_eq!(annotated[0].ret, None);
+        assert_eq!(annotated[0].log_ret, None);
+        assert_eq!(annotated[1].ret, Some(rets[0]));
+        assert!((annotated[1].log_ret.unwrap() - 1.1f64.ln()).abs() < 1e-12);
+    }
+
+    /// the bundled test data, resolved against the crate root so that the tests do not
+    /// depend on the working directory
+    pub fn test_data_path() -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/data/test/orcl-1995-2014.txt")
+    }
+
+    #[test]
+    fn test_test_data_path() {
+        const CHILD: &str = "BACKGAMBLER_TEST_DATA_CHILD";
+        if std::env::var_os(CHILD).is_some() {
+            // the relative path is not there to fall back on
+            assert!(!std::path::Path::new("src/data/test/orcl-1995-2014.txt").exists());
+            assert!(test_data_iter().next().is_some());
+            return;
         }
+        assert!(test_data_path().is_absolute());
+
+        // load it again in this test rerun from another directory, changing the directory
+        // of this process would affect the other tests
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "data::tests::test_test_data_path"])
+            .env(CHILD, "1")
+            .current_dir(std::env::temp_dir())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
     }
 
-    pub fn get_test_data() -> Vec<Bar> {
-        let mut rdr = csv::Reader::from_path("src/data/test/orcl-1995-2014.txt").unwrap();
-        rdr.deserialize()
-            .into_iter()
-            .map(|x| x.unwrap())
-            .map(|x: TestBar| x.into())
-            .collect()
+    /// lazily stream the bundled test data
+    pub fn test_data_iter() -> impl Iterator<Item = Bar> {
+        let rdr = csv::Reader::from_path(test_data_path()).unwrap();
+        rdr.into_deserialize()
+            .map(|x: Result<TestBar, _>| x.unwrap().into())
     }
 }
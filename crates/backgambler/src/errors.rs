@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use thiserror::Error;
 
 /// All errors generated in the barter::portfolio module.
@@ -11,4 +13,17 @@ pub enum ErrorRepr {
     NotSatisfied(&'static str),
     #[error("expired: {}", .0)]
     OrderExpired(String),
+    #[error("rejected: {}", .0)]
+    OrderRejected(String),
+    #[error("canceled: {}", .0)]
+    OrderCanceled(String),
+    #[error("invalid data: {}", .0)]
+    InvalidData(String),
+    #[error("invalid config: {}", .0)]
+    InvalidConfig(String),
+    /// raised by user strategies and order managers
+    #[error("strategy: {}", .0)]
+    Strategy(String),
+    #[error(transparent)]
+    Custom(Arc<dyn std::error::Error + Send + Sync>),
 }
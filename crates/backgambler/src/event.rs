@@ -1,6 +1,8 @@
+use serde::Serialize;
+
 use crate::{
     data::*,
-    order::{Fill, Order},
+    order::{Fill, Order, OrderKind},
 };
 
 use super::strategy::Decision;
@@ -11,4 +13,164 @@ pub enum Event {
     Decision(Decision),
     Order(Order),
     Fill(Fill),
+    DecisionSkipped(Decision, SkipReason),
+}
+
+/// an event flattened into one row of a timeline, see `Gambler::export_timeline_csv`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelineRow {
+    pub time: DateTime,
+    pub symbol: Symbol,
+    pub event_type: &'static str,
+    pub qty: Option<i32>,
+    pub price: Option<f64>,
+    pub cost: Option<f64>,
+    /// decision kind, order status or skip reason
+    pub status: Option<String>,
+}
+
+impl From<&Event> for TimelineRow {
+    fn from(e: &Event) -> Self {
+        let row = |time, symbol: &Symbol, event_type| TimelineRow {
+            time,
+            symbol: symbol.clone(),
+            event_type,
+            qty: None,
+            price: None,
+            cost: None,
+            status: None,
+        };
+        match e {
+            Event::Market(bar) => TimelineRow {
+                price: Some(bar.close),
+                ..row(bar.time, &bar.sym, "market")
+            },
+            Event::Decision(d) => TimelineRow {
+                price: d.price,
+                status: Some(format!("{:?}", d.kind)),
+                ..row(d.time, &d.sym, "decision")
+            },
+            Event::Order(ord) => TimelineRow {
+                qty: Some(ord.qty.0),
+                price: match ord.kind {
+                    OrderKind::Market => None,
+                    OrderKind::Limit { limit, .. } => Some(limit),
+                },
+                status: Some(format!("{:?}", ord.status)),
+                ..row(ord.time, &ord.sym, "order")
+            },
+            Event::Fill(f) => TimelineRow {
+                qty: Some(f.qty.0),
+                price: Some(f.price.0),
+                cost: Some(f.cost),
+                ..row(f.time, &f.sym, "fill")
+            },
+            Event::DecisionSkipped(d, reason) => TimelineRow {
+                price: d.price,
+                status: Some(format!("{:?}", reason)),
+                ..row(d.time, &d.sym, "skipped")
+            },
+        }
+    }
+}
+
+/// first row where a replay differs from its recording, None past the end of either
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub index: usize,
+    pub recorded: Option<TimelineRow>,
+    pub replayed: Option<TimelineRow>,
+}
+
+impl Divergence {
+    /// compare two timelines row by row
+    pub fn find(recorded: &[TimelineRow], replayed: &[TimelineRow]) -> Option<Divergence> {
+        (0..recorded.len().max(replayed.len())).find_map(|index| {
+            let (recorded, replayed) = (recorded.get(index), replayed.get(index));
+            (recorded != replayed).then(|| Divergence {
+                index,
+                recorded: recorded.cloned(),
+                replayed: replayed.cloned(),
+            })
+        })
+    }
+}
+
+/// why a decision did not turn into a trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// the allocator made no order, e.g. rejected or sized to zero
+    NoOrder,
+    /// the broker clamped the order to zero quantity
+    Unfilled,
+    Expired,
+    /// the broker refused the order
+    Rejected,
+    /// canceled by its time in force
+    Canceled,
+    /// the strategy vetoed the allocated order
+    Vetoed,
+}
+
+/// how a gambler reports skipped decisions, e.g. to run large sweeps quietly
+#[derive(Debug, Clone, Copy, Default)]
+pub enum RejectionPolicy {
+    /// log every decision left without an order and every rejected or canceled order
+    #[default]
+    Log,
+    /// only log the number of skipped decisions once the data is exhausted
+    Count,
+    /// log nothing, the skips are still counted
+    Silent,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SkippedDecisions {
+    pub no_order: usize,
+    pub unfilled: usize,
+    pub expired: usize,
+    pub rejected: usize,
+    pub canceled: usize,
+    pub vetoed: usize,
+}
+
+impl SkippedDecisions {
+    pub fn add(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::NoOrder => self.no_order += 1,
+            SkipReason::Unfilled => self.unfilled += 1,
+            SkipReason::Expired => self.expired += 1,
+            SkipReason::Rejected => self.rejected += 1,
+            SkipReason::Canceled => self.canceled += 1,
+            SkipReason::Vetoed => self.vetoed += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.no_order + self.unfilled + self.expired + self.rejected + self.canceled + self.vetoed
+    }
+}
+
+/// number of unfulfilled orders, sampled once per bar
+#[derive(Debug, Clone, Default)]
+pub struct OpenOrderStats {
+    pub max: usize,
+    total: usize,
+    samples: usize,
+}
+
+impl OpenOrderStats {
+    pub fn sample(&mut self, open: usize) {
+        self.max = self.max.max(open);
+        self.total += open;
+        self.samples += 1;
+    }
+
+    pub fn avg(&self) -> f64 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.samples as f64
+        }
+    }
 }
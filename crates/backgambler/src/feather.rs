@@ -0,0 +1,122 @@
+//! export of the stats as arrow ipc files, aka feather v2, e.g. for polars or pandas
+use arrow::{
+    array::{
+        ArrayRef, Float64Array, Int32Array, StringArray, TimestampNanosecondArray, UInt64Array,
+    },
+    error::ArrowError,
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use std::{fs::File, path::Path, sync::Arc};
+
+use crate::{data::DateTime, errors::ErrorRepr, portfolio::PortfolioStats};
+
+impl From<ArrowError> for ErrorRepr {
+    fn from(e: ArrowError) -> Self {
+        ErrorRepr::Custom(Arc::new(e))
+    }
+}
+
+fn timestamps(times: impl Iterator<Item = DateTime>) -> ArrayRef {
+    let nanos = times.map(|x| x.timestamp_nanos_opt()).collect::<Vec<_>>();
+    Arc::new(TimestampNanosecondArray::from(nanos).with_timezone("UTC"))
+}
+
+fn write(path: &Path, batch: RecordBatch) -> Result<(), ErrorRepr> {
+    let file = File::create(path).map_err(|e| ErrorRepr::Custom(Arc::new(e)))?;
+    let mut writer = FileWriter::try_new(file, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+impl PortfolioStats {
+    /// write the equity curve to `equity.arrow` and the round trips to `trades.arrow` in
+    /// the directory `dir`
+    pub fn to_arrow(&self, dir: impl AsRef<Path>) -> Result<(), ErrorRepr> {
+        let dir = dir.as_ref();
+        let equity = RecordBatch::try_from_iter([
+            ("time", timestamps(self.equity_curve.iter().map(|x| x.0))),
+            (
+                "equity",
+                Arc::new(Float64Array::from_iter_values(
+                    self.equity_curve.iter().map(|x| x.1),
+                )) as ArrayRef,
+            ),
+        ])?;
+        write(&dir.join("equity.arrow"), equity)?;
+
+        let cols = self.trades_columnar();
+        let trades = RecordBatch::try_from_iter([
+            (
+                "sym",
+                Arc::new(StringArray::from_iter_values(&cols.sym)) as ArrayRef,
+            ),
+            ("entry_time", timestamps(cols.entry_time.into_iter())),
+            ("exit_time", timestamps(cols.exit_time.into_iter())),
+            (
+                "bars",
+                Arc::new(UInt64Array::from_iter_values(
+                    cols.bars.iter().map(|x| *x as u64),
+                )),
+            ),
+            ("qty", Arc::new(Int32Array::from(cols.qty))),
+            ("pnl", Arc::new(Float64Array::from(cols.pnl))),
+        ])?;
+        write(&dir.join("trades.arrow"), trades)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::{array::Array, ipc::reader::FileReader};
+
+    #[test]
+    fn test_to_arrow() {
+        let start = chrono::Utc::now();
+        let stats = PortfolioStats {
+            equity_curve: [1000.0, 1010.5, 990.25]
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| (start + chrono::Duration::days(i as i64), x))
+                .collect(),
+            ..Default::default()
+        };
+        let dir = std::env::temp_dir().join(format!("backgambler-arrow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        stats.to_arrow(&dir).unwrap();
+
+        let file = File::open(dir.join("equity.arrow")).unwrap();
+        let batches = FileReader::try_new(file, None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        let times = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<TimestampNanosecondArray>()
+            .unwrap();
+        let equity = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(equity.len(), 3);
+        for (i, (time, value)) in stats.equity_curve.iter().enumerate() {
+            assert_eq!(times.value(i), time.timestamp_nanos_opt().unwrap());
+            assert_eq!(equity.value(i), *value);
+        }
+
+        let file = File::open(dir.join("trades.arrow")).unwrap();
+        let trades = FileReader::try_new(file, None)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(trades.num_rows(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,18 +1,58 @@
 use crate::{
     broker::*,
-    data::{Bar, Symbol},
+    clock::{Clock, SystemClock},
+    data::{BadRowPolicy, Bar, DataError, DateTime, Pipeline, Symbol, TryBarIterExt},
     errors::ErrorRepr,
     event::*,
-    order::{Fill, Order, OrderAllocator, OrderStatus},
-    portfolio::PositionManager,
-    strategy::{Decision, DecisionMaker},
+    order::{aggregate_fills, Fill, Order, OrderAllocator, OrderKind, OrderStatus, TimeInForce},
+    portfolio::{PortfolioStats, PositionManager, Statistics},
+    strategy::{Decision, DecisionKind, DecisionMaker, WarmupPhase},
+    units::Qty,
 };
 use derive_builder::Builder;
 use parking_lot::Mutex;
-use std::{collections::VecDeque, sync::Arc};
+use std::{cmp::Ordering, collections::VecDeque, path::Path, sync::Arc};
+
+pub type EventHook = Box<dyn Fn(Symbol, &Event) + Send>;
+/// receives the messages logged by a gambler
+pub type LogSink = Box<dyn Fn(log::Level, &std::fmt::Arguments) + Send>;
+
+/// what to do with a bar whose timestamp is not after the previous one
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TimestampPolicy {
+    /// process a bar sharing the previous timestamp anyway, panic on one before it
+    #[default]
+    Keep,
+    /// merge bars sharing a timestamp into one, drop out-of-order ones
+    Merge,
+    /// drop it
+    Skip,
+    /// panic
+    Error,
+}
+
+/// what to do with the orders still pending once the data is exhausted
+#[derive(Debug, Clone, Copy, Default)]
+pub enum EndOfDataPolicy {
+    /// cancel them and report their decisions as skipped with `SkipReason::Unfilled`
+    #[default]
+    ReportUnfilled,
+    /// execute them at the close of the last bar, the rest of the bar was already seen
+    /// when they were decided
+    ExecuteOnLastBar,
+}
+
+/// the bar the position left open once the data is exhausted is closed against
+#[derive(Debug, Clone)]
+pub enum Settlement {
+    /// a flat bar at the close of the last bar
+    LastClose,
+    /// this bar, e.g. a known settlement price
+    Bar(Bar),
+}
 
 #[derive(Builder)]
-#[builder(pattern = "owned")]
+#[builder(pattern = "owned", build_fn(private, name = "build_unchecked"))]
 pub struct Gambler<Strategy, Data, Broker, Portfolio> {
     #[builder(setter(into))]
     sym: Symbol,
@@ -26,8 +66,187 @@ pub struct Gambler<Strategy, Data, Broker, Portfolio> {
     deferred_event_q: VecDeque<Event>,
     #[builder(setter(skip))]
     unfulfilled_orders: Vec<Order>,
+    /// id of the last order placed
+    #[builder(setter(skip))]
+    last_order_id: u64,
+    #[builder(setter(skip))]
+    skipped: SkippedDecisions,
+    #[builder(setter(skip))]
+    open_orders: OpenOrderStats,
+    #[builder(default)]
+    event_hooks: Vec<EventHook>,
+    /// where `fallible_data` reports the row the data failed at
+    #[builder(default, private)]
+    data_error: DataError,
+    /// fill orders against the bar they were decided on instead of the next one
+    #[builder(default)]
+    same_bar_fill: bool,
+    /// a partially filled GTC or Day limit order keeps working its remainder on the
+    /// following bars, instead of completing with the partial fill
+    #[builder(default)]
+    rest_remainders: bool,
+    #[builder(default)]
+    timestamp_policy: TimestampPolicy,
+    /// the data is newest-first, buffer it and run it backwards. without it, data whose
+    /// second timestamp is before the first is rejected
+    #[builder(default)]
+    reverse: bool,
+    #[builder(setter(skip))]
+    reversed: Option<Vec<Bar>>,
+    #[builder(setter(skip))]
+    first_time: Option<DateTime>,
+    #[builder(setter(skip))]
+    pending_bar: Option<Bar>,
+    #[builder(setter(skip))]
+    peeked_bar: Option<Bar>,
+    /// time of the bar being processed
+    #[builder(setter(skip))]
+    current_time: Option<DateTime>,
+    #[builder(setter(skip))]
+    last_time: Option<DateTime>,
+    /// report the fills of a bar to the strategy as one fill per order
+    #[builder(default)]
+    aggregate_fills: bool,
+    #[builder(setter(skip))]
+    batched_fills: Vec<Fill>,
+    /// use the decisions of `DecisionMaker::precompute` instead of calling `make_decision`
+    #[builder(default)]
+    precompute: bool,
+    #[builder(setter(skip))]
+    buffered_bars: Option<VecDeque<Bar>>,
+    #[builder(setter(skip))]
+    signals: Option<VecDeque<Decision>>,
+    /// discard the decision made on the first bar, so that signals start with a prior bar
+    #[builder(default)]
+    skip_first_decision: bool,
+    /// number of bars whose decisions are discarded while the strategy warms up
+    #[builder(default)]
+    warmup: usize,
+    #[builder(setter(skip))]
+    bars_seen: usize,
+    /// ask the strategy for a decision only every this many bars, e.g. 5 to rebalance
+    /// weekly on daily bars. `on_data` still sees every bar
+    #[builder(default = "1")]
+    decision_interval: usize,
+    /// drop a decision of the same kind as the previous one, so that a strategy repeating
+    /// its signal every bar only trades when it changes
+    #[builder(default)]
+    debounce: bool,
+    #[builder(setter(skip))]
+    last_kind: Option<DecisionKind>,
+    #[builder(default)]
+    end_of_data: EndOfDataPolicy,
+    /// close the position at the end of the run against this bar, after the pending
+    /// orders are settled
     #[builder(default)]
-    event_hooks: Vec<Box<dyn Fn(Symbol, &Event) + Send>>,
+    settlement: Option<Settlement>,
+    #[builder(setter(skip))]
+    last_bar: Option<Bar>,
+    #[builder(default)]
+    rejection_policy: RejectionPolicy,
+    /// log a summary of the portfolio once the run is finished
+    #[builder(default)]
+    log_summary: bool,
+    #[builder(setter(skip))]
+    finished: bool,
+    /// time source for anything happening outside of a bar
+    #[builder(default = "Box::new(SystemClock)")]
+    clock: Box<dyn Clock>,
+    /// where the messages of the run go, the `log` crate by default
+    #[builder(default = "Box::new(|level, args| log::log!(level, \"{}\", args))")]
+    log_sink: LogSink,
+    /// seed of every random draw of the run, overriding the one of the broker and
+    /// passed on to the shadows, so that one seed reproduces the whole run
+    #[builder(default, setter(strip_option))]
+    seed: Option<u64>,
+    #[builder(setter(skip))]
+    shadows: Vec<Shadow<Broker, Portfolio>>,
+}
+
+/// a broker and portfolio trading the decisions of another gambler's strategy
+struct Shadow<Broker, Portfolio> {
+    gambler: Gambler<Replay, Pipeline, Broker, Portfolio>,
+    bars: Arc<Mutex<VecDeque<Bar>>>,
+    decisions: Arc<Mutex<VecDeque<Decision>>>,
+}
+
+/// replays the decision made on the bar, holding if there was none. the shadow steps
+/// right after its gambler, so there is at most one queued
+struct Replay(Arc<Mutex<VecDeque<Decision>>>);
+
+impl DecisionMaker for Replay {
+    fn make_decision(&mut self, data: &Bar) -> Decision {
+        self.0.lock().pop_front().unwrap_or_else(|| Decision {
+            sym: data.sym.clone(),
+            kind: DecisionKind::Hold,
+            time: data.time,
+            strength: 1.0,
+            price: Some(data.close),
+            signal: None,
+            stop: None,
+        })
+    }
+}
+
+fn feed(bars: &Arc<Mutex<VecDeque<Bar>>>) -> Pipeline {
+    let bars = Arc::clone(bars);
+    Pipeline::new(std::iter::from_fn(move || bars.lock().pop_front()))
+}
+
+impl<Strategy, Exector, Portfolio> GamblerBuilder<Strategy, Pipeline, Exector, Portfolio> {
+    /// take the bars of a fallible source, handling the rows failing to load by `policy`
+    pub fn fallible_data<I, E>(self, data: I, policy: BadRowPolicy) -> Self
+    where
+        I: Iterator<Item = Result<Bar, E>> + Send + 'static,
+        E: std::fmt::Display,
+    {
+        let bars = data.bad_rows(policy);
+        self.data_error(bars.error()).data(Pipeline::new(bars))
+    }
+
+    /// transform the bars of the data set so far with an iterator adapter before the
+    /// strategy sees them, in the order the transforms are added.
+    /// panics if no data is set yet
+    pub fn transform<I, F>(mut self, f: F) -> Self
+    where
+        I: Iterator<Item = Bar> + Send + 'static,
+        F: FnOnce(Box<dyn Iterator<Item = Bar> + Send>) -> I,
+    {
+        let Some(data) = self.data.take() else {
+            panic!("`transform` called before `data`, there are no bars to transform");
+        };
+        self.data = Some(data.then(f));
+        self
+    }
+}
+
+impl<Strategy, Data, Exector, Portfolio> GamblerBuilder<Strategy, Data, Exector, Portfolio>
+where
+    Exector: Broker,
+    Portfolio: Wallet,
+{
+    /// build the gambler, rejecting missing fields and inconsistent configs such as a
+    /// portfolio without cash or a broker with negative costs
+    pub fn build(self) -> Result<Gambler<Strategy, Data, Exector, Portfolio>, ErrorRepr> {
+        let mut g = self
+            .build_unchecked()
+            .map_err(|e| ErrorRepr::InvalidConfig(e.to_string()))?;
+        if let Some(seed) = g.seed {
+            g.broker.set_seed(seed);
+        }
+        if g.decision_interval == 0 {
+            return Err(ErrorRepr::InvalidConfig("decision interval is 0".into()));
+        }
+        let cash = g.portfolio.lock().balance();
+        if cash <= 0.0 {
+            return Err(ErrorRepr::InvalidConfig(format!(
+                "cash not positive: {}",
+                cash
+            )));
+        }
+        g.broker.validate()?;
+        Ok(g)
+    }
 }
 
 impl<Strategy, Data, Exector, Portfolio> Gambler<Strategy, Data, Exector, Portfolio>
@@ -47,57 +266,318 @@ where
         self.event_hooks.push(Box::new(f));
     }
 
+    /// write every event processed from now on as a row of a csv timeline at `path`
+    pub fn export_timeline_csv(&mut self, path: impl AsRef<Path>) -> Result<(), ErrorRepr> {
+        let wtr = csv::Writer::from_path(path).map_err(|e| ErrorRepr::Custom(Arc::new(e)))?;
+        let wtr = Mutex::new(wtr);
+        self.add_event_hook(move |_, event| {
+            let mut wtr = wtr.lock();
+            let res = wtr
+                .serialize(TimelineRow::from(event))
+                .and_then(|_| Ok(wtr.flush()?));
+            if let Err(err) = res {
+                log::error!("write timeline failed: {}", err);
+            }
+        });
+        Ok(())
+    }
+
+    /// collect every event processed from now on as a row of a timeline
+    pub fn record_timeline(&mut self) -> Arc<Mutex<Vec<TimelineRow>>> {
+        let rows = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&rows);
+        self.add_event_hook(move |_, event| sink.lock().push(TimelineRow::from(event)));
+        rows
+    }
+
+    /// run over `data` from the start and check that the events match a timeline recorded
+    /// by `record_timeline`, reporting the first row that differs. the strategy and the
+    /// portfolio must be in the state the recording started from
+    pub fn verify_replay(
+        &mut self,
+        recorded: &[TimelineRow],
+        data: Data,
+    ) -> Result<(), Box<Divergence>> {
+        self.reset(data);
+        let rows = self.record_timeline();
+        while self.step() {}
+        self.event_hooks.pop();
+        let replayed = rows.lock();
+        match Divergence::find(recorded, &replayed) {
+            Some(d) => Err(Box::new(d)),
+            None => Ok(()),
+        }
+    }
+
+    /// prepare for another run over `data`, keeping the strategy, portfolio and hooks
+    pub fn reset(&mut self, data: Data) {
+        self.data = data;
+        self.broker.reset();
+        self.event_q.clear();
+        self.deferred_event_q.clear();
+        self.unfulfilled_orders.clear();
+        self.last_order_id = 0;
+        self.skipped = Default::default();
+        self.open_orders = Default::default();
+        self.pending_bar = None;
+        self.peeked_bar = None;
+        self.reversed = None;
+        self.first_time = None;
+        self.current_time = None;
+        self.last_time = None;
+        self.batched_fills.clear();
+        self.buffered_bars = None;
+        self.signals = None;
+        self.bars_seen = 0;
+        self.last_kind = None;
+        self.last_bar = None;
+        self.finished = false;
+        for shadow in &mut self.shadows {
+            shadow.bars.lock().clear();
+            shadow.decisions.lock().clear();
+            shadow.gambler.reset(feed(&shadow.bars));
+        }
+    }
+
+    /// also trade the decisions of the strategy through `broker` and `portfolio` during the
+    /// same pass over the data, e.g. to compare cost assumptions on the exact same signals.
+    /// the strategy only hears about the fills of its own portfolio
+    pub fn add_shadow(
+        &mut self,
+        mut broker: Exector,
+        portfolio: Arc<Mutex<Portfolio>>,
+    ) -> Result<(), ErrorRepr> {
+        if let Some(seed) = self.seed {
+            broker.set_seed(seed);
+        }
+        let bars = Arc::new(Mutex::new(VecDeque::new()));
+        let decisions = Arc::new(Mutex::new(VecDeque::new()));
+        let gambler = GamblerBuilder::default()
+            .sym(self.sym.clone())
+            .strategy(Replay(Arc::clone(&decisions)))
+            .data(feed(&bars))
+            .broker(broker)
+            .portfolio(portfolio)
+            .same_bar_fill(self.same_bar_fill)
+            .end_of_data(self.end_of_data)
+            .settlement(self.settlement.clone())
+            .build()?;
+        self.shadows.push(Shadow {
+            gambler,
+            bars,
+            decisions,
+        });
+        Ok(())
+    }
+
+    pub fn broker_stats(&self) -> BrokerStats {
+        self.broker.stats()
+    }
+
+    /// the error the data ended with under `BadRowPolicy::Fail`, if any
+    pub fn data_error(&self) -> Option<ErrorRepr> {
+        self.data_error.lock().clone()
+    }
+
+    pub fn skipped_decisions(&self) -> &SkippedDecisions {
+        &self.skipped
+    }
+
+    pub fn open_order_stats(&self) -> &OpenOrderStats {
+        &self.open_orders
+    }
+
+    fn on_skip(&mut self, decision: Decision, reason: SkipReason) {
+        self.skipped.add(reason);
+        self.portfolio.lock().record_skip(reason);
+        self.event_q
+            .push_back(Event::DecisionSkipped(decision, reason));
+    }
+
     fn on_data(&mut self, bar: &Bar) {
-        let e = Event::Decision(self.strategy.make_decision(bar));
-        self.event_q.push_back(e);
+        self.bars_seen += 1;
+        let precomputed = self.signals.as_mut().and_then(|x| x.pop_front());
+        if !self.bars_seen.is_multiple_of(self.decision_interval) {
+            return;
+        }
+        let decision = match precomputed {
+            Some(d) => d,
+            None => self.strategy.make_decision(bar),
+        };
+        if self.bars_seen <= self.warmup || (self.skip_first_decision && self.bars_seen == 1) {
+            return;
+        }
+        if self.debounce && self.last_kind.replace(decision.kind) == Some(decision.kind) {
+            return;
+        }
+        for shadow in &self.shadows {
+            shadow.decisions.lock().push_back(decision.clone());
+        }
+        self.event_q.push_back(Event::Decision(decision));
     }
 
-    fn on_decision(&mut self, decision: &Decision, is_deferred: bool) {
-        let opt = self
-            .portfolio
-            .lock()
-            .allocate_order(decision)
-            .expect("allocate_order failed");
+    /// buffer all the data and let the strategy precompute its decisions, a no-op unless
+    /// `precompute` is set or if already prepared
+    pub fn prepare(&mut self) {
+        if !self.precompute || self.buffered_bars.is_some() {
+            return;
+        }
+        let bars = std::iter::from_fn(|| self.next_bar()).collect::<Vec<_>>();
+        self.signals = self.strategy.precompute(&bars).map(VecDeque::from);
+        self.buffered_bars = Some(bars.into());
+    }
 
-        if let Some(ord) = opt {
-            self.strategy.on_order(&ord);
+    fn pull_bar(&mut self) -> Option<Bar> {
+        if !self.precompute {
+            return self.next_bar();
+        }
+        self.prepare();
+        self.buffered_bars.as_mut()?.pop_front()
+    }
 
-            let e = Event::Order(ord);
-            if is_deferred {
-                self.deferred_event_q.push_back(e);
-            } else {
-                self.event_q.push_back(e);
+    fn on_decision(&mut self, decision: &Decision, is_deferred: bool) {
+        let res = self.portfolio.lock().allocate_order(decision);
+        let opt = match res {
+            Ok(opt) => opt,
+            Err(err @ (ErrorRepr::Strategy(_) | ErrorRepr::Custom(_))) => {
+                self.on_err(err);
+                None
+            }
+            Err(err) => panic!("allocate_order failed: {:?}", err),
+        };
+
+        let Some(mut ord) = opt else {
+            if !matches!(decision.kind, DecisionKind::Hold) {
+                if matches!(self.rejection_policy, RejectionPolicy::Log) {
+                    self.log(
+                        log::Level::Warn,
+                        format_args!("no order made for {:?}", decision),
+                    );
+                }
+                self.on_skip(decision.clone(), SkipReason::NoOrder);
             }
+            return;
+        };
+        self.assign_id(&mut ord);
+
+        if !self.strategy.on_order_pending(&mut ord) {
+            return self.on_skip(decision.clone(), SkipReason::Vetoed);
+        }
+
+        self.strategy.on_order(&ord);
+
+        let e = Event::Order(ord);
+        if is_deferred {
+            self.deferred_event_q.push_back(e);
+        } else {
+            self.event_q.push_back(e);
         }
     }
 
+    fn assign_id(&mut self, ord: &mut Order) {
+        self.last_order_id += 1;
+        ord.id = self.last_order_id;
+    }
+
     fn on_fill(&mut self, fill: &Fill) {
-        let r = self.portfolio.lock().update_from_fill(fill);
-        match r {
-            Err(err) => self.on_err(err),
+        let res = self.portfolio.lock().update_from_fill(fill);
+        self.report_fill(fill, res);
+    }
+
+    /// pass a fill booked by the portfolio with `res` on to the strategy
+    fn report_fill(&mut self, fill: &Fill, res: Result<(), ErrorRepr>) {
+        match res {
+            Err(err) => return self.on_err(err),
+            Ok(_) if self.aggregate_fills => self.batched_fills.push(fill.clone()),
             Ok(_) => self.strategy.on_fill(fill),
         }
+        let trade = self.portfolio.lock().closed_trade(fill);
+        if let Some(trade) = trade {
+            self.strategy.on_trade_closed(&trade);
+        }
+    }
+
+    fn flush_fills(&mut self) {
+        for fill in aggregate_fills(std::mem::take(&mut self.batched_fills)) {
+            self.strategy.on_fill(&fill);
+        }
     }
 
     fn on_order(&mut self, ord: &mut Order, is_deferred: bool) {
-        let mut wallet = self.portfolio.lock();
+        let time = self.current_time.unwrap_or_else(|| self.clock.now());
+        if !self.portfolio.lock().admit(ord) {
+            ord.history.push((time, "rejected: not admitted".into()));
+            ord.status = OrderStatus::Canceled;
+            self.strategy.on_order(ord);
+            return self.on_skip(decision_of(ord), SkipReason::Rejected);
+        }
+        let res = self.broker.exec_order(ord, &mut *self.portfolio.lock());
 
-        let fill = match self.broker.exec_order(ord, &mut *wallet) {
+        let fill = match res {
+            Ok(f) if f.qty == 0 => {
+                ord.history.push((time, "unfilled".into()));
+                ord.status = OrderStatus::Canceled;
+                self.strategy.on_order(ord);
+                return self.on_skip(decision_of(ord), SkipReason::Unfilled);
+            }
             Ok(f) => f,
-            Err(ErrorRepr::NotSatisfied(_)) => {
+            Err(ErrorRepr::NotSatisfied(reason)) => {
                 let mut ord = ord.clone();
+                ord.history
+                    .push((time, format!("deferred: {} not met", reason)));
                 ord.lifetime = ord.lifetime.map(|x| x.saturating_sub(1));
                 return self.unfulfilled_orders.push(ord.to_owned());
             }
             Err(ErrorRepr::OrderExpired(_)) => {
+                ord.history.push((time, "expired".into()));
                 ord.status = OrderStatus::Expired;
                 self.strategy.on_order(ord);
-                return;
+                return self.on_skip(decision_of(ord), SkipReason::Expired);
+            }
+            Err(ErrorRepr::OrderRejected(msg)) => {
+                if matches!(self.rejection_policy, RejectionPolicy::Log) {
+                    self.log(log::Level::Warn, format_args!("order rejected: {}", msg));
+                }
+                ord.history.push((time, format!("rejected: {}", msg)));
+                ord.status = OrderStatus::Canceled;
+                self.strategy.on_order(ord);
+                return self.on_skip(decision_of(ord), SkipReason::Rejected);
+            }
+            Err(ErrorRepr::OrderCanceled(msg)) => {
+                if matches!(self.rejection_policy, RejectionPolicy::Log) {
+                    self.log(log::Level::Info, format_args!("order canceled: {}", msg));
+                }
+                ord.history.push((time, format!("canceled: {}", msg)));
+                ord.status = OrderStatus::Canceled;
+                self.strategy.on_order(ord);
+                return self.on_skip(decision_of(ord), SkipReason::Canceled);
             }
             Err(err) => panic!("Unhandled ERROR: {:?}", err),
         };
 
+        let rests = self.rest_remainders
+            && matches!(
+                ord.kind,
+                OrderKind::Limit {
+                    tif: TimeInForce::GTC | TimeInForce::Day,
+                    ..
+                }
+            );
         ord.status = OrderStatus::Completed;
+        if fill.qty == ord.qty {
+            ord.history.push((time, "filled".into()));
+        } else {
+            ord.history
+                .push((time, format!("partial: {}/{}", fill.qty, ord.qty)));
+            if rests {
+                ord.status = OrderStatus::PartialCompleted;
+                let mut rest = ord.clone();
+                rest.qty -= fill.qty;
+                rest.filled += fill.qty;
+                rest.cost += fill.cost;
+                self.unfulfilled_orders.push(rest);
+            }
+        }
         self.strategy.on_order(ord);
 
         let e = Event::Fill(fill);
@@ -108,62 +588,429 @@ where
         }
     }
 
-    fn enqueue_unfulfilled_orders(&mut self) {
-        while let Some(ord) = self.unfulfilled_orders.pop() {
+    /// next bar of the data in the order it is run
+    fn next_raw_bar(&mut self) -> Option<Bar> {
+        if !self.reverse {
+            return self.data.next();
+        }
+        self.reversed
+            .get_or_insert_with(|| self.data.by_ref().collect())
+            .pop()
+    }
+
+    fn next_bar(&mut self) -> Option<Bar> {
+        use TimestampPolicy::*;
+
+        loop {
+            let mut bar = self.pending_bar.take().or_else(|| self.next_raw_bar())?;
+
+            match self.first_time {
+                None => self.first_time = Some(bar.time),
+                Some(t) if self.last_time == Some(t) && bar.time < t => panic!(
+                    "{}",
+                    ErrorRepr::InvalidData(format!(
+                        "data in descending time order, set `reverse`: {:?}",
+                        bar
+                    ))
+                ),
+                _ => {}
+            }
+
+            if self.last_time.is_some_and(|t| bar.time <= t) {
+                match self.timestamp_policy {
+                    Keep | Error if self.last_time.is_some_and(|t| bar.time < t) => panic!(
+                        "{}",
+                        ErrorRepr::InvalidData(format!("out-of-order timestamp: {:?}", bar))
+                    ),
+                    Keep => self.log(
+                        log::Level::Warn,
+                        format_args!("non-increasing timestamp: {:?}", bar),
+                    ),
+                    Merge | Skip => {
+                        self.log(
+                            log::Level::Warn,
+                            format_args!("skip bar with non-increasing timestamp: {:?}", bar),
+                        );
+                        continue;
+                    }
+                    Error => panic!(
+                        "{}",
+                        ErrorRepr::InvalidData(format!("non-increasing timestamp: {:?}", bar))
+                    ),
+                }
+            }
+
+            if matches!(self.timestamp_policy, Merge) {
+                while let Some(next) = self.next_raw_bar() {
+                    if next.time != bar.time {
+                        self.pending_bar = Some(next);
+                        break;
+                    }
+                    self.log(
+                        log::Level::Warn,
+                        format_args!("merge bar with duplicate timestamp: {:?}", next),
+                    );
+                    bar.high = bar.high.max(next.high);
+                    bar.low = bar.low.min(next.low);
+                    bar.close = next.close;
+                    bar.vol += next.vol;
+                }
+            }
+
+            self.last_time = Some(bar.time);
+            return Some(bar);
+        }
+    }
+
+    fn enqueue_unfulfilled_orders(&mut self, prev: Option<DateTime>, time: DateTime) {
+        let is_new_date = prev.is_some_and(|t| t.date_naive() != time.date_naive());
+
+        while let Some(mut ord) = self.unfulfilled_orders.pop() {
+            if is_new_date
+                && matches!(
+                    ord.kind,
+                    OrderKind::Limit {
+                        tif: TimeInForce::Day,
+                        ..
+                    }
+                )
+            {
+                ord.status = OrderStatus::Expired;
+                self.strategy.on_order(&ord);
+                self.on_skip(decision_of(&ord), SkipReason::Expired);
+                continue;
+            }
             self.deferred_event_q.push_back(Event::Order(ord));
         }
     }
 
     fn on_err(&mut self, err: ErrorRepr) {
-        log::error!("{}", err);
+        self.log(log::Level::Error, format_args!("{}", err));
     }
 
-    pub async fn run(&mut self) {
-        'outer: loop {
-            match self.data.next() {
-                Some(bar) => {
-                    self.event_q.push_back(Event::Market(bar));
+    fn log(&self, level: log::Level, args: std::fmt::Arguments) {
+        (self.log_sink)(level, &args);
+    }
+
+    /// time of the next bar, without processing it
+    pub fn peek_time(&mut self) -> Option<DateTime> {
+        if self.peeked_bar.is_none() {
+            self.peeked_bar = self.pull_bar();
+        }
+        self.peeked_bar.as_ref().map(|x| x.time)
+    }
+
+    fn step_candidate(&self) -> StepCandidate {
+        let deferred = self.deferred_event_q.iter().filter_map(|x| match x {
+            Event::Order(ord) => Some(ord),
+            _ => None,
+        });
+        let pending_qty = deferred
+            .chain(&self.unfulfilled_orders)
+            .fold(Qty(0), |acc, x| acc + x.qty.abs());
+        StepCandidate {
+            sym: self.sym.clone(),
+            pending_qty,
+        }
+    }
+
+    /// settle the orders left pending after the last bar according to `end_of_data`.
+    /// called by `step` once the data is exhausted, calling it again is a no-op
+    pub fn finish(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let pending = self
+            .unfulfilled_orders
+            .drain(..)
+            .map(Event::Order)
+            .collect::<Vec<_>>();
+        self.deferred_event_q.extend(pending);
+
+        match self.end_of_data {
+            EndOfDataPolicy::ExecuteOnLastBar => {
+                if let Some(last) = &self.last_bar {
+                    self.broker.set_lastest_bar(&at_close(last));
                 }
-                _ => break 'outer,
-            }
-
-            self.enqueue_unfulfilled_orders();
-
-            while let Some(mut evt) = self.event_q.pop_front() {
-                match &mut evt {
-                    Event::Market(bar) => {
-                        // update before the deferred queue
-                        self.broker.set_lastest_bar(bar);
-                        self.portfolio
-                            .lock()
-                            .update_from_market(bar)
-                            .expect("update position failed");
-                        self.strategy.on_data(bar);
-
-                        while let Some(mut evt) = self.deferred_event_q.pop_front() {
-                            match &mut evt {
-                                Event::Order(ord) => self.on_order(ord, true),
-                                Event::Fill(fill) => self.on_fill(fill),
-                                _ => unreachable!(),
-                            }
-                            self.call_event_hook(&evt);
-                        }
+                while let Some(mut evt) = self.deferred_event_q.pop_front() {
+                    match &mut evt {
+                        Event::Order(ord) => self.on_order(ord, true),
+                        Event::Fill(fill) => self.on_fill(fill),
+                        _ => unreachable!(),
+                    }
+                    self.call_event_hook(&evt);
+                }
+                self.flush_fills();
+                // executing against the last bar may leave orders unfulfilled again
+                let left = self.unfulfilled_orders.drain(..).collect::<Vec<_>>();
+                self.report_unfilled(left);
+            }
+            EndOfDataPolicy::ReportUnfilled => {
+                let pending = self
+                    .deferred_event_q
+                    .drain(..)
+                    .map(|evt| match evt {
+                        Event::Order(ord) => ord,
+                        _ => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                self.report_unfilled(pending);
+            }
+        }
+
+        while let Some(evt) = self.event_q.pop_front() {
+            self.call_event_hook(&evt);
+        }
+        self.settle();
+        if matches!(self.rejection_policy, RejectionPolicy::Count) {
+            self.log(
+                log::Level::Info,
+                format_args!(
+                    "{}: {} decisions skipped, {:?}",
+                    self.sym,
+                    self.skipped.total(),
+                    self.skipped
+                ),
+            );
+        }
+        if let Some(time) = self.current_time {
+            self.portfolio.lock().mark_timestamp(time);
+        }
+        if self.log_summary {
+            let summary = self.portfolio.lock().summary();
+            if let Some(summary) = summary {
+                self.log(log::Level::Info, format_args!("{}: {}", self.sym, summary));
+            }
+        }
+        for shadow in &mut self.shadows {
+            shadow.gambler.finish();
+        }
+    }
+
+    /// cancel `orders` left pending by the end of the data, reporting their decisions as
+    /// skipped
+    fn report_unfilled(&mut self, orders: Vec<Order>) {
+        let time = self.current_time.unwrap_or_else(|| self.clock.now());
+        for mut ord in orders {
+            ord.history.push((time, "unfilled: end of data".into()));
+            ord.status = OrderStatus::Canceled;
+            self.strategy.on_order(&ord);
+            self.on_skip(decision_of(&ord), SkipReason::Unfilled);
+        }
+    }
 
-                        // update after the deferred queue
-                        self.on_data(bar)
+    /// close the position against the `settlement` bar
+    fn settle(&mut self) {
+        let bar = match self.settlement.clone() {
+            None => return,
+            Some(Settlement::Bar(bar)) => Bar {
+                sym: self.sym.clone(),
+                ..bar
+            },
+            Some(Settlement::LastClose) => {
+                let Some(last) = self.last_bar.take() else {
+                    return;
+                };
+                at_close(&last)
+            }
+        };
+        self.current_time = Some(bar.time);
+        self.broker.set_lastest_bar(&bar);
+        self.portfolio
+            .lock()
+            .update_from_market(&bar)
+            .expect("update position failed");
+
+        let Some(mut ord) = self.portfolio.lock().flatten(&self.sym, bar.time) else {
+            return;
+        };
+        self.assign_id(&mut ord);
+        self.strategy.on_order(&ord);
+        self.on_order(&mut ord, false);
+        self.call_event_hook(&Event::Order(ord));
+        while let Some(evt) = self.event_q.pop_front() {
+            if let Event::Fill(fill) = &evt {
+                self.on_fill(fill);
+            }
+            self.call_event_hook(&evt);
+        }
+        self.flush_fills();
+    }
+
+    /// execute at the current bar what the portfolio asks for on its own: closing the
+    /// position once it is liquidating or rebalancing it, unless an order is pending
+    fn place_portfolio_order(&mut self, time: DateTime) {
+        if !self.unfulfilled_orders.is_empty() {
+            return;
+        }
+        let ord = {
+            let mut portfolio = self.portfolio.lock();
+            if portfolio.is_liquidating() {
+                portfolio.flatten(&self.sym, time)
+            } else {
+                portfolio.rebalance(&self.sym, time)
+            }
+        };
+        let Some(mut ord) = ord else {
+            return;
+        };
+        self.assign_id(&mut ord);
+        self.strategy.on_order(&ord);
+        self.on_order(&mut ord, true);
+        self.call_event_hook(&Event::Order(ord));
+        while let Some(evt) = self.deferred_event_q.pop_front() {
+            if let Event::Fill(fill) = &evt {
+                self.on_fill(fill);
+            }
+            self.call_event_hook(&evt);
+        }
+        self.flush_fills();
+    }
+
+    /// book a fill that happened outside of the simulation, e.g. on a live account, paying
+    /// for it and reporting it to the strategy and the hooks like an internal one.
+    /// there is no buying power check, the cash may go negative
+    pub fn apply_external_fill(&mut self, fill: Fill) {
+        let res = self.portfolio.lock().apply_external_fill(&fill);
+        self.report_fill(&fill, res);
+        self.flush_fills();
+        self.call_event_hook(&Event::Fill(fill));
+    }
+
+    /// process the next bar, false if the data is exhausted
+    pub fn step(&mut self) -> bool {
+        let Some(bar) = self.peeked_bar.take().or_else(|| self.pull_bar()) else {
+            self.finish();
+            return false;
+        };
+        let time = bar.time;
+        let prev = self.current_time.replace(time);
+        for shadow in &self.shadows {
+            shadow.bars.lock().push_back(bar.clone());
+        }
+        self.event_q.push_back(Event::Market(bar));
+
+        self.enqueue_unfulfilled_orders(prev, time);
+
+        while let Some(mut evt) = self.event_q.pop_front() {
+            match &mut evt {
+                Event::Market(bar) => {
+                    if matches!(self.settlement, Some(Settlement::LastClose))
+                        || matches!(self.end_of_data, EndOfDataPolicy::ExecuteOnLastBar)
+                    {
+                        self.last_bar = Some(bar.clone());
                     }
-                    Event::Decision(d) => self.on_decision(d, true),
-                    Event::Order(ord) => self.on_order(ord, false),
-                    Event::Fill(fill) => self.on_fill(fill),
+                    // update before the deferred queue
+                    self.broker.set_lastest_bar(bar);
+                    self.portfolio
+                        .lock()
+                        .update_from_market(bar)
+                        .expect("update position failed");
+                    self.strategy
+                        .on_warmup(WarmupPhase(self.bars_seen < self.warmup));
+                    self.strategy.on_data(bar);
+
+                    while let Some(mut evt) = self.deferred_event_q.pop_front() {
+                        match &mut evt {
+                            Event::Order(ord) => self.on_order(ord, true),
+                            Event::Fill(fill) => self.on_fill(fill),
+                            _ => unreachable!(),
+                        }
+                        self.call_event_hook(&evt);
+                    }
+                    self.flush_fills();
+                    self.place_portfolio_order(bar.time);
+
+                    // update after the deferred queue
+                    self.on_data(bar)
                 }
-                self.call_event_hook(&evt);
+                Event::Decision(d) => self.on_decision(d, !self.same_bar_fill),
+                Event::Order(ord) => self.on_order(ord, false),
+                Event::Fill(fill) => self.on_fill(fill),
+                Event::DecisionSkipped(..) => {}
             }
+            self.call_event_hook(&evt);
+        }
+        self.flush_fills();
+        self.open_orders.sample(self.unfulfilled_orders.len());
+        self.portfolio.lock().mark_timestamp(time);
+        for shadow in &mut self.shadows {
+            shadow.gambler.step();
+        }
+        true
+    }
+
+    pub async fn run(&mut self) {
+        while self.step() {}
+    }
+}
+
+/// the decision an order was allocated from
+fn decision_of(ord: &Order) -> Decision {
+    Decision {
+        strength: 1.0,
+        price: None,
+        signal: None,
+        stop: None,
+        sym: ord.sym.clone(),
+        kind: if ord.qty > 0 {
+            DecisionKind::Buy
+        } else {
+            DecisionKind::Sell
+        },
+        time: ord.time,
+    }
+}
+
+/// a flat bar at the close of `bar`
+fn at_close(bar: &Bar) -> Bar {
+    Bar {
+        open: bar.close,
+        high: bar.close,
+        low: bar.close,
+        dividend: 0.0,
+        ..bar.clone()
+    }
+}
+
+/// a gambler about to step at a timestamp of `Casino::run_ordered`
+#[derive(Debug, Clone)]
+pub struct StepCandidate {
+    pub sym: Symbol,
+    /// total absolute quantity of the orders waiting for the bar
+    pub pending_qty: Qty,
+}
+
+pub type StepComparator = Arc<dyn Fn(&StepCandidate, &StepCandidate) -> Ordering + Send + Sync>;
+
+/// order in which `Casino::run_ordered` steps the gamblers sharing a timestamp, which
+/// decides whose orders fill first against a shared cash budget
+#[derive(Clone, Default)]
+pub enum StepPriority {
+    /// the order the gamblers were given in
+    #[default]
+    Insertion,
+    /// alphabetical by symbol
+    Symbol,
+    /// largest pending quantity first
+    OrderSize,
+    Custom(StepComparator),
+}
+
+impl StepPriority {
+    fn compare(&self, a: &StepCandidate, b: &StepCandidate) -> Ordering {
+        match self {
+            StepPriority::Insertion => Ordering::Equal,
+            StepPriority::Symbol => a.sym.cmp(&b.sym),
+            StepPriority::OrderSize => b.pending_qty.cmp(&a.pending_qty),
+            StepPriority::Custom(f) => f(a, b),
         }
     }
 }
 
 pub struct Casino<A, B, C, D> {
     gamblers: Vec<Gambler<A, B, C, D>>,
+    priority: StepPriority,
 }
 
 impl<Strategy, Data, Exector, Portfolio> Casino<Strategy, Data, Exector, Portfolio>
@@ -174,20 +1021,107 @@ where
     Portfolio: PositionManager + OrderAllocator + Wallet + Send + 'static,
 {
     pub fn new(gamblers: Vec<Gambler<Strategy, Data, Exector, Portfolio>>) -> Self {
-        Self { gamblers }
+        Self {
+            gamblers,
+            priority: Default::default(),
+        }
     }
 
-    pub async fn run(&mut self) {
+    /// let the strategies precompute on the blocking pool, one batch of gamblers per core
+    async fn prepare(&mut self) {
+        if !self.gamblers.iter().any(|g| g.precompute) {
+            return;
+        }
+        let workers = std::thread::available_parallelism().map_or(1, |x| x.get());
+        let size = self.gamblers.len().div_ceil(workers);
+        let mut rest = std::mem::take(&mut self.gamblers);
+        let mut batches = vec![];
+        while !rest.is_empty() {
+            let tail = rest.split_off(size.min(rest.len()));
+            let mut batch = std::mem::replace(&mut rest, tail);
+            batches.push(tokio::task::spawn_blocking(move || {
+                batch.iter_mut().for_each(|g| g.prepare());
+                batch
+            }));
+        }
+        for batch in batches {
+            self.gamblers.extend(batch.await.unwrap());
+        }
+    }
+
+    /// set the order gamblers sharing a timestamp are stepped in by `run_ordered`
+    pub fn with_priority(mut self, priority: StepPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// run all gamblers concurrently, returning the portfolio stats of each, sorted by symbol
+    pub async fn run(&mut self) -> Vec<(Symbol, Portfolio::Stats)>
+    where
+        Portfolio: Statistics,
+        Portfolio::Stats: Send + 'static,
+    {
         let mut join_handlers = tokio::task::JoinSet::new();
 
         while let Some(mut g) = self.gamblers.pop() {
             join_handlers.spawn(async move {
                 g.run().await;
+                let stats = g.portfolio.lock().stats();
+                (g.sym, stats)
             });
         }
 
+        let mut summary = vec![];
         while let Some(res) = join_handlers.join_next().await {
-            res.unwrap();
+            summary.push(res.unwrap());
+        }
+        summary.sort_by(|a, b| a.0.cmp(&b.0));
+        summary
+    }
+
+    /// stats over the distinct portfolios of the gamblers, counting a shared one once.
+    /// `run` consumes the gamblers, so this is only meaningful after `run_ordered`
+    pub fn combined_stats(&self) -> PortfolioStats
+    where
+        Portfolio: Statistics<Stats = PortfolioStats>,
+    {
+        let mut portfolios: Vec<&Arc<Mutex<Portfolio>>> = vec![];
+        for g in &self.gamblers {
+            if !portfolios.iter().any(|x| Arc::ptr_eq(x, &g.portfolio)) {
+                portfolios.push(&g.portfolio);
+            }
+        }
+        PortfolioStats::combine(portfolios.iter().map(|x| x.lock().stats()).collect())
+    }
+
+    /// step all gamblers together in timestamp order, so that a shared portfolio is
+    /// marked once per timestamp after every symbol has been processed
+    pub async fn run_ordered(&mut self) {
+        self.prepare().await;
+
+        while let Some(time) = self.gamblers.iter_mut().filter_map(|g| g.peek_time()).min() {
+            let mut due = vec![];
+            for (i, g) in self.gamblers.iter_mut().enumerate() {
+                if g.peek_time() == Some(time) {
+                    due.push((i, g.step_candidate()));
+                }
+            }
+            // stable, ties keep the insertion order
+            due.sort_by(|a, b| self.priority.compare(&a.1, &b.1));
+            for (i, _) in due {
+                let g = &mut self.gamblers[i];
+                g.step();
+                // settle now, marking after the later timestamps would be out of order
+                if g.peek_time().is_none() {
+                    g.finish();
+                }
+            }
+            for g in &self.gamblers {
+                g.portfolio.lock().mark_timestamp(time);
+            }
+        }
+        for g in self.gamblers.iter_mut() {
+            g.finish();
         }
     }
 }
@@ -1,128 +1,2265 @@
 pub mod broker;
+pub mod clock;
 pub mod data;
 pub mod errors;
 pub mod event;
+#[cfg(feature = "arrow")]
+pub mod feather;
 pub mod gambler;
+pub mod master;
+pub mod metrics;
 pub mod order;
 pub mod portfolio;
-pub mod strategy;
 pub mod position;
+pub mod research;
+pub mod strategy;
+pub mod units;
+
+/// absolute tolerance of the float comparisons in internal consistency checks
+pub const TOLERANCE: f64 = 1e-9;
+
+#[cfg(test)]
+mod tests {
+    use more_asserts::*;
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::{data::Bar, gambler::Casino, portfolio::Statistics, strategy::DecisionMaker};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct TestStrategy {
+        idx: i32,
+    }
+
+    impl DecisionMaker for TestStrategy {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            self.idx += 1;
+            strategy::Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                time: data.time,
+                sym: data.sym.clone(),
+                kind: if self.idx % 2 == 1 {
+                    strategy::DecisionKind::Buy
+                } else {
+                    strategy::DecisionKind::Sell
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gambler() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let bars = vec![
+            build_bar(5.0, 6.0),
+            build_bar(7.0, 8.0),
+            build_bar(1.0, 2.0),
+            build_bar(1.0, 2.0),
+        ];
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(
+                broker::SimulatedBrokerBuilder::default()
+                    .commission(0.001)
+                    .build()
+                    .unwrap(),
+            )
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+        g.add_event_hook(|s, evt| println!(">>> ({}) event: {:?}", s, evt));
+        g.run().await;
+
+        let p = portfolio.lock();
+        assert_eq!(p.init_cash, 10_000.0);
+        assert_le!(
+            (10_000.0 - 700.0 + 100.0 - 100.0 - 0.9 - p.cash).abs(),
+            0.01
+        );
+    }
+
+    #[tokio::test]
+    async fn test_casino() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let bars = vec![
+            build_bar(5.0, 6.0),
+            build_bar(7.0, 8.0),
+            build_bar(1.0, 2.0),
+            build_bar(1.0, 2.0),
+        ];
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(
+                broker::SimulatedBrokerBuilder::default()
+                    .commission(0.001)
+                    .build()
+                    .unwrap(),
+            )
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+
+        g.add_event_hook(|s, evt| println!(">>> ({}) event: {:?}", s, evt));
+
+        let mut casino = Casino::new(vec![g]);
+        casino.run().await;
+
+        let p = portfolio.lock();
+        assert_eq!(p.init_cash, 10_000.0);
+        assert_le!(
+            (10_000.0 - 700.0 + 100.0 - 100.0 - 0.9 - p.cash).abs(),
+            0.01
+        );
+    }
+
+    #[tokio::test]
+    async fn test_casino_pnl_split() {
+        let gamblers = [("closed", 3), ("open", 2)].map(|(sym, n)| {
+            let bars = [(5.0, 6.0), (7.0, 8.0), (9.0, 10.0)].map(|(open, close)| Bar {
+                sym: sym.into(),
+                ..build_bar(open, close)
+            });
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 100,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+
+            gambler::GamblerBuilder::default()
+                .sym(sym)
+                .strategy(TestStrategy { idx: 0 })
+                .data(bars.into_iter().take(n))
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .build()
+                .unwrap()
+        });
+
+        let summary = Casino::new(gamblers.into()).run().await;
+        assert_eq!(summary.len(), 2);
+
+        let (sym, closed) = &summary[0];
+        assert_eq!(sym, "closed");
+        assert_eq!(closed.realized_pnl, 200.0);
+        assert_eq!(closed.unrealized_pnl, 0.0);
+
+        let (sym, open) = &summary[1];
+        assert_eq!(sym, "open");
+        assert_eq!(open.realized_pnl, 0.0);
+        assert_eq!(open.unrealized_pnl, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_step_priority() {
+        let run = |priority| {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 60,
+                    ..Default::default()
+                })
+                .cash(1000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            let start = chrono::Utc::now();
+            // both buy 60 on the first bar, only one of them is affordable on the second
+            let gamblers = ["b", "a"].map(|sym| {
+                let bars = (0..2)
+                    .map(|i| Bar {
+                        sym: sym.into(),
+                        time: start + chrono::Duration::days(i),
+                        ..build_bar(10.0, 10.0)
+                    })
+                    .collect::<Vec<_>>();
+                gambler::GamblerBuilder::default()
+                    .sym(sym)
+                    .strategy(FillRecorder::default())
+                    .data(bars.into_iter())
+                    .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                    .portfolio(Arc::clone(&portfolio))
+                    .build()
+                    .unwrap()
+            });
+            async move {
+                Casino::new(gamblers.into())
+                    .with_priority(priority)
+                    .run_ordered()
+                    .await;
+                let p = portfolio.lock();
+                (p.positions["a"].qty.0, p.positions["b"].qty.0)
+            }
+        };
+
+        assert_eq!(run(gambler::StepPriority::Insertion).await, (40, 60));
+        assert_eq!(run(gambler::StepPriority::Symbol).await, (60, 40));
+        let reversed = gambler::StepPriority::Custom(Arc::new(|a, b| b.sym.cmp(&a.sym)));
+        assert_eq!(run(reversed).await, (40, 60));
+    }
+
+    /// buys on the first bar only
+    struct EntryStrategy(f64);
+
+    impl DecisionMaker for EntryStrategy {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            let strength = std::mem::take(&mut self.0);
+            strategy::Decision {
+                strength,
+                price: None,
+                signal: None,
+                stop: None,
+                time: data.time,
+                sym: data.sym.clone(),
+                kind: if strength > 0.0 {
+                    strategy::DecisionKind::Buy
+                } else {
+                    strategy::DecisionKind::Hold
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_bar_entry() {
+        for (source, qty) in [
+            (order::PriceSource::CurrentOpen, 200),
+            (order::PriceSource::LastClose, 166),
+        ] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedValueOrderManager {
+                    val: 1000.0,
+                    price_source: source,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(EntryStrategy(1.0))
+                .data(vec![build_bar(5.0, 6.0)].into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .same_bar_fill(true)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            // filled at the open of the bar it was decided on
+            let p = portfolio.lock();
+            assert_eq!(p.positions["test"].qty, qty);
+            assert_eq!(p.cash, 10000.0 - 5.0 * qty as f64);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cash_utilization() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedValueOrderManager {
+                val: 5000.0,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let start = chrono::Utc::now();
+        let bars = (0..10)
+            .map(|i| Bar {
+                time: start + chrono::Duration::days(i),
+                ..build_bar(5.0, 5.0)
+            })
+            .collect::<Vec<_>>();
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(EntryStrategy(1.0))
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .same_bar_fill(true)
+            .build()
+            .unwrap();
+        g.run().await;
+
+        // half of the cash is spent on the first bar and held throughout
+        let stats = portfolio.lock().stats();
+        let series = stats.cash_utilization_series();
+        assert_eq!(series.len(), 10);
+        assert!(series.iter().all(|x| (x.1 - 0.5).abs() < 1e-9));
+        assert!((stats.avg_cash_utilization() - 0.5).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_run_ordered_uneven_data() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let start = chrono::Utc::now();
+        let gamblers = [("short", 2), ("long", 5)].map(|(sym, len)| {
+            let bars = (0..len)
+                .map(|i| Bar {
+                    sym: sym.into(),
+                    time: start + chrono::Duration::days(i),
+                    ..build_bar(10.0, 10.0)
+                })
+                .collect::<Vec<_>>();
+            gambler::GamblerBuilder::default()
+                .sym(sym)
+                .strategy(EntryStrategy(1.0))
+                .data(bars.into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .end_of_data(gambler::EndOfDataPolicy::ExecuteOnLastBar)
+                .build()
+                .unwrap()
+        });
+        Casino::new(gamblers.into()).run_ordered().await;
+
+        let stats = portfolio.lock().stats();
+        assert_eq!(stats.equity_curve.len(), 5);
+        assert!(stats.equity_curve.windows(2).all(|x| x[0].0 < x[1].0));
+        assert!(stats.cash_curve.windows(2).all(|x| x[0].0 < x[1].0));
+    }
+
+    #[tokio::test]
+    async fn test_max_new_positions_per_bar() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(100000.0)
+            .max_new_positions_per_bar(Some(2))
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let start = chrono::Utc::now();
+        let signals = [("a", 0.3), ("b", 0.5), ("c", 0.1), ("d", 0.4), ("e", 0.2)];
+        let gamblers = signals.map(|(sym, strength)| {
+            let bars = (0..2)
+                .map(|i| Bar {
+                    sym: sym.into(),
+                    time: start + chrono::Duration::days(i),
+                    ..build_bar(10.0, 10.0)
+                })
+                .collect::<Vec<_>>();
+            gambler::GamblerBuilder::default()
+                .sym(sym)
+                .strategy(EntryStrategy(strength))
+                .data(bars.into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .build()
+                .unwrap()
+        });
+        Casino::new(gamblers.into()).run_ordered().await;
+
+        let p = portfolio.lock();
+        let qty = |sym: &str| p.positions.get(sym).map_or(0, |x| x.qty.0);
+        assert_eq!(signals.map(|x| qty(x.0)), [0, 50, 0, 40, 0]);
+        assert_eq!(p.rejections, 3);
+    }
+
+    #[tokio::test]
+    async fn test_shortfall_policy() {
+        use strategy::DecisionKind::*;
+
+        for (policy, held, rejections) in [
+            (broker::ShortfallPolicy::Cancel, 0, 1),
+            (broker::ShortfallPolicy::Defer, 200, 0),
+        ] {
+            // enough for one of the two buys
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 200,
+                    ..Default::default()
+                })
+                .cash(1000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            let start = chrono::Utc::now();
+            let scripts = [("a", vec![Buy, Hold, Sell]), ("b", vec![Buy])];
+            let gamblers = scripts.map(|(sym, script)| {
+                let bars = (0..5)
+                    .map(|i| Bar {
+                        sym: sym.into(),
+                        time: start + chrono::Duration::days(i),
+                        ..build_bar(5.0, 5.0)
+                    })
+                    .collect::<Vec<_>>();
+                gambler::GamblerBuilder::default()
+                    .sym(sym)
+                    .strategy(ScriptedStrategy(script.into()))
+                    .data(bars.into_iter())
+                    .broker(
+                        broker::SimulatedBrokerBuilder::default()
+                            .shortfall(policy)
+                            .build()
+                            .unwrap(),
+                    )
+                    .portfolio(Arc::clone(&portfolio))
+                    .build()
+                    .unwrap()
+            });
+            let mut casino = Casino::new(gamblers.into());
+            casino.run_ordered().await;
+
+            // b waits for the cash a frees by selling, instead of being dropped
+            let p = portfolio.lock();
+            assert_eq!(p.positions["a"].qty, 0);
+            assert_eq!(p.positions["b"].qty, held);
+            assert_eq!(p.rejections, rejections);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_combined_stats() {
+        let portfolios = [(); 3].map(|_| {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 100,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            Arc::new(Mutex::new(portfolio))
+        });
+        let gamblers = ["a", "b", "c"]
+            .into_iter()
+            .zip(&portfolios)
+            .map(|(sym, portfolio)| {
+                let bars = [(5.0, 6.0), (7.0, 8.0), (9.0, 10.0)].map(|(open, close)| Bar {
+                    sym: sym.into(),
+                    ..build_bar(open, close)
+                });
+                gambler::GamblerBuilder::default()
+                    .sym(sym)
+                    .strategy(TestStrategy::default())
+                    .data(bars.into_iter())
+                    .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                    .portfolio(Arc::clone(portfolio))
+                    .build()
+                    .unwrap()
+            });
+
+        let mut casino = Casino::new(gamblers.collect());
+        casino.run_ordered().await;
+        let combined = casino.combined_stats();
+
+        let pnls = portfolios
+            .iter()
+            .map(|x| x.lock().stats().pnl)
+            .collect::<Vec<_>>();
+        assert_eq!(pnls, vec![200.0; 3]);
+        assert_eq!(combined.pnl, pnls.iter().sum::<f64>());
+        assert_eq!(combined.init_cash, 30000.0);
+        assert_eq!(combined.positions.len(), 3);
+        assert_eq!(combined.equity_curve.last().unwrap().1, 30600.0);
+    }
+
+    #[tokio::test]
+    async fn test_recording_broker() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let bars = vec![
+            build_bar(5.0, 6.0),
+            build_bar(7.0, 8.0),
+            build_bar(9.0, 10.0),
+        ];
+        let broker = broker::RecordingBroker::new(
+            broker::SimulatedBrokerBuilder::default().build().unwrap(),
+        );
+        let records = broker.records();
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(broker)
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        let records = records.lock();
+        let executed = records
+            .iter()
+            .map(|(ord, res)| (ord.qty.0, res.as_ref().unwrap().price.0))
+            .collect::<Vec<_>>();
+        assert_eq!(executed, vec![(100, 7.0), (-100, 9.0)]);
+    }
+
+    #[tokio::test]
+    async fn test_skip_first_decision() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let strategy = FillRecorder::default();
+        let fills = Arc::clone(&strategy.fills);
+        let bars = vec![build_bar(5.0, 6.0), build_bar(7.0, 8.0)];
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .same_bar_fill(true)
+            .skip_first_decision(true)
+            .build()
+            .unwrap();
+        g.run().await;
+
+        // only the decision of the second bar makes an order
+        let fills = fills.lock();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 7.0);
+    }
+
+    #[derive(Default)]
+    struct BarRecorder {
+        bars: Arc<Mutex<Vec<Bar>>>,
+    }
+
+    impl DecisionMaker for BarRecorder {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            strategy::Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                time: data.time,
+                sym: data.sym.clone(),
+                kind: strategy::DecisionKind::Hold,
+            }
+        }
+
+        fn on_data(&mut self, data: &Bar) {
+            self.bars.lock().push(data.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_data_pipeline() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager::default())
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let closes = [1.0, 2.0, -1.0, 3.0, 4.0, f64::NAN, 5.0];
+        let bars = closes.map(|close| build_bar(close, close)).to_vec();
+        let strategy = BarRecorder::default();
+        let seen = Arc::clone(&strategy.bars);
+
+        // drop invalid bars then merge every 2 bars into one
+        let resample = |iter: Box<dyn Iterator<Item = Bar> + Send>| {
+            let mut iter = iter;
+            std::iter::from_fn(move || {
+                let first = iter.next()?;
+                Some(match iter.next() {
+                    Some(last) => Bar {
+                        close: last.close,
+                        high: first.high.max(last.high),
+                        low: first.low.min(last.low),
+                        vol: first.vol + last.vol,
+                        ..first
+                    },
+                    None => first,
+                })
+            })
+        };
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(data::Pipeline::new(bars.into_iter()))
+            .transform(|iter| iter.filter(|x| x.close > 0.0))
+            .transform(resample)
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        let seen = seen.lock();
+        let ohlc = seen.iter().map(|x| (x.open, x.close)).collect::<Vec<_>>();
+        assert_eq!(ohlc, [(1.0, 2.0), (3.0, 4.0), (5.0, 5.0)]);
+        assert_eq!(seen[0].vol, 20000.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "`transform` called before `data`")]
+    fn test_transform_without_data() {
+        let _ = gambler::GamblerBuilder::<
+            TestStrategy,
+            data::Pipeline,
+            broker::SimulatedBroker,
+            portfolio::SimplePortfolio<order::FixedSizeOrderManager>,
+        >::default()
+        .transform(|iter| iter.filter(|x| x.close > 0.0));
+    }
+
+    #[derive(Default)]
+    struct WarmupCounter {
+        warmup: bool,
+        live_bars: Arc<Mutex<usize>>,
+    }
+
+    impl DecisionMaker for WarmupCounter {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            strategy::Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                time: data.time,
+                sym: data.sym.clone(),
+                kind: strategy::DecisionKind::Buy,
+            }
+        }
+
+        fn on_warmup(&mut self, phase: strategy::WarmupPhase) {
+            self.warmup = phase.0;
+        }
+
+        fn on_data(&mut self, _: &Bar) {
+            if !self.warmup {
+                *self.live_bars.lock() += 1;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let bars = (0..5)
+            .map(|i| build_bar(5.0 + i as f64, 6.0))
+            .collect::<Vec<_>>();
+
+        let strategy = WarmupCounter::default();
+        let live_bars = Arc::clone(&strategy.live_bars);
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .same_bar_fill(true)
+            .warmup(3)
+            .build()
+            .unwrap();
+        g.run().await;
+
+        assert_eq!(*live_bars.lock(), 2);
+        // only the last two bars buy
+        assert_eq!(portfolio.lock().positions["test"].qty, 20);
+    }
+
+    #[tokio::test]
+    async fn test_decision_interval() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let bars = (0..12)
+            .map(|i| build_bar(i as f64 + 1.0, i as f64 + 1.0))
+            .collect::<Vec<_>>();
+
+        let strategy = WarmupCounter::default();
+        let live_bars = Arc::clone(&strategy.live_bars);
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .same_bar_fill(true)
+            .decision_interval(5)
+            .build()
+            .unwrap();
+        g.run().await;
+
+        assert_eq!(*live_bars.lock(), 12);
+        // bought on the 5th and 10th bars only
+        let p = portfolio.lock();
+        let prices = p.positions["test"]
+            .stats
+            .transactions
+            .iter()
+            .map(|x| x.price.0)
+            .collect::<Vec<_>>();
+        assert_eq!(prices, [5.0, 10.0]);
+    }
+
+    #[tokio::test]
+    async fn test_export_timeline_csv() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(FillRecorder::default())
+            .data(vec![build_bar(5.0, 6.0), build_bar(7.0, 8.0)].into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+
+        let events = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&events);
+        g.add_event_hook(move |_, _| *counter.lock() += 1);
+        let path = std::env::temp_dir().join("backgambler_test_timeline.csv");
+        g.export_timeline_csv(&path).unwrap();
+        g.run().await;
+
+        let mut rdr = csv::Reader::from_path(&path).unwrap();
+        assert_eq!(
+            rdr.headers().unwrap(),
+            vec![
+                "time",
+                "symbol",
+                "event_type",
+                "qty",
+                "price",
+                "cost",
+                "status"
+            ]
+        );
+        let rows = rdr
+            .records()
+            .map(|x| x.unwrap()[2].to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), *events.lock());
+        // the order decided on the first bar executes as the second bar arrives, the one
+        // decided on the last bar is reported unfilled
+        assert_eq!(
+            rows,
+            vec!["market", "decision", "order", "fill", "market", "decision", "skipped"]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_end_of_data() {
+        use gambler::EndOfDataPolicy::*;
+
+        for policy in [ReportUnfilled, ExecuteOnLastBar] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let strategy = FillRecorder::default();
+            let fills = Arc::clone(&strategy.fills);
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(strategy)
+                .data(vec![build_bar(5.0, 6.0)].into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .end_of_data(policy)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            // the buy decided on the only bar has no next bar to fill on
+            match policy {
+                ReportUnfilled => {
+                    assert!(fills.lock().is_empty());
+                    assert_eq!(g.skipped_decisions().unfilled, 1);
+                }
+                ExecuteOnLastBar => {
+                    assert_eq!(fills.lock().len(), 1);
+                    // at the close, the open was already past when the buy was decided
+                    assert_eq!(fills.lock()[0].price, 6.0);
+                    assert_eq!(g.skipped_decisions().total(), 0);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_end_of_data_unmet_limit() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(LimitOrderManager(order::TimeInForce::GTC))
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let strategy = FillRecorder::default();
+        let (fills, orders) = (Arc::clone(&strategy.fills), Arc::clone(&strategy.orders));
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(vec![build_bar(6.0, 6.0)].into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .end_of_data(gambler::EndOfDataPolicy::ExecuteOnLastBar)
+            .build()
+            .unwrap();
+        g.run().await;
+
+        // the limit of 5 is not reached at the last close of 6, reported as unfilled
+        assert!(fills.lock().is_empty());
+        assert_eq!(g.skipped_decisions().unfilled, 1);
+        let ord = orders.lock().last().cloned().unwrap();
+        assert!(matches!(ord.status, order::OrderStatus::Canceled));
+        assert_eq!(ord.history.last().unwrap().1, "unfilled: end of data");
+    }
+
+    #[tokio::test]
+    async fn test_settlement() {
+        use gambler::Settlement;
+
+        let settlement_bar = Bar {
+            time: chrono::Utc::now() + chrono::Duration::days(1),
+            ..build_bar(8.0, 8.0)
+        };
+        let settlement_time = settlement_bar.time;
+        for (settlement, cash) in [
+            (Settlement::LastClose, 10010.0),
+            (Settlement::Bar(settlement_bar), 10030.0),
+        ] {
+            let settles_late = matches!(settlement, Settlement::Bar(_));
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+
+            let strategy = FillRecorder::default();
+            let orders = Arc::clone(&strategy.orders);
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(strategy)
+                .data(vec![build_bar(5.0, 6.0), build_bar(5.0, 6.0)].into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .settlement(Some(settlement))
+                .build()
+                .unwrap();
+            g.run().await;
+
+            // 10 bought at 5 on the second bar, sold at the settlement price
+            let p = portfolio.lock();
+            assert_eq!(p.positions["test"].qty, 0);
+            assert_eq!(p.cash, cash);
+            // the closing order is stamped with the settlement bar
+            let close = orders.lock().last().cloned().unwrap();
+            assert_eq!(close.qty, -10);
+            if settles_late {
+                assert_eq!(close.time, settlement_time);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shadow_portfolio() {
+        use strategy::DecisionKind::*;
+
+        let build_portfolio = || {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            Arc::new(Mutex::new(portfolio))
+        };
+        let free = build_portfolio();
+        let costly = build_portfolio();
+        let bars = [(5.0, 6.0), (6.0, 7.0), (7.0, 8.0), (8.0, 8.0)]
+            .iter()
+            .enumerate()
+            .map(|(i, (open, close))| Bar {
+                time: chrono::Utc::now() + chrono::Duration::days(i as i64),
+                ..build_bar(*open, *close)
+            })
+            .collect::<Vec<_>>();
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(ScriptedStrategy([Buy, Hold, Sell].into()))
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&free))
+            .build()
+            .unwrap();
+        let broker = broker::SimulatedBrokerBuilder::default()
+            .commission(broker::Commission::Ratio(0.01))
+            .build()
+            .unwrap();
+        g.add_shadow(broker, Arc::clone(&costly)).unwrap();
+        g.run().await;
+
+        // bought 10 at 6, sold 10 at 8 in both
+        let free = free.lock().stats();
+        let costly = costly.lock().stats();
+        assert!(free.reconcile().is_ok());
+        assert!(costly.reconcile().is_ok());
+        assert_eq!(free.fees_paid, 0.0);
+        assert!((costly.fees_paid - 1.4).abs() < 1e-9);
+        assert_eq!(free.gross_pnl, 20.0);
+        assert!((costly.gross_pnl - free.gross_pnl).abs() < 1e-9);
+        assert!((costly.pnl + costly.fees_paid - free.pnl).abs() < 1e-9);
+    }
+
+    struct ScriptedStrategy(std::collections::VecDeque<strategy::DecisionKind>);
+
+    impl DecisionMaker for ScriptedStrategy {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            strategy::Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                time: data.time,
+                sym: data.sym.clone(),
+                kind: self.0.pop_front().unwrap_or(strategy::DecisionKind::Hold),
+            }
+        }
+    }
+
+    #[test]
+    fn test_liquidation_closes_positions() {
+        let mut portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        portfolio.set_liquidation_trigger(|x| x.drawdown > 0.02);
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let start = chrono::Utc::now();
+        let bars = [
+            (10.0, 10.0),
+            (10.0, 10.0),
+            (5.0, 5.0),
+            (6.0, 6.0),
+            (7.0, 7.0),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (open, close))| Bar {
+            time: start + chrono::Duration::days(i as i64),
+            ..build_bar(open, close)
+        })
+        .collect::<Vec<_>>();
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(ScriptedStrategy([strategy::DecisionKind::Buy].into()))
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+        // the buy on the first bar fills at the open of the second
+        g.step();
+        g.step();
+        assert_eq!(portfolio.lock().positions["test"].qty, 100);
+        // down to 5, the trigger fires at the end of the bar
+        g.step();
+        assert!(portfolio.lock().liquidating);
+        assert_eq!(portfolio.lock().positions["test"].qty, 100);
+        // closed at the next open, before the hold of that bar would close it a bar later
+        g.step();
+        let p = portfolio.lock();
+        assert_eq!(p.positions["test"].qty, 0);
+        assert_eq!(p.positions["test"].trades[0].pnl, 100.0 * (6.0 - 10.0));
+    }
+
+    #[tokio::test]
+    async fn test_debounce() {
+        use strategy::DecisionKind::*;
+
+        for (debounce, expected) in [
+            (false, vec![10, 10, -10, -10, 10]),
+            (true, vec![10, -10, 10]),
+        ] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            let bars = (0..6).map(|_| build_bar(5.0, 5.0)).collect::<Vec<_>>();
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(ScriptedStrategy([Buy, Buy, Sell, Sell, Buy].into()))
+                .data(bars.into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .debounce(debounce)
+                .build()
+                .unwrap();
+            let fills = Arc::new(Mutex::new(vec![]));
+            let sink = Arc::clone(&fills);
+            g.add_event_hook(move |_, evt| {
+                if let event::Event::Fill(fill) = evt {
+                    sink.lock().push(fill.qty.0);
+                }
+            });
+            g.run().await;
+
+            assert_eq!(*fills.lock(), expected);
+        }
+    }
+
+    #[derive(Default)]
+    struct TradeRecorder {
+        inner: TestStrategy,
+        trades: Arc<Mutex<Vec<position::Trade>>>,
+    }
+
+    impl DecisionMaker for TradeRecorder {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            self.inner.make_decision(data)
+        }
+
+        fn on_trade_closed(&mut self, trade: &position::Trade) {
+            self.trades.lock().push(trade.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_trade_closed() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let strategy = TradeRecorder::default();
+        let trades = Arc::clone(&strategy.trades);
+        let bars = vec![
+            build_bar(5.0, 6.0),
+            build_bar(7.0, 8.0),
+            build_bar(9.0, 10.0),
+        ];
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        // bought at 7 and sold at 9, the buy decided on the last bar never fills
+        let trades = trades.lock();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].pnl, 200.0);
+        assert_eq!(trades[0].bars, 1);
+    }
+
+    /// a log sink keeping the messages, and the messages it kept
+    fn capture_log() -> (gambler::LogSink, Arc<Mutex<Vec<String>>>) {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&lines);
+        let sink: gambler::LogSink = Box::new(move |_, args| sink.lock().push(args.to_string()));
+        (sink, lines)
+    }
+
+    #[tokio::test]
+    async fn test_log_summary() {
+        for log_summary in [false, true] {
+            let (sink, lines) = capture_log();
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(FillRecorder::default())
+                .data(vec![build_bar(5.0, 6.0), build_bar(5.0, 6.0)].into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .settlement(Some(gambler::Settlement::LastClose))
+                .log_summary(log_summary)
+                .log_sink(sink)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            // 10 bought at 5 and settled at 6
+            let lines = lines.lock();
+            let summary = lines.iter().find(|x| x.starts_with("test: equity"));
+            assert_eq!(summary.is_some(), log_summary);
+            if let Some(summary) = summary {
+                assert!(summary.contains("equity 10010.00"), "{}", summary);
+                assert!(summary.contains("1 trades"), "{}", summary);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejection_policy() {
+        for policy in [event::RejectionPolicy::Log, event::RejectionPolicy::Silent] {
+            let (sink, lines) = capture_log();
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(LimitOrderManager(order::TimeInForce::IOC))
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+
+            // the limit at 5 is never reached, so every IOC order is canceled
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(FillRecorder::default())
+                .data(vec![build_bar(6.0, 7.0), build_bar(6.0, 7.0)].into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .rejection_policy(policy)
+                .log_sink(sink)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            let logs = lines.lock().len();
+            match policy {
+                event::RejectionPolicy::Silent => assert_eq!(logs, 0),
+                _ => assert_gt!(logs, 0),
+            }
+            // canceled on the second bar, unfilled at the end of the data
+            assert_eq!(portfolio.lock().stats().rejections, 2);
+        }
+    }
+
+    #[test]
+    fn test_gambler_validation() {
+        let build = |cash, commission| {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager::default())
+                .cash(cash)
+                .build()
+                .unwrap();
+            let broker = broker::SimulatedBrokerBuilder::default()
+                .commission(commission)
+                .build()
+                .unwrap();
+            gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(FillRecorder::default())
+                .data(vec![build_bar(5.0, 6.0)].into_iter())
+                .broker(broker)
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .build()
+        };
+
+        assert!(build(1000.0, 0.001).is_ok());
+        let err = build(-1000.0, 0.001).err().unwrap();
+        assert!(matches!(err, errors::ErrorRepr::InvalidConfig(_)));
+        let err = build(1000.0, -0.001).err().unwrap();
+        assert!(matches!(err, errors::ErrorRepr::InvalidConfig(_)));
+    }
+
+    #[test]
+    fn test_apply_external_fill() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager::default())
+            .cash(1000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let strategy = FillRecorder::default();
+        let fills = Arc::clone(&strategy.fills);
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(strategy)
+            .data(std::iter::empty())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+        let events = Arc::new(Mutex::new(0));
+        let seen = Arc::clone(&events);
+        g.add_event_hook(move |_, evt| {
+            if matches!(evt, event::Event::Fill(_)) {
+                *seen.lock() += 1;
+            }
+        });
+
+        g.apply_external_fill(order::Fill {
+            order_id: 0,
+            risk: None,
+            sym: "test".into(),
+            qty: units::Qty(10),
+            price: units::Price(20.0),
+            cost: 1.0,
+            time: chrono::Utc::now(),
+        });
+
+        let p = portfolio.lock();
+        assert_eq!(p.positions["test"].qty, 10);
+        assert_eq!(p.cash, 1000.0 - 200.0 - 1.0);
+        assert_eq!(fills.lock().len(), 1);
+        assert_eq!(*events.lock(), 1);
+    }
+
+    #[test]
+    fn test_verify_replay() {
+        let bars = vec![
+            build_bar(5.0, 6.0),
+            build_bar(7.0, 8.0),
+            build_bar(1.0, 2.0),
+            build_bar(1.0, 2.0),
+        ];
+        let build = |idx| {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 100,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(TestStrategy { idx })
+                .data(bars.clone().into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .build()
+                .unwrap()
+        };
+
+        let mut g = build(0);
+        let recorded = g.record_timeline();
+        while g.step() {}
+        let recorded = recorded.lock().clone();
+        assert!(!recorded.is_empty());
+
+        assert_eq!(
+            build(0).verify_replay(&recorded, bars.clone().into_iter()),
+            Ok(())
+        );
+        // sells first instead of buying
+        let diff = build(1)
+            .verify_replay(&recorded, bars.clone().into_iter())
+            .unwrap_err();
+        assert_eq!(diff.index, 1);
+        assert_eq!(diff.recorded.unwrap().status.as_deref(), Some("Buy"));
+    }
+
+    #[test]
+    fn test_gambler_seed() {
+        let run = |seed| {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 100,
+                    ..Default::default()
+                })
+                .cash(100000.0)
+                .build()
+                .unwrap();
+            let broker = broker::SimulatedBrokerBuilder::default()
+                .slippage(broker::Cost::Ratio(0.01))
+                .seed(1)
+                .build()
+                .unwrap();
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("ORCL")
+                .strategy(TestStrategy { idx: 0 })
+                .data(data::tests::test_data_iter())
+                .broker(broker)
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .seed(seed)
+                .build()
+                .unwrap();
+            let rows = g.record_timeline();
+            while g.step() {}
+            let mut wtr = csv::Writer::from_writer(vec![]);
+            for row in rows.lock().iter() {
+                wtr.serialize(row).unwrap();
+            }
+            wtr.into_inner().unwrap()
+        };
+
+        let recording = run(42);
+        assert!(!recording.is_empty());
+        assert_eq!(recording, run(42));
+        // the seed of the gambler overrides the one of the broker
+        assert_ne!(recording, run(43));
+    }
+
+    #[tokio::test]
+    async fn test_gambler_reset() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let fresh = portfolio.clone();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(FillRecorder::default())
+            .data(vec![build_bar(5.0, 6.0), build_bar(7.0, 8.0)].into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+        g.run().await;
+        assert_eq!(portfolio.lock().positions["test"].qty, 10);
+
+        // the order decided on the last bar must not leak into the next run
+        *portfolio.lock() = fresh;
+        g.reset(vec![build_bar(3.0, 4.0), build_bar(9.0, 10.0)].into_iter());
+        g.run().await;
+
+        let p = portfolio.lock();
+        let fills = &p.positions["test"].stats.transactions;
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 9.0);
+        assert_eq!(p.cash, 10000.0 - 90.0);
+    }
+
+    #[tokio::test]
+    async fn test_skipped_decision() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(1.0)
+            .build()
+            .unwrap();
+
+        let portfolio = Arc::new(Mutex::new(portfolio));
+        let bars = vec![build_bar(5.0, 6.0), build_bar(7.0, 8.0)];
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+
+        let skipped = Arc::new(Mutex::new(vec![]));
+        let skipped_ = Arc::clone(&skipped);
+        g.add_event_hook(move |_, evt| {
+            if let event::Event::DecisionSkipped(d, reason) = evt {
+                skipped_.lock().push((d.kind, *reason));
+            }
+        });
+        g.run().await;
+
+        let skipped = skipped.lock();
+        assert!(matches!(
+            skipped[0],
+            (strategy::DecisionKind::Buy, event::SkipReason::Unfilled)
+        ));
+        assert_eq!(g.skipped_decisions().unfilled, 1);
+        assert_eq!(g.skipped_decisions().total(), skipped.len());
+        assert_eq!(portfolio.lock().cash, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_timestamp() {
+        use gambler::TimestampPolicy;
+
+        let bar = build_bar(5.0, 6.0);
+        for (policy, expected) in [
+            (TimestampPolicy::Keep, 3),
+            (TimestampPolicy::Skip, 2),
+            (TimestampPolicy::Merge, 2),
+        ] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager::default())
+                .cash(10000.0)
+                .build()
+                .unwrap();
+
+            let bars = vec![bar.clone(), bar.clone(), build_bar(7.0, 8.0)];
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(TestStrategy { idx: 0 })
+                .data(bars.into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .timestamp_policy(policy)
+                .build()
+                .unwrap();
+
+            let n = Arc::new(Mutex::new(0));
+            let n_ = Arc::clone(&n);
+            g.add_event_hook(move |_, evt| {
+                if let event::Event::Market(_) = evt {
+                    *n_.lock() += 1;
+                }
+            });
+            g.run().await;
+            assert_eq!(*n.lock(), expected);
+        }
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "out-of-order timestamp")]
+    async fn test_out_of_order_data() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager::default())
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        // ascending at first, so not mistaken for newest-first data
+        let start = chrono::Utc::now();
+        let bars = [0, 2, 1].map(|i| Bar {
+            time: start + chrono::Duration::days(i),
+            ..build_bar(5.0, 6.0)
+        });
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+        g.run().await;
+    }
+
+    #[test]
+    fn test_bad_rows() {
+        use data::BadRowPolicy;
+
+        for (policy, expected) in [
+            (BadRowPolicy::Skip, vec![6.0, 8.0]),
+            (BadRowPolicy::Stop, vec![6.0]),
+        ] {
+            let mut g = build_fallible_gambler(policy);
+            let closes = Arc::new(Mutex::new(vec![]));
+            let sink = Arc::clone(&closes);
+            g.add_event_hook(move |_, evt| {
+                if let event::Event::Market(bar) = evt {
+                    sink.lock().push(bar.close);
+                }
+            });
+            while g.step() {}
+            assert_eq!(*closes.lock(), expected);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use more_asserts::*;
-    use std::sync::Arc;
+    #[test]
+    fn test_bad_rows_fail() {
+        let mut g = build_fallible_gambler(data::BadRowPolicy::Fail);
+        assert!(g.data_error().is_none());
+        let mut bars = 0;
+        while g.step() {
+            bars += 1;
+        }
 
-    use parking_lot::Mutex;
+        // the data ends at the bad row with its error
+        assert_eq!(bars, 1);
+        assert!(matches!(
+            g.data_error(),
+            Some(errors::ErrorRepr::InvalidData(x)) if x == "bad row"
+        ));
+    }
+
+    /// a gambler over data failing to load its second row
+    fn build_fallible_gambler(
+        policy: data::BadRowPolicy,
+    ) -> gambler::Gambler<
+        TestStrategy,
+        data::Pipeline,
+        broker::SimulatedBroker,
+        portfolio::SimplePortfolio<order::FixedSizeOrderManager>,
+    > {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager::default())
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let start = chrono::Utc::now();
+        let rows = vec![
+            Ok(build_bar(5.0, 6.0)),
+            Err("bad row"),
+            Ok(Bar {
+                time: start + chrono::Duration::days(1),
+                ..build_bar(7.0, 8.0)
+            }),
+        ];
+        gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .fallible_data(rows.into_iter(), policy)
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap()
+    }
 
-    use crate::{
-        data::Bar, gambler::Casino, portfolio::Statistics,
-        strategy::DecisionMaker,
-    };
+    #[test]
+    #[should_panic(expected = "descending time order")]
+    fn test_descending_data() {
+        let mut g = build_reverse_gambler(false);
+        while g.step() {}
+    }
 
-    use super::*;
+    #[test]
+    fn test_reverse() {
+        let mut g = build_reverse_gambler(true);
+        let times = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&times);
+        g.add_event_hook(move |_, evt| {
+            if let event::Event::Market(bar) = evt {
+                sink.lock().push(bar.time);
+            }
+        });
+        while g.step() {}
 
-    #[derive(Clone)]
-    struct TestStrategy {
-        idx: i32,
+        let times = times.lock();
+        assert_eq!(times.len(), 3);
+        assert!(times.windows(2).all(|x| x[0] < x[1]));
     }
 
-    impl DecisionMaker for TestStrategy {
+    /// a gambler over newest-first data
+    fn build_reverse_gambler(
+        reverse: bool,
+    ) -> gambler::Gambler<
+        TestStrategy,
+        std::vec::IntoIter<Bar>,
+        broker::SimulatedBroker,
+        portfolio::SimplePortfolio<order::FixedSizeOrderManager>,
+    > {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager::default())
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let now = chrono::Utc::now();
+        let bars = (0..3)
+            .map(|i| Bar {
+                time: now - chrono::Duration::days(i),
+                ..build_bar(5.0, 5.0)
+            })
+            .collect::<Vec<_>>();
+        gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .reverse(reverse)
+            .build()
+            .unwrap()
+    }
+
+    #[derive(Clone, Default)]
+    struct FillRecorder {
+        fills: Arc<Mutex<Vec<order::Fill>>>,
+        orders: Arc<Mutex<Vec<order::Order>>>,
+    }
+
+    impl DecisionMaker for FillRecorder {
         fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
-            self.idx += 1;
             strategy::Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
                 time: data.time,
                 sym: data.sym.clone(),
-                kind: if self.idx % 2 == 1 {
-                    strategy::DecisionKind::Buy
-                } else {
-                    strategy::DecisionKind::Sell
-                },
+                kind: strategy::DecisionKind::Buy,
+            }
+        }
+
+        fn on_fill(&mut self, fill: &order::Fill) {
+            self.fills.lock().push(fill.clone());
+        }
+
+        fn on_order(&mut self, ord: &order::Order) {
+            self.orders.lock().push(ord.clone());
+        }
+    }
+
+    #[derive(Clone)]
+    struct LimitOrderManager(order::TimeInForce);
+
+    impl order::OrderManager for LimitOrderManager {
+        fn make_order(
+            &mut self,
+            decision: &strategy::Decision,
+            _: &portfolio::PortfolioView,
+        ) -> Result<Option<order::Order>, errors::ErrorRepr> {
+            let ord = order::OrderBuilder::default()
+                .sym(decision.sym.clone())
+                .time(decision.time)
+                .qty(10)
+                .kind(order::OrderKind::Limit {
+                    limit: 5.0,
+                    stop: None,
+                    post_only: false,
+                    tif: self.0,
+                })
+                .build()
+                .unwrap();
+            Ok(Some(ord))
+        }
+    }
+
+    /// `LimitOrderManager` making no order on hold
+    #[derive(Clone)]
+    struct EntryLimitOrderManager(LimitOrderManager);
+
+    impl order::OrderManager for EntryLimitOrderManager {
+        fn make_order(
+            &mut self,
+            decision: &strategy::Decision,
+            view: &portfolio::PortfolioView,
+        ) -> Result<Option<order::Order>, errors::ErrorRepr> {
+            if matches!(decision.kind, strategy::DecisionKind::Hold) {
+                return Ok(None);
             }
+            self.0.make_order(decision, view)
         }
     }
 
     #[tokio::test]
-    async fn test_gambler() {
+    async fn test_rest_remainders() {
+        for (rest_remainders, expected) in [(false, vec![4]), (true, vec![4, 4, 2])] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(EntryLimitOrderManager(LimitOrderManager(
+                    order::TimeInForce::GTC,
+                )))
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            // at most 4 can be bought on a bar
+            let bars = (0..4)
+                .map(|_| Bar {
+                    vol: 4.0,
+                    ..build_bar(4.0, 4.0)
+                })
+                .collect::<Vec<_>>();
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(ScriptedStrategy([strategy::DecisionKind::Buy].into()))
+                .data(bars.into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .rest_remainders(rest_remainders)
+                .build()
+                .unwrap();
+            let fills = Arc::new(Mutex::new(vec![]));
+            let sink = Arc::clone(&fills);
+            g.add_event_hook(move |_, evt| {
+                if let event::Event::Fill(fill) = evt {
+                    sink.lock().push(fill.qty.0);
+                }
+            });
+            g.run().await;
+
+            assert_eq!(*fills.lock(), expected);
+            assert_eq!(
+                portfolio.lock().positions["test"].qty,
+                expected.iter().sum::<i32>()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commission_on_partials() {
+        for (on_partials, costs) in [(true, vec![5.0, 5.0, 5.0]), (false, vec![5.0, 0.04, 0.02])] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(EntryLimitOrderManager(LimitOrderManager(
+                    order::TimeInForce::GTC,
+                )))
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            // the 10 bought on the first bar fill 4, 4 then 2 at a time
+            let bars = (0..4)
+                .map(|_| Bar {
+                    vol: 4.0,
+                    ..build_bar(4.0, 4.0)
+                })
+                .collect::<Vec<_>>();
+            let broker = broker::SimulatedBrokerBuilder::default()
+                .commission(broker::Commission::PerShare {
+                    rate: 0.01,
+                    min: 5.0,
+                    max_pct: 1.0,
+                })
+                .commission_on_partials(on_partials)
+                .build()
+                .unwrap();
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(ScriptedStrategy([strategy::DecisionKind::Buy].into()))
+                .data(bars.into_iter())
+                .broker(broker)
+                .portfolio(Arc::new(Mutex::new(portfolio)))
+                .rest_remainders(true)
+                .build()
+                .unwrap();
+            let fills = Arc::new(Mutex::new(vec![]));
+            let sink = Arc::clone(&fills);
+            g.add_event_hook(move |_, evt| {
+                if let event::Event::Fill(fill) = evt {
+                    sink.lock().push(fill.clone());
+                }
+            });
+            g.run().await;
+
+            let fills = fills.lock();
+            assert_eq!(fills.iter().map(|x| x.qty.0).collect::<Vec<_>>(), [4, 4, 2]);
+            let cost = fills.iter().map(|x| x.cost).collect::<Vec<_>>();
+            for (a, b) in cost.iter().zip(&costs) {
+                assert!((a - b).abs() < 1e-9, "{:?} != {:?}", cost, costs);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broker_stats() {
         let portfolio = portfolio::SimplePortfolioBuilder::default()
-            .order_manager(order::FixedSizeOrderManager { size: 100 })
+            .order_manager(LimitOrderManager(order::TimeInForce::IOC))
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        // the limit is out of reach on the second bar, the third has only 5 shares
+        let bars = vec![
+            build_bar(10.0, 10.0),
+            build_bar(10.0, 10.0),
+            Bar {
+                vol: 5.0,
+                ..build_bar(4.0, 4.0)
+            },
+        ];
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(FillRecorder::default())
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        let stats = g.broker_stats();
+        assert_eq!(stats.partial, 1);
+        assert_eq!(stats.canceled, 1);
+        assert_eq!(stats.filled + stats.rejected + stats.deferred, 0);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_fills() {
+        for aggregate_fills in [false, true] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(LimitOrderManager(order::TimeInForce::GTC))
+                .cash(10000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+
+            // the limit is only reached on the last bar, where both resting orders partially
+            // fill on the same side and must still be reported apart
+            let bars = vec![
+                build_bar(10.0, 10.0),
+                build_bar(10.0, 10.0),
+                build_bar(4.0, 4.0),
+            ];
+            let strategy = FillRecorder::default();
+            let fills = Arc::clone(&strategy.fills);
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(strategy)
+                .data(bars.into_iter())
+                .broker(
+                    broker::SimulatedBrokerBuilder::default()
+                        .limit_participation(Some(0.0006))
+                        .build()
+                        .unwrap(),
+                )
+                .portfolio(Arc::clone(&portfolio))
+                .aggregate_fills(aggregate_fills)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            let fills = fills
+                .lock()
+                .iter()
+                .map(|x| (x.order_id, x.qty))
+                .collect::<Vec<_>>();
+            assert_eq!(fills, vec![(2, units::Qty(6)), (1, units::Qty(6))]);
+            assert_eq!(portfolio.lock().positions["test"].qty, 12);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_order_stats() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(LimitOrderManager(order::TimeInForce::GTC))
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let bars = (0..5).map(|_| build_bar(10.0, 10.0)).collect::<Vec<_>>();
+
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(FillRecorder::default())
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        // a resting limit order is left behind on every bar but the first
+        let stats = g.open_order_stats();
+        assert_eq!(stats.max, 4);
+        assert_eq!(stats.avg(), 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_order_history() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(LimitOrderManager(order::TimeInForce::GTC))
             .cash(10000.0)
             .build()
             .unwrap();
 
-        let portfolio = Arc::new(Mutex::new(portfolio));
         let bars = vec![
-            build_bar(5.0, 6.0),
-            build_bar(7.0, 8.0),
-            build_bar(1.0, 2.0),
-            build_bar(1.0, 2.0),
+            build_bar(10.0, 10.0),
+            build_bar(10.0, 10.0),
+            build_bar(10.0, 10.0),
+            build_bar(4.0, 4.0),
         ];
+        let strategy = FillRecorder::default();
+        let orders = Arc::clone(&strategy.orders);
 
         let mut g = gambler::GamblerBuilder::default()
             .sym("test")
-            .strategy(TestStrategy { idx: 0 })
+            .strategy(strategy)
             .data(bars.into_iter())
-            .broker(
-                broker::SimulatedBrokerBuilder::default()
-                    .commission(0.001)
-                    .build()
-                    .unwrap(),
-            )
-            .portfolio(Arc::clone(&portfolio))
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::new(Mutex::new(portfolio)))
             .build()
             .unwrap();
-        g.add_event_hook(|s, evt| println!(">>> ({}) event: {:?}", s, evt));
         g.run().await;
 
-        let p = portfolio.lock();
-        assert_eq!(p.init_cash, 10_000.0);
-        assert_le!(
-            (10_000.0 - 700.0 + 100.0 - 100.0 - 0.9 - p.cash).abs(),
-            0.01
+        // the first order rests on bar 2 and 3 and fills on bar 4
+        let orders = orders.lock();
+        let ord = orders
+            .iter()
+            .filter(|x| matches!(x.status, order::OrderStatus::Completed))
+            .max_by_key(|x| x.history.len())
+            .unwrap();
+        let history = ord.history.iter().map(|x| x.1.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            vec![
+                "deferred: limit order not met",
+                "deferred: limit order not met",
+                "filled"
+            ]
         );
     }
 
     #[tokio::test]
-    async fn test_casino() {
+    async fn test_day_order_expired() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(LimitOrderManager(order::TimeInForce::Day))
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+
+        let bars = (1..=3)
+            .map(|d| Bar {
+                time: format!("2023-02-0{} 00:00:00Z", d).parse().unwrap(),
+                ..build_bar(10.0, 10.0)
+            })
+            .collect::<Vec<_>>();
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(FillRecorder::default())
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        // the first order is tried on day 2 and expires on day 3
+        assert_eq!(g.skipped_decisions().expired, 1);
+        assert_eq!(portfolio.lock().cash, 10000.0);
+    }
+
+    #[derive(Clone)]
+    struct CooldownOrderManager;
+
+    impl order::OrderManager for CooldownOrderManager {
+        fn make_order(
+            &mut self,
+            _: &strategy::Decision,
+            _: &portfolio::PortfolioView,
+        ) -> Result<Option<order::Order>, errors::ErrorRepr> {
+            Err(errors::ErrorRepr::Strategy("cooldown".into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strategy_error() {
         let portfolio = portfolio::SimplePortfolioBuilder::default()
-            .order_manager(order::FixedSizeOrderManager { size: 100 })
+            .order_manager(CooldownOrderManager)
             .cash(10000.0)
             .build()
             .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+
+        let bars = vec![build_bar(5.0, 6.0), build_bar(7.0, 8.0)];
+        let mut g = gambler::GamblerBuilder::default()
+            .sym("test")
+            .strategy(TestStrategy { idx: 0 })
+            .data(bars.into_iter())
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+            .portfolio(Arc::clone(&portfolio))
+            .build()
+            .unwrap();
+        g.run().await;
+
+        assert_eq!(g.skipped_decisions().no_order, 2);
+        assert_eq!(portfolio.lock().cash, 10000.0);
+    }
+
+    #[derive(Clone)]
+    struct VetoStrategy(TestStrategy);
+
+    impl DecisionMaker for VetoStrategy {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            self.0.make_decision(data)
+        }
 
+        fn on_order_pending(&mut self, _: &mut order::Order) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn test_veto_order() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
         let portfolio = Arc::new(Mutex::new(portfolio));
+
         let bars = vec![
             build_bar(5.0, 6.0),
             build_bar(7.0, 8.0),
             build_bar(1.0, 2.0),
-            build_bar(1.0, 2.0),
         ];
-
         let mut g = gambler::GamblerBuilder::default()
             .sym("test")
-            .strategy(TestStrategy { idx: 0 })
+            .strategy(VetoStrategy(TestStrategy { idx: 0 }))
             .data(bars.into_iter())
-            .broker(
-                broker::SimulatedBrokerBuilder::default()
-                    .commission(0.001)
-                    .build()
-                    .unwrap(),
-            )
+            .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
             .portfolio(Arc::clone(&portfolio))
             .build()
             .unwrap();
+        g.run().await;
 
-        g.add_event_hook(|s, evt| println!(">>> ({}) event: {:?}", s, evt));
+        // the buys are vetoed, the sells have nothing to sell
+        assert_eq!(g.skipped_decisions().vetoed, 2);
+        let p = portfolio.lock();
+        assert_eq!(p.cash, 10000.0);
+        assert!(p.positions["test"].stats.transactions.is_empty());
+    }
 
-        let mut casino = Casino::new(vec![g]);
-        casino.run().await;
+    #[tokio::test]
+    async fn test_same_bar_fill() {
+        for (same_bar_fill, expected) in [(false, 7.0), (true, 5.0)] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 100,
+                    ..Default::default()
+                })
+                .cash(10000.0)
+                .build()
+                .unwrap();
 
-        let p = portfolio.lock();
-        assert_eq!(p.init_cash, 10_000.0);
-        assert_le!(
-            (10_000.0 - 700.0 + 100.0 - 100.0 - 0.9 - p.cash).abs(),
-            0.01
-        );
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            let bars = vec![build_bar(5.0, 6.0), build_bar(7.0, 8.0)];
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(TestStrategy { idx: 0 })
+                .data(bars.into_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .same_bar_fill(same_bar_fill)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            let p = portfolio.lock();
+            let fill = &p.positions["test"].stats.transactions[0];
+            assert_eq!(fill.qty, 100);
+            assert_eq!(fill.price, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_casino_ordered_equity_curve() {
+        let portfolio = portfolio::SimplePortfolioBuilder::default()
+            .order_manager(order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .cash(10000.0)
+            .build()
+            .unwrap();
+        let portfolio = Arc::new(Mutex::new(portfolio));
+
+        let gamblers = ["a", "b"]
+            .into_iter()
+            .map(|sym| {
+                let bars = (1..=3)
+                    .map(|d| Bar {
+                        sym: sym.into(),
+                        time: format!("2023-02-0{} 00:00:00Z", d).parse().unwrap(),
+                        ..build_bar(5.0, 6.0)
+                    })
+                    .collect::<Vec<_>>();
+                gambler::GamblerBuilder::default()
+                    .sym(sym)
+                    .strategy(TestStrategy { idx: 0 })
+                    .data(bars.into_iter())
+                    .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                    .portfolio(Arc::clone(&portfolio))
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+
+        let mut casino = Casino::new(gamblers);
+        casino.run_ordered().await;
+
+        let stats = portfolio.lock().stats();
+        assert_eq!(stats.equity_curve.len(), 3);
+        // both symbols bought 100 at 5 and marked at 6
+        let (_, equity) = stats.equity_curve[1];
+        assert_eq!(equity, 10000.0 + 2.0 * 100.0 * (6.0 - 5.0));
+    }
+
+    /// buys when the close rises and sells when it falls
+    #[derive(Clone, Default)]
+    struct MomentumStrategy {
+        prev_close: Option<f64>,
+        calls: Arc<Mutex<usize>>,
+    }
+
+    fn momentum(bar: &Bar, prev_close: Option<f64>) -> strategy::Decision {
+        use strategy::DecisionKind::*;
+        strategy::Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: bar.time,
+            sym: bar.sym.clone(),
+            kind: match prev_close {
+                Some(prev) if bar.close > prev => Buy,
+                Some(prev) if bar.close < prev => Sell,
+                _ => Hold,
+            },
+        }
+    }
+
+    impl DecisionMaker for MomentumStrategy {
+        fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
+            *self.calls.lock() += 1;
+            let d = momentum(data, self.prev_close);
+            self.prev_close = Some(data.close);
+            d
+        }
+
+        fn precompute(&self, bars: &[Bar]) -> Option<Vec<strategy::Decision>> {
+            let prev = std::iter::once(None).chain(bars.iter().map(|x| Some(x.close)));
+            Some(
+                bars.iter()
+                    .zip(prev)
+                    .map(|(bar, p)| momentum(bar, p))
+                    .collect(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_precompute() {
+        let mut results = vec![];
+        for precompute in [false, true] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(100000.0)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+            let strategy = MomentumStrategy::default();
+            let calls = Arc::clone(&strategy.calls);
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(strategy)
+                .data(data::tests::test_data_iter().take(200))
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .precompute(precompute)
+                .build()
+                .unwrap();
+            g.run().await;
+
+            assert_eq!(*calls.lock(), if precompute { 0 } else { 200 });
+            let p = portfolio.lock();
+            results.push((p.cash, p.open_positions()));
+        }
+        assert_ne!(results[0].0, 100000.0);
+        assert_eq!(results[0], results[1]);
+
+        // a casino precomputes its gamblers on the blocking pool
+        let strategy = MomentumStrategy::default();
+        let calls = Arc::clone(&strategy.calls);
+        let portfolios = [(); 3].map(|_| {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .cash(100000.0)
+                .build()
+                .unwrap();
+            Arc::new(Mutex::new(portfolio))
+        });
+        let gamblers = portfolios.clone().map(|portfolio| {
+            gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(strategy.clone())
+                .data(data::tests::test_data_iter().take(200))
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(portfolio)
+                .precompute(true)
+                .build()
+                .unwrap()
+        });
+        Casino::new(gamblers.into()).run_ordered().await;
+        assert_eq!(*calls.lock(), 0);
+        for p in portfolios {
+            let p = p.lock();
+            assert_eq!((p.cash, p.open_positions()), results[0]);
+        }
     }
 
     #[derive(Clone, Default, Debug)]
@@ -137,6 +2274,10 @@ mod tests {
     impl DecisionMaker for TestStrategy2 {
         fn make_decision(&mut self, data: &Bar) -> strategy::Decision {
             let mut d = strategy::Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
                 time: data.time,
                 sym: data.sym.clone(),
                 kind: strategy::DecisionKind::Hold,
@@ -148,13 +2289,12 @@ mod tests {
 
             if self.qty == 0 {
                 let n = self.prev_close.len();
-                if n >= 3 {
-                    if self.prev_close[n - 2] > self.prev_close[n - 1]
-                        && self.prev_close[n - 3] > self.prev_close[n - 2]
-                    {
-                        d.kind = strategy::DecisionKind::Buy;
-                        println!("BUY created, close: {:.2}, debug: {:?}\n", data.close, self);
-                    }
+                if n >= 3
+                    && self.prev_close[n - 2] > self.prev_close[n - 1]
+                    && self.prev_close[n - 3] > self.prev_close[n - 2]
+                {
+                    d.kind = strategy::DecisionKind::Buy;
+                    println!("BUY created, close: {:.2}, debug: {:?}\n", data.close, self);
                 }
             } else {
                 if self.idx >= self.bar_executed + 5 {
@@ -187,7 +2327,7 @@ mod tests {
 
         fn on_fill(&mut self, fill: &order::Fill) {
             self.bar_executed = self.idx;
-            self.qty += fill.qty;
+            self.qty += fill.qty.0;
             if fill.qty > 0 {
                 println!("BUY executed, fill: {0:.2}", fill.price);
             } else {
@@ -199,18 +2339,19 @@ mod tests {
     #[tokio::test]
     async fn test_real_data() {
         let portfolio = portfolio::SimplePortfolioBuilder::default()
-            .order_manager(order::FixedSizeOrderManager { size: 1 })
+            .order_manager(order::FixedSizeOrderManager {
+                size: 1,
+                ..Default::default()
+            })
             .cash(100000.0)
             .build()
             .unwrap();
 
         let portfolio = Arc::new(Mutex::new(portfolio));
-        let bars = data::tests::get_test_data();
-
         let mut g = gambler::GamblerBuilder::default()
             .sym("test")
             .strategy(TestStrategy2::default())
-            .data(bars.iter().cloned())
+            .data(data::tests::test_data_iter())
             .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
             .portfolio(Arc::clone(&portfolio))
             .build()
@@ -232,6 +2373,42 @@ mod tests {
         assert_eq!(((stats.init_cash + stats.pnl) * 100.0).round(), 10001968.0);
     }
 
+    #[tokio::test]
+    async fn test_streaming_stats() {
+        let mut results = vec![];
+        for streaming in [false, true] {
+            let portfolio = portfolio::SimplePortfolioBuilder::default()
+                .order_manager(order::FixedSizeOrderManager {
+                    size: 100,
+                    ..Default::default()
+                })
+                .cash(100000.0)
+                .streaming_stats(streaming)
+                .build()
+                .unwrap();
+            let portfolio = Arc::new(Mutex::new(portfolio));
+
+            let mut g = gambler::GamblerBuilder::default()
+                .sym("test")
+                .strategy(TestStrategy2::default())
+                .data(data::tests::test_data_iter())
+                .broker(broker::SimulatedBrokerBuilder::default().build().unwrap())
+                .portfolio(Arc::clone(&portfolio))
+                .build()
+                .unwrap();
+            g.run().await;
+
+            let stats = portfolio.lock().stats();
+            assert_eq!(stats.equity_curve.is_empty(), streaming);
+            results.push((stats.sharpe, stats.max_drawdown));
+        }
+
+        assert_ne!(results[0].0, 0.0);
+        assert_gt!(results[0].1, 0.0);
+        assert_lt!((results[0].0 - results[1].0).abs(), 1e-9);
+        assert_lt!((results[0].1 - results[1].1).abs(), 1e-12);
+    }
+
     fn build_bar(open: f64, close: f64) -> Bar {
         Bar {
             sym: "test".into(),
@@ -241,6 +2418,7 @@ mod tests {
             high: 0.0,
             low: 0.0,
             vol: 10000.0,
+            dividend: 0.0,
         }
     }
 }
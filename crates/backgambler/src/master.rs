@@ -0,0 +1,110 @@
+//! a read-only roll-up of several portfolios, e.g. one per strategy, each still traded
+//! by its own gamblers
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    data::Symbol,
+    portfolio::{PortfolioStats, SimplePortfolio, Statistics},
+    units::Qty,
+};
+
+pub struct MasterPortfolio<T> {
+    pub parts: Vec<Arc<Mutex<SimplePortfolio<T>>>>,
+}
+
+impl<T> MasterPortfolio<T> {
+    pub fn new(parts: Vec<Arc<Mutex<SimplePortfolio<T>>>>) -> Self {
+        Self { parts }
+    }
+
+    /// cash of all the parts
+    pub fn cash(&self) -> f64 {
+        self.parts.iter().map(|x| x.lock().cash).sum()
+    }
+
+    /// equity of all the parts
+    pub fn equity(&self) -> f64 {
+        self.parts.iter().map(|x| x.lock().equity()).sum()
+    }
+
+    /// quantity held of every symbol, longs and shorts of different parts netted out.
+    /// symbols netting to zero are left out
+    pub fn net_positions(&self) -> HashMap<Symbol, Qty> {
+        let mut net = HashMap::<Symbol, Qty>::new();
+        for part in &self.parts {
+            for (sym, pos) in &part.lock().positions {
+                *net.entry(sym.clone()).or_default() += pos.qty;
+            }
+        }
+        net.retain(|_, qty| !qty.is_zero());
+        net
+    }
+}
+
+impl<T> Statistics for MasterPortfolio<T> {
+    type Stats = PortfolioStats;
+
+    /// see `PortfolioStats::combine`
+    fn stats(&self) -> PortfolioStats {
+        PortfolioStats::combine(self.parts.iter().map(|x| x.lock().stats()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        order::{Fill, FixedSizeOrderManager},
+        portfolio::{PositionManager, SimplePortfolioBuilder},
+        units::Price,
+    };
+
+    #[test]
+    fn test_net_positions() {
+        let build = |allow_short| {
+            SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .allow_short(allow_short)
+                .order_manager(FixedSizeOrderManager::default())
+                .build()
+                .unwrap()
+        };
+        let fill = |sym: &str, qty| Fill {
+            order_id: 0,
+            risk: None,
+            time: chrono::Utc::now(),
+            qty: Qty(qty),
+            sym: sym.into(),
+            price: Price(10.0),
+            cost: 0.0,
+        };
+        let mut long = build(false);
+        long.update_from_fill(&fill("test", 30)).unwrap();
+        long.update_from_fill(&fill("other", 5)).unwrap();
+        let mut short = build(true);
+        short.update_from_fill(&fill("test", -10)).unwrap();
+        short.update_from_fill(&fill("other", -5)).unwrap();
+
+        let master = MasterPortfolio::new(vec![
+            Arc::new(Mutex::new(long)),
+            Arc::new(Mutex::new(short)),
+        ]);
+        assert_eq!(
+            master.net_positions(),
+            HashMap::from([("test".into(), Qty(20))])
+        );
+        assert_eq!(master.cash(), 2000.0);
+
+        let stats = master.stats();
+        assert_eq!(stats.init_cash, 2000.0);
+        assert_eq!(stats.cash, master.cash());
+
+        // each part still trades on its own
+        master.parts[0]
+            .lock()
+            .update_from_fill(&fill("test", -30))
+            .unwrap();
+        assert_eq!(master.net_positions()["test"], Qty(-10));
+    }
+}
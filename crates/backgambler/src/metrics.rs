@@ -0,0 +1,321 @@
+use crate::data::DateTime;
+
+pub const SECS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0;
+
+/// simple returns between consecutive values
+pub fn returns(curve: &[f64]) -> Vec<f64> {
+    curve.windows(2).map(|w| w[1] / w[0] - 1.0).collect()
+}
+
+pub fn mean(xs: &[f64]) -> f64 {
+    if xs.is_empty() {
+        return 0.0;
+    }
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// sample standard deviation
+pub fn std(xs: &[f64]) -> f64 {
+    if xs.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(xs);
+    let var = xs.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (xs.len() - 1) as f64;
+    var.sqrt()
+}
+
+/// per-period sharpe ratio of a return series, 0 if it has no variance
+pub fn sharpe(rets: &[f64]) -> f64 {
+    let sd = std(rets);
+    if sd == 0.0 {
+        0.0
+    } else {
+        mean(rets) / sd
+    }
+}
+
+/// sensitivity of `rets` to `bench`, the covariance of the two return series over the
+/// variance of the benchmark's. 0 if the benchmark has no variance
+pub fn beta(rets: &[f64], bench: &[f64]) -> f64 {
+    let n = rets.len().min(bench.len());
+    if n < 2 {
+        return 0.0;
+    }
+    let (rets, bench) = (&rets[..n], &bench[..n]);
+    let (m, mb) = (mean(rets), mean(bench));
+    let cov = rets
+        .iter()
+        .zip(bench)
+        .map(|(x, b)| (x - m) * (b - mb))
+        .sum::<f64>();
+    let var = bench.iter().map(|b| (b - mb).powi(2)).sum::<f64>();
+    if var == 0.0 {
+        0.0
+    } else {
+        cov / var
+    }
+}
+
+/// annualized volatility of the returns of a timed curve. plain, every return is one
+/// period and the periods per year are observed from the span of the curve. time
+/// weighted, the variance is taken per calendar year elapsed between the points, so
+/// that a return over a weekend counts for three days. 0 without a span
+pub fn annualized_volatility(curve: &[(DateTime, f64)], time_weighted: bool) -> f64 {
+    let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+        return 0.0;
+    };
+    let span = (last.0 - first.0).num_seconds() as f64 / SECS_PER_YEAR;
+    if span <= 0.0 {
+        return 0.0;
+    }
+    let values = curve.iter().map(|x| x.1).collect::<Vec<_>>();
+    let rets = returns(&values);
+    if !time_weighted {
+        return std(&rets) * (rets.len() as f64 / span).sqrt();
+    }
+    let drift = rets.iter().sum::<f64>() / span;
+    let var = curve
+        .windows(2)
+        .zip(&rets)
+        .map(|(w, r)| {
+            let dt = (w[1].0 - w[0].0).num_seconds() as f64 / SECS_PER_YEAR;
+            (r - drift * dt).powi(2)
+        })
+        .sum::<f64>()
+        / span;
+    var.sqrt()
+}
+
+/// largest decline from a running peak, as a ratio of the peak
+pub fn max_drawdown(curve: &[f64]) -> f64 {
+    let mut stats = StreamingStats::default();
+    curve.iter().for_each(|x| stats.push(*x));
+    stats.max_drawdown()
+}
+
+/// largest decline from a running peak, in currency
+pub fn max_drawdown_abs(curve: &[f64]) -> f64 {
+    let mut stats = StreamingStats::default();
+    curve.iter().for_each(|x| stats.push(*x));
+    stats.max_drawdown_abs()
+}
+
+/// root mean square of the decline from the running peak at every point, as ratios of
+/// the peak. 0 for an empty curve
+pub fn ulcer_index(curve: &[f64]) -> f64 {
+    let mut peak = f64::MIN;
+    let drawdowns = curve
+        .iter()
+        .map(|x| {
+            peak = peak.max(*x);
+            (1.0 - x / peak).powi(2)
+        })
+        .collect::<Vec<_>>();
+    mean(&drawdowns).sqrt()
+}
+
+/// stretches of a curve below a prior peak
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// points from a peak to the first one back at it, longest of the recovered drawdowns
+    pub bars: usize,
+    /// points from the last peak to the end of the curve if it is still below it, 0 if
+    /// not in a drawdown
+    pub underwater: usize,
+}
+
+/// longest time to recover from a drawdown, see `Recovery`
+pub fn max_recovery(curve: &[f64]) -> Recovery {
+    let mut res = Recovery::default();
+    let mut peak = 0;
+    let mut below = false;
+    for (i, x) in curve.iter().enumerate() {
+        if *x < curve[peak] {
+            below = true;
+        } else {
+            // a new high without a drawdown before it recovers nothing
+            if below {
+                res.bars = res.bars.max(i - peak);
+            }
+            peak = i;
+            below = false;
+        }
+    }
+    if below {
+        res.underwater = curve.len() - 1 - peak;
+    }
+    res
+}
+
+/// sharpe and drawdown of an equity curve in constant memory, using Welford's algorithm
+/// for the variance of the returns
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamingStats {
+    n: usize,
+    mean: f64,
+    m2: f64,
+    last: Option<f64>,
+    peak: f64,
+    max_drawdown: f64,
+    max_drawdown_abs: f64,
+}
+
+impl StreamingStats {
+    pub fn push(&mut self, equity: f64) {
+        self.push_excess(equity, 0.0);
+    }
+
+    /// push an equity point whose return is taken in excess of the period `risk_free` rate
+    pub fn push_excess(&mut self, equity: f64, risk_free: f64) {
+        if let Some(prev) = self.last {
+            let ret = equity / prev - 1.0 - risk_free;
+            self.n += 1;
+            let delta = ret - self.mean;
+            self.mean += delta / self.n as f64;
+            self.m2 += delta * (ret - self.mean);
+        }
+        self.last = Some(equity);
+        self.peak = self.peak.max(equity);
+        self.max_drawdown_abs = self.max_drawdown_abs.max(self.peak - equity);
+        if self.peak > 0.0 {
+            self.max_drawdown = self.max_drawdown.max(1.0 - equity / self.peak);
+        }
+    }
+
+    /// per-period sharpe ratio of the excess returns, 0 if they have no variance
+    pub fn sharpe(&self) -> f64 {
+        if self.n < 2 {
+            return 0.0;
+        }
+        let sd = (self.m2 / (self.n - 1) as f64).sqrt();
+        if sd == 0.0 {
+            0.0
+        } else {
+            self.mean / sd
+        }
+    }
+
+    pub fn max_drawdown(&self) -> f64 {
+        self.max_drawdown
+    }
+
+    pub fn max_drawdown_abs(&self) -> f64 {
+        self.max_drawdown_abs
+    }
+}
+
+/// apply `f` to every window of `window` values, empty if the series is shorter
+pub fn rolling<T>(curve: &[f64], window: usize, f: impl Fn(&[f64]) -> T) -> Vec<T> {
+    if window == 0 {
+        return vec![];
+    }
+    curve.windows(window).map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_std() {
+        assert_eq!(mean(&[1.0, 2.0, 3.0]), 2.0);
+        assert_eq!(std(&[1.0, 2.0, 3.0]), 1.0);
+        assert_eq!(std(&[1.0]), 0.0);
+        assert_eq!(sharpe(&[0.1, 0.1]), 0.0);
+    }
+
+    #[test]
+    fn test_ulcer_index() {
+        assert_eq!(ulcer_index(&[]), 0.0);
+        assert_eq!(ulcer_index(&[100.0, 110.0, 120.0]), 0.0);
+        // drawdowns of 0, 10%, 20%, 0 and 10%
+        let ui = ulcer_index(&[100.0, 90.0, 80.0, 100.0, 90.0]);
+        assert!((ui - (0.06f64 / 5.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_max_recovery() {
+        assert_eq!(max_recovery(&[]), Recovery::default());
+        // never below a peak
+        assert_eq!(max_recovery(&[1.0, 2.0, 3.0]), Recovery::default());
+
+        // drops after the peak at 1 and is back at it 10 points later
+        let mut curve = vec![100.0, 110.0];
+        curve.extend((0..9).map(|i| 100.0 - i as f64));
+        curve.extend([110.0, 120.0, 115.0]);
+        assert_eq!(
+            max_recovery(&curve),
+            Recovery {
+                bars: 10,
+                underwater: 1
+            }
+        );
+
+        // a shorter drawdown still going on
+        curve.extend([100.0; 2]);
+        assert_eq!(
+            max_recovery(&curve),
+            Recovery {
+                bars: 10,
+                underwater: 3
+            }
+        );
+
+        curve.extend([100.0; 10]);
+        assert_eq!(
+            max_recovery(&curve),
+            Recovery {
+                bars: 10,
+                underwater: 13
+            }
+        );
+    }
+
+    #[test]
+    fn test_annualized_volatility() {
+        // a curve alternating up and down by 1% per calendar day
+        let curve = |days: &[i64]| {
+            let start = chrono::Utc::now();
+            let mut equity = 100.0;
+            let mut curve = vec![(start, equity)];
+            for (i, w) in days.windows(2).enumerate() {
+                let ret =
+                    0.01 * ((w[1] - w[0]) as f64).sqrt() * if i % 2 == 0 { 1.0 } else { -1.0 };
+                equity *= 1.0 + ret;
+                curve.push((start + chrono::Duration::days(w[1]), equity));
+            }
+            curve
+        };
+        let daily = curve(&(0..=28).collect::<Vec<_>>());
+        let weekdays = curve(
+            &(0..4)
+                .flat_map(|w| (0..5).map(move |d| w * 7 + d))
+                .chain([28])
+                .collect::<Vec<_>>(),
+        );
+
+        let (a, b) = (
+            annualized_volatility(&daily, true),
+            annualized_volatility(&weekdays, true),
+        );
+        assert!((a - b).abs() < 1e-9);
+        let (a, b) = (
+            annualized_volatility(&daily, false),
+            annualized_volatility(&weekdays, false),
+        );
+        assert!((a - b).abs() > 1e-3);
+    }
+
+    #[test]
+    fn test_streaming_stats() {
+        let curve = [100.0, 110.0, 99.0, 120.0, 90.0, 132.0];
+        let mut stats = StreamingStats::default();
+        curve.iter().for_each(|x| stats.push(*x));
+
+        assert!((stats.sharpe() - sharpe(&returns(&curve))).abs() < 1e-12);
+        assert!((stats.max_drawdown() - 0.25).abs() < 1e-12);
+        assert_eq!(max_drawdown(&curve), stats.max_drawdown());
+        assert_eq!(stats.max_drawdown_abs(), 30.0);
+        assert_eq!(max_drawdown_abs(&curve), stats.max_drawdown_abs());
+    }
+}
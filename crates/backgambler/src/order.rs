@@ -1,24 +1,60 @@
 use derive_builder::Builder;
 use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
 
-use crate::{data::*, errors::ErrorRepr, position::Position, strategy::DecisionKind};
+use crate::{
+    broker::Cost,
+    data::*,
+    errors::ErrorRepr,
+    event::SkipReason,
+    portfolio::PortfolioView,
+    position::Position,
+    strategy::DecisionKind,
+    units::{Price, Qty},
+    TOLERANCE,
+};
 
 use super::strategy::Decision;
 
 #[derive(Debug, Clone, Builder)]
 pub struct Order {
+    /// assigned by the gambler placing the order, unique within its run. 0 until then
+    #[builder(default)]
+    pub id: u64,
     #[builder(default)]
     pub sym: Symbol,
     #[builder(default = "OrderKind::Market")]
     pub kind: OrderKind,
-    #[builder(default)]
-    pub qty: i32,
-    #[builder(default = "chrono::Utc::now()")]
+    #[builder(default, setter(into))]
+    pub qty: Qty,
+    /// when the order was made, e.g. the time of its decision or bar
     pub time: DateTime,
     #[builder(default)]
     pub lifetime: Option<usize>,
     #[builder(default)]
     pub status: OrderStatus,
+    /// execution attempts, e.g. "deferred: limit order not met", "partial: 30/100", "filled"
+    #[builder(default)]
+    pub history: Vec<(DateTime, String)>,
+    /// only execute once the condition on another symbol is met
+    #[builder(default, setter(strip_option))]
+    pub trigger: Option<TriggerCondition>,
+    #[builder(default)]
+    pub timing: OrderTiming,
+    /// quantity filled by earlier partial fills, `qty` being what is left
+    #[builder(default)]
+    pub filled: Qty,
+    /// commission paid by earlier partial fills
+    #[builder(default)]
+    pub cost: f64,
+    /// in `[0, 1]`, how aggressively the order takes liquidity, see
+    /// `broker::UrgencySlippage`. 0 pays no extra slippage
+    #[builder(default)]
+    pub urgency: f64,
+    /// distance from the price of an entry to its stop. its fills carry the loss if
+    /// stopped out, see `Fill::risk`
+    #[builder(default)]
+    pub stop_distance: Option<f64>,
 }
 
 impl Order {
@@ -27,10 +63,64 @@ impl Order {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+/// a condition on the latest close of a symbol
+#[derive(Debug, Clone)]
+pub struct TriggerCondition {
+    pub sym: Symbol,
+    pub comparison: Comparison,
+    pub level: f64,
+}
+
+impl TriggerCondition {
+    pub fn is_met(&self, close: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => close > self.level,
+            Comparison::Below => close < self.level,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum OrderKind {
     Market,
-    Limit { limit: f64, stop: Option<f64> },
+    Limit {
+        limit: f64,
+        stop: Option<f64>,
+        /// reject instead of executing as a taker
+        post_only: bool,
+        tif: TimeInForce,
+    },
+}
+
+/// the part of the bar an order executes in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrderTiming {
+    /// at the broker's reference price
+    #[default]
+    Immediate,
+    /// market on open
+    OnOpen,
+    /// market on close
+    OnClose,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeInForce {
+    /// expire at the end of the date it was first tried on
+    Day,
+    /// never expire
+    #[default]
+    GTC,
+    /// fill what is available now, cancel the rest
+    IOC,
+    /// fill fully or cancel
+    FOK,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -45,108 +135,893 @@ pub enum OrderStatus {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Fill {
+    /// id of the order filled, 0 for a fill from outside of the simulation
+    pub order_id: u64,
     pub sym: Symbol,
-    pub qty: i32,
-    pub price: f64,
+    pub qty: Qty,
+    pub price: Price,
     pub cost: f64,
     pub time: DateTime,
+    /// loss of the quantity filled if stopped out, recorded on the position to express
+    /// the pnl of the trade in multiples of it
+    pub risk: Option<f64>,
+}
+
+/// merge the fills of the same order into one, priced at their average. fills without an
+/// order are kept apart
+pub fn aggregate_fills(fills: Vec<Fill>) -> Vec<Fill> {
+    let mut res: Vec<Fill> = vec![];
+    for fill in fills {
+        match res
+            .iter_mut()
+            .find(|x| fill.order_id != 0 && x.order_id == fill.order_id)
+        {
+            Some(agg) => {
+                let qty = agg.qty + fill.qty;
+                agg.price = (agg.price * agg.qty + fill.price * fill.qty) / qty;
+                agg.qty = qty;
+                agg.cost += fill.cost;
+                agg.time = fill.time;
+                agg.risk = agg.risk.zip(fill.risk).map(|(a, b)| a + b);
+            }
+            None => res.push(fill),
+        }
+    }
+    res
 }
 
 pub trait OrderAllocator {
     fn allocate_order(&mut self, decision: &Decision) -> Result<Option<Order>, ErrorRepr>;
+    /// called when a decision did not turn into a trade
+    fn record_skip(&mut self, _: SkipReason) {}
+    /// a market order closing the position of `sym` at `time`, if any
+    fn flatten(&self, _: &str, _: DateTime) -> Option<Order> {
+        None
+    }
+    /// whether `ord` may be executed, asked before every attempt
+    fn admit(&mut self, _: &Order) -> bool {
+        true
+    }
+    /// the book is being liquidated, the gamblers close their positions
+    fn is_liquidating(&self) -> bool {
+        false
+    }
+    /// an order the portfolio wants at the start of the bar at `time` on its own, e.g. to
+    /// resize the position of `sym` to a volatility target
+    fn rebalance(&mut self, _: &str, _: DateTime) -> Option<Order> {
+        None
+    }
 }
 
 pub trait OrderManager {
     fn make_order(
         &mut self,
         decision: &Decision,
-        position: Option<&Position>,
+        view: &PortfolioView,
     ) -> Result<Option<Order>, ErrorRepr>;
+
+    fn update_from_market(&mut self, _: &Bar) {}
+
+    /// called with every fill booked by the portfolio
+    fn update_from_fill(&mut self, _: &Fill) {}
+}
+
+/// refuse a buy once the position has been added to `max_adds` times since last flat,
+/// 0 for no limit
+fn is_max_adds_reached(max_adds: usize, position: Option<&Position>) -> bool {
+    max_adds > 0 && position.is_some_and(|x| x.entries > max_adds)
 }
 
-#[derive(Clone)]
+/// an order taking the position back to zero from either side, exempt from lot rules
+fn close_order(decision: &Decision, position: Option<&Position>) -> Option<Order> {
+    let current = position.map_or(Qty(0), |x| x.qty);
+    if current.is_zero() {
+        return None;
+    }
+    let ord = OrderBuilder::default()
+        .qty(-current)
+        .time(decision.time)
+        .sym(decision.sym.clone())
+        .build()
+        .unwrap();
+    Some(ord)
+}
+
+/// quantity selling `fraction` of a long position, rounded towards zero. zero unless long
+fn fraction_qty(position: Option<&Position>, fraction: f64) -> Qty {
+    let current = position.map_or(Qty(0), |x| x.qty).max(Qty(0));
+    -Qty::whole(f64::from(current) * fraction.clamp(0.0, 1.0))
+}
+
+/// what to do with an exit decision for a symbol without a position
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoPositionPolicy {
+    /// place no order
+    #[default]
+    Ignore,
+    /// fail with `ErrorRepr::Strategy`, e.g. to catch strategies losing track of
+    /// their holdings
+    Error,
+}
+
+/// what to do with a sell asking for more than the long position held
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OversellPolicy {
+    /// sell the whole holding
+    #[default]
+    Clamp,
+    /// sell the requested quantity, going short for the rest
+    Short,
+    /// place no order
+    Reject,
+}
+
+impl OversellPolicy {
+    /// signed quantity selling `requested` out of `current`, None if rejected
+    pub fn sell_qty(&self, requested: Qty, current: Qty) -> Option<Qty> {
+        let held = current.max(Qty(0));
+        match self {
+            _ if requested <= held => Some(-requested),
+            OversellPolicy::Clamp => Some(-held),
+            OversellPolicy::Short => Some(-requested),
+            OversellPolicy::Reject => {
+                log::debug!("sell of {} exceeds the holding of {}", requested, held);
+                None
+            }
+        }
+    }
+}
+
+/// board lot rules of a market
+#[derive(Debug, Clone, Copy)]
+pub struct LotRules {
+    /// buys are rounded down to a multiple of this. 0 is taken as 1
+    pub buy_lot: i32,
+    /// sells of any size are allowed, otherwise they are rounded down to the lot too
+    pub allow_odd_sell: bool,
+}
+
+impl Default for LotRules {
+    fn default() -> Self {
+        Self {
+            buy_lot: 1,
+            allow_odd_sell: true,
+        }
+    }
+}
+
+impl LotRules {
+    /// A-shares trade in lots of 100 but odd lots can be sold
+    pub fn a_shares() -> Self {
+        Self {
+            buy_lot: 100,
+            allow_odd_sell: true,
+        }
+    }
+
+    pub fn round(&self, qty: Qty) -> Qty {
+        if qty < 0 && self.allow_odd_sell {
+            qty
+        } else {
+            Qty(qty.0 - qty.0 % self.buy_lot.max(1))
+        }
+    }
+}
+
+/// how value-based sizes are rounded to the whole units orders are made of
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Rounding {
+    #[default]
+    Floor,
+    Nearest,
+}
+
+impl Rounding {
+    pub fn round(&self, qty: f64) -> f64 {
+        match self {
+            Rounding::Floor => (qty + TOLERANCE).floor(),
+            Rounding::Nearest => qty.round(),
+        }
+    }
+}
+
+/// which price buys are sized with, falling back to the others if it is missing
+#[derive(Debug, Clone, Copy, Default)]
+pub enum PriceSource {
+    /// latest close of the position
+    #[default]
+    LastClose,
+    /// reference price of the decision
+    DecisionPrice,
+    /// open of the bar being decided on, e.g. for entries filled on that same bar
+    CurrentOpen,
+}
+
+impl PriceSource {
+    pub fn price(&self, decision: &Decision, position: Option<&Position>) -> Option<f64> {
+        let last_close = position.and_then(|x| x.latest_market_close);
+        match self {
+            PriceSource::LastClose => last_close.or(decision.price),
+            PriceSource::DecisionPrice => decision.price.or(last_close),
+            PriceSource::CurrentOpen => position
+                .and_then(|x| x.latest_market_open)
+                .or(last_close)
+                .or(decision.price),
+        }
+    }
+}
+
+/// the type of order a manager places, priced relative to a reference price
+#[derive(Debug, Clone, Copy, Default)]
+pub enum OrderStyle {
+    #[default]
+    Market,
+    /// limit at `price * (1 + offset)`
+    Limit { offset: f64, tif: TimeInForce },
+    /// limit improving on the price by `offset`, below it for buys and above for sells
+    Better { offset: Cost, tif: TimeInForce },
+}
+
+impl OrderStyle {
+    pub fn kind(&self, price: Option<f64>, buy: bool) -> Option<OrderKind> {
+        let (limit, tif) = match *self {
+            OrderStyle::Market => return Some(OrderKind::Market),
+            OrderStyle::Limit { offset, tif } => (price? * (1.0 + offset), tif),
+            OrderStyle::Better { offset, tif } => {
+                let price = price?;
+                let delta = match offset {
+                    Cost::Ratio(r) => price * r,
+                    Cost::Fixed(f) => f,
+                };
+                (if buy { price - delta } else { price + delta }, tif)
+            }
+        };
+        Some(OrderKind::Limit {
+            limit,
+            stop: None,
+            post_only: false,
+            tif,
+        })
+    }
+}
+
+/// the order kind of `style` at the reference price of `source`
+fn styled_kind(
+    style: OrderStyle,
+    source: PriceSource,
+    decision: &Decision,
+    position: Option<&Position>,
+) -> Option<OrderKind> {
+    let buy = matches!(decision.kind, DecisionKind::Buy);
+    let kind = style.kind(source.price(decision, position), buy);
+    if kind.is_none() {
+        log::warn!(
+            "no price to place a limit at, skip decision: {:?}",
+            decision
+        );
+    }
+    kind
+}
+
+#[derive(Clone, Default)]
 pub struct FixedValueOrderManager {
     pub val: f64,
+    /// adds allowed to a position since last flat, 0 for no limit
+    pub max_adds: usize,
+    pub rounding: Rounding,
+    pub lot: LotRules,
+    pub price_source: PriceSource,
+    /// order type of buys
+    pub entry_kind: OrderStyle,
+    /// order type of sells
+    pub exit_kind: OrderStyle,
+    /// sells and closes of a symbol without a position
+    pub no_position: NoPositionPolicy,
+}
+
+impl FixedValueOrderManager {
+    /// quantity to buy at `price` with `val` scaled by `strength`, sized down to the available cash
+    pub fn target_qty(&self, strength: f64, price: f64, cash: f64) -> f64 {
+        let qty = self.rounding.round((self.val * strength).min(cash) / price);
+        // rounding up may not buy more than the cash allows
+        if qty * price > cash + TOLERANCE {
+            Rounding::Floor.round(cash / price)
+        } else {
+            qty
+        }
+    }
 }
 
 impl OrderManager for FixedValueOrderManager {
     fn make_order(
         &mut self,
         decision: &Decision,
-        position: Option<&Position>,
+        view: &PortfolioView,
     ) -> Result<Option<Order>, ErrorRepr> {
         use DecisionKind::*;
 
+        let position = view.position;
         let mut b = OrderBuilder::default();
 
+        let flat = position.is_none_or(|x| x.qty.is_zero());
+        if flat && matches!(decision.kind, Sell | Close | SellFraction(_)) {
+            return match self.no_position {
+                NoPositionPolicy::Ignore => Ok(None),
+                NoPositionPolicy::Error => Err(ErrorRepr::Strategy(format!(
+                    "{:?} without a position in {}",
+                    decision.kind, decision.sym
+                ))),
+            };
+        }
+
         match decision.kind {
+            Buy if is_max_adds_reached(self.max_adds, position) => return Ok(None),
             Buy => {
-                let price = position.unwrap().latest_market_close.unwrap();
-                b.qty((self.val / price).floor() as i32);
+                let Some(price) = self.price_source.price(decision, position) else {
+                    log::warn!("no price to size the order, skip decision: {:?}", decision);
+                    return Ok(None);
+                };
+                let Some(kind) =
+                    styled_kind(self.entry_kind, self.price_source, decision, position)
+                else {
+                    return Ok(None);
+                };
+                // size down to what is affordable instead of leaving it to the broker
+                b.qty(self.target_qty(decision.clamped_strength(), price, view.cash) as i32)
+                    .kind(kind);
             }
-            Sell | Close => {
-                let current = position.map_or(0, |x| x.qty);
-                b.qty(-current);
+            Close => return Ok(close_order(decision, position)),
+            Sell => {
+                let Some(kind) = styled_kind(self.exit_kind, self.price_source, decision, position)
+                else {
+                    return Ok(None);
+                };
+                let current = position.map_or(Qty(0), |x| x.qty);
+                b.qty(-current.max(Qty(0))).kind(kind);
+            }
+            SellFraction(f) => {
+                let Some(kind) = styled_kind(self.exit_kind, self.price_source, decision, position)
+                else {
+                    return Ok(None);
+                };
+                b.qty(fraction_qty(position, f)).kind(kind);
             }
             _ => return Ok(None),
         }
 
         b.time(decision.time).sym(decision.sym.clone());
 
-        let ord = b.build().unwrap();
+        let mut ord = b.build().unwrap();
+        ord.qty = self.lot.round(ord.qty);
         Ok(if ord.qty != 0 {
             Some(ord)
         } else {
-            log::warn!("cannot make order with qty == 0. order: {:?}", ord);
+            log::debug!("cannot make order with qty == 0. order: {:?}", ord);
             None
         })
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct FixedSizeOrderManager {
     pub size: i32,
+    /// adds allowed to a position since last flat, 0 for no limit
+    pub max_adds: usize,
+    pub lot: LotRules,
+    /// order type of buys, limits are placed relative to the latest close
+    pub entry_kind: OrderStyle,
+    /// order type of sells, limits are placed relative to the latest close
+    pub exit_kind: OrderStyle,
+    /// sells of `size` larger than the holding
+    pub oversell: OversellPolicy,
 }
 
 impl OrderManager for FixedSizeOrderManager {
     fn make_order(
         &mut self,
         decision: &Decision,
-        position: Option<&Position>,
+        view: &PortfolioView,
     ) -> Result<Option<Order>, ErrorRepr> {
         use DecisionKind::*;
 
+        let position = view.position;
         let mut b = OrderBuilder::default();
-        let current = position.map_or(0, |x| x.qty);
+        let current = position.map_or(Qty(0), |x| x.qty);
+        let size = Qty::whole(self.size as f64 * decision.clamped_strength()).0;
 
         match decision.kind {
+            Buy if is_max_adds_reached(self.max_adds, position) => return Ok(None),
             Buy => {
-                b.qty(self.size);
+                let Some(kind) =
+                    styled_kind(self.entry_kind, PriceSource::LastClose, decision, position)
+                else {
+                    return Ok(None);
+                };
+                b.qty(size).kind(kind);
             }
             Sell => {
-                b.qty(-self.size.min(current));
+                let Some(kind) =
+                    styled_kind(self.exit_kind, PriceSource::LastClose, decision, position)
+                else {
+                    return Ok(None);
+                };
+                let Some(qty) = self.oversell.sell_qty(Qty(size), current) else {
+                    return Ok(None);
+                };
+                b.qty(qty).kind(kind);
+            }
+            SellFraction(f) => {
+                let Some(kind) =
+                    styled_kind(self.exit_kind, PriceSource::LastClose, decision, position)
+                else {
+                    return Ok(None);
+                };
+                b.qty(fraction_qty(position, f)).kind(kind);
+            }
+            Close => return Ok(close_order(decision, position)),
+            _ => return Ok(None),
+        }
+
+        b.time(decision.time).sym(decision.sym.clone());
+
+        let mut ord = b.build().unwrap();
+        ord.qty = self.lot.round(ord.qty);
+        Ok(if ord.qty != 0 {
+            Some(ord)
+        } else {
+            log::debug!("cannot make order with qty == 0");
+            None
+        })
+    }
+}
+
+/// builds a position of `total` in `tranches` equal buys, one per decision from the buy
+/// on, held or not, to spread the impact over several bars. a buy only builds what the
+/// position is short of `total`. sells and closes exit the whole position and abandon
+/// what is left to build
+#[derive(Clone, Default)]
+pub struct ScaledEntryOrderManager {
+    pub total: i32,
+    pub tranches: usize,
+    pub lot: LotRules,
+    /// quantity still to buy of every symbol being built
+    remaining: HashMap<Symbol, i32>,
+}
+
+impl ScaledEntryOrderManager {
+    pub fn new(total: i32, tranches: usize) -> Self {
+        Self {
+            total,
+            tranches,
+            ..Default::default()
+        }
+    }
+
+    /// quantity still to buy of `sym`, 0 once fully built
+    pub fn remaining(&self, sym: &str) -> i32 {
+        self.remaining.get(sym).copied().unwrap_or(0)
+    }
+
+    /// take the next tranche off what is left to build of `sym` with `held` already
+    /// bought, the last one takes the rest of the rounding
+    fn next_tranche(&mut self, sym: &str, held: i32) -> Qty {
+        let size = self
+            .lot
+            .round(Qty(self.total / self.tranches.max(1) as i32));
+        let Some(remaining) = self.remaining.get_mut(sym) else {
+            return Qty(0);
+        };
+        *remaining = (*remaining).min(self.total - held);
+        let last = size.is_zero() || *remaining < 2 * size.0;
+        let qty = if last {
+            self.lot.round(Qty((*remaining).max(0)))
+        } else {
+            size
+        };
+        *remaining -= qty.0;
+        if last {
+            self.remaining.remove(sym);
+        }
+        qty
+    }
+}
+
+impl OrderManager for ScaledEntryOrderManager {
+    fn make_order(
+        &mut self,
+        decision: &Decision,
+        view: &PortfolioView,
+    ) -> Result<Option<Order>, ErrorRepr> {
+        use DecisionKind::*;
+
+        let position = view.position;
+        let held = position.map_or(0, |x| x.qty.0.max(0));
+        let qty = match decision.kind {
+            Sell | Close => {
+                self.remaining.remove(&decision.sym);
+                return Ok(close_order(decision, position));
             }
-            Close => {
-                b.qty(-current);
+            Buy if !self.remaining.contains_key(&decision.sym) => {
+                self.remaining.insert(decision.sym.clone(), self.total);
+                self.next_tranche(&decision.sym, held)
+            }
+            _ if self.remaining.contains_key(&decision.sym) => {
+                self.next_tranche(&decision.sym, held)
+            }
+            _ => return Ok(None),
+        };
+
+        if qty.is_zero() {
+            log::debug!("cannot make order with qty == 0: {:?}", decision);
+            return Ok(None);
+        }
+        let ord = OrderBuilder::default()
+            .qty(qty)
+            .time(decision.time)
+            .sym(decision.sym.clone())
+            .build()
+            .unwrap();
+        Ok(Some(ord))
+    }
+}
+
+/// weights each symbol inversely to the volatility of its recent returns
+#[derive(Clone, Default)]
+pub struct InverseVolatilityOrderManager {
+    /// total value to allocate across the basket
+    pub val: f64,
+    /// number of returns in the rolling window
+    pub window: usize,
+    pub lot: LotRules,
+    closes: HashMap<Symbol, f64>,
+    returns: HashMap<Symbol, VecDeque<f64>>,
+}
+
+impl InverseVolatilityOrderManager {
+    pub fn new(val: f64, window: usize) -> Self {
+        Self {
+            val,
+            window,
+            ..Default::default()
+        }
+    }
+
+    /// standard deviation of the returns in the window
+    pub fn volatility(&self, sym: &str) -> Option<f64> {
+        let rets = self.returns.get(sym)?;
+        if rets.len() < 2 {
+            return None;
+        }
+        let n = rets.len() as f64;
+        let mean = rets.iter().sum::<f64>() / n;
+        let var = rets.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        Some(var.sqrt())
+    }
+
+    /// normalized target weights, symbols without a usable volatility are left out
+    pub fn weights(&self) -> HashMap<Symbol, f64> {
+        let inv = self
+            .returns
+            .keys()
+            .filter_map(|sym| match self.volatility(sym) {
+                Some(vol) if vol > 0.0 => Some((sym.clone(), 1.0 / vol)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        let total: f64 = inv.values().sum();
+        inv.into_iter().map(|(sym, w)| (sym, w / total)).collect()
+    }
+}
+
+impl OrderManager for InverseVolatilityOrderManager {
+    fn make_order(
+        &mut self,
+        decision: &Decision,
+        view: &PortfolioView,
+    ) -> Result<Option<Order>, ErrorRepr> {
+        use DecisionKind::*;
+
+        let position = view.position;
+        let mut b = OrderBuilder::default();
+
+        match decision.kind {
+            Buy => {
+                let Some(weight) = self.weights().get(&decision.sym).copied() else {
+                    log::warn!("no volatility for {}, skip decision", decision.sym);
+                    return Ok(None);
+                };
+                let price = position
+                    .and_then(|x| x.latest_market_close)
+                    .ok_or(ErrorRepr::NotExists("latest price"))?;
+                let val = self.val * weight * decision.clamped_strength();
+                b.qty(Qty::whole(val.min(view.cash) / price));
+            }
+            Close => return Ok(close_order(decision, position)),
+            Sell => {
+                let current = position.map_or(Qty(0), |x| x.qty);
+                b.qty(-current.max(Qty(0)));
             }
             _ => return Ok(None),
         }
 
         b.time(decision.time).sym(decision.sym.clone());
 
-        let ord = b.build().unwrap();
+        let mut ord = b.build().unwrap();
+        ord.qty = self.lot.round(ord.qty);
         Ok(if ord.qty != 0 {
             Some(ord)
         } else {
-            log::warn!("cannot make order with qty == 0");
+            log::debug!("cannot make order with qty == 0. order: {:?}", ord);
             None
         })
     }
+
+    fn update_from_market(&mut self, data: &Bar) {
+        if let Some(prev) = self.closes.insert(data.sym.clone(), data.close) {
+            if prev == 0.0 {
+                return;
+            }
+            let rets = self.returns.entry(data.sym.clone()).or_default();
+            rets.push_back(data.close / prev - 1.0);
+            while rets.len() > self.window {
+                rets.pop_front();
+            }
+        }
+    }
+}
+
+/// splits a shared cash pool across a basket by weight. what a symbol leaves unspent of
+/// its share, e.g. from rounding or a partial fill, goes to the symbols not invested yet.
+/// spending is booked from the fills, so an unfilled buy keeps its share
+#[derive(Clone, Default)]
+pub struct BasketAllocator {
+    pub pool: f64,
+    pub weights: HashMap<Symbol, f64>,
+    pub lot: LotRules,
+    pub price_source: PriceSource,
+    /// quantity held and its cost of every invested symbol
+    spent: HashMap<Symbol, (Qty, f64)>,
+}
+
+impl BasketAllocator {
+    pub fn new(pool: f64, weights: HashMap<Symbol, f64>) -> Self {
+        Self {
+            pool,
+            weights,
+            ..Default::default()
+        }
+    }
+
+    /// share of the pool of `sym` by weight
+    pub fn allocation(&self, sym: &str) -> f64 {
+        let total: f64 = self.weights.values().sum();
+        match self.weights.get(sym) {
+            Some(w) if total > 0.0 => self.pool * w / total,
+            _ => 0.0,
+        }
+    }
+
+    /// cash `sym` may spend: its allocation plus its part of what invested symbols left
+    pub fn budget(&self, sym: &str) -> f64 {
+        if self.spent.contains_key(sym) {
+            return 0.0;
+        }
+        let leftover: f64 = self
+            .spent
+            .iter()
+            .map(|(s, (_, v))| self.allocation(s) - v)
+            .sum();
+        let pending: f64 = self
+            .weights
+            .iter()
+            .filter(|(s, _)| !self.spent.contains_key(*s))
+            .map(|x| x.1)
+            .sum();
+        let weight = self.weights.get(sym).copied().unwrap_or(0.0);
+        if pending > 0.0 {
+            self.allocation(sym) + leftover * weight / pending
+        } else {
+            0.0
+        }
+    }
+}
+
+impl OrderManager for BasketAllocator {
+    fn make_order(
+        &mut self,
+        decision: &Decision,
+        view: &PortfolioView,
+    ) -> Result<Option<Order>, ErrorRepr> {
+        use DecisionKind::*;
+
+        let position = view.position;
+        let qty = match decision.kind {
+            Buy => {
+                let Some(price) = self.price_source.price(decision, position) else {
+                    log::warn!("no price to size the order, skip decision: {:?}", decision);
+                    return Ok(None);
+                };
+                let budget = self.budget(&decision.sym).min(view.cash);
+                self.lot.round(Qty::whole(budget / price))
+            }
+            Sell | Close => -position.map_or(Qty(0), |x| x.qty).max(Qty(0)),
+            _ => return Ok(None),
+        };
+
+        if qty.is_zero() {
+            return Ok(None);
+        }
+        let ord = OrderBuilder::default()
+            .qty(qty)
+            .time(decision.time)
+            .sym(decision.sym.clone())
+            .build()
+            .unwrap();
+        Ok(Some(ord))
+    }
+
+    fn update_from_fill(&mut self, fill: &Fill) {
+        if !self.weights.contains_key(&fill.sym) {
+            return;
+        }
+        let (qty, cost) = self.spent.entry(fill.sym.clone()).or_default();
+        if fill.qty > 0 {
+            *cost += (fill.qty * fill.price).0;
+        } else if !qty.is_zero() {
+            // the capital of what was sold goes back to the pool
+            *cost *= 1.0 + f64::from(fill.qty) / f64::from(*qty);
+        }
+        *qty += fill.qty;
+        if *qty <= 0 {
+            self.spent.remove(&fill.sym);
+        }
+    }
+}
+
+/// sizes buys so that a stop-out at the stop of the decision loses `risk`, before costs
+#[derive(Clone, Default)]
+pub struct FixedRiskOrderManager {
+    /// loss per trade if stopped out, scaled by the strength of the decision
+    pub risk: f64,
+    pub lot: LotRules,
+    pub price_source: PriceSource,
+}
+
+impl FixedRiskOrderManager {
+    /// quantity losing `risk` over `price - stop`, 0 if the stop is not below the price
+    pub fn target_qty(&self, strength: f64, price: f64, stop: f64) -> Qty {
+        let distance = price - stop;
+        if distance <= 0.0 {
+            return Qty(0);
+        }
+        Qty::whole(self.risk * strength / distance)
+    }
+}
+
+impl OrderManager for FixedRiskOrderManager {
+    fn make_order(
+        &mut self,
+        decision: &Decision,
+        view: &PortfolioView,
+    ) -> Result<Option<Order>, ErrorRepr> {
+        use DecisionKind::*;
+
+        let position = view.position;
+        let mut stop_distance = None;
+        let qty = match decision.kind {
+            Buy => {
+                let (Some(price), Some(stop)) =
+                    (self.price_source.price(decision, position), decision.stop)
+                else {
+                    log::warn!(
+                        "no price or stop to size the order, skip decision: {:?}",
+                        decision
+                    );
+                    return Ok(None);
+                };
+                let affordable = Qty::whole(view.cash / price);
+                stop_distance = Some((price - stop).abs());
+                self.target_qty(decision.clamped_strength(), price, stop)
+                    .min(affordable)
+            }
+            Sell => -position.map_or(Qty(0), |x| x.qty).max(Qty(0)),
+            SellFraction(f) => fraction_qty(position, f),
+            Close => return Ok(close_order(decision, position)),
+            _ => return Ok(None),
+        };
+
+        let qty = self.lot.round(qty);
+        if qty.is_zero() {
+            log::debug!("cannot make order with qty == 0: {:?}", decision);
+            return Ok(None);
+        }
+        let ord = OrderBuilder::default()
+            .qty(qty)
+            .time(decision.time)
+            .sym(decision.sym.clone())
+            .stop_distance(stop_distance)
+            .build()
+            .unwrap();
+        Ok(Some(ord))
+    }
+}
+
+/// trades the difference between the position and a target exposure of `max_notional`.
+/// buys and sells are full long and short targets, closes a zero target
+#[derive(Clone, Default)]
+pub struct TargetExposureOrderManager {
+    pub max_notional: f64,
+    pub lot: LotRules,
+    pub price_source: PriceSource,
+}
+
+impl TargetExposureOrderManager {
+    /// quantity held at `target` exposure, truncated towards zero
+    pub fn target_qty(&self, target: f64, price: f64) -> Qty {
+        Qty::whole(target.clamp(-1.0, 1.0) * self.max_notional / price)
+    }
+}
+
+impl OrderManager for TargetExposureOrderManager {
+    fn make_order(
+        &mut self,
+        decision: &Decision,
+        view: &PortfolioView,
+    ) -> Result<Option<Order>, ErrorRepr> {
+        use DecisionKind::*;
+
+        let position = view.position;
+        let target = match decision.kind {
+            Hold => return Ok(None),
+            Buy => 1.0,
+            Sell => -1.0,
+            Close => return Ok(close_order(decision, position)),
+            Target(x) => x,
+            SellFraction(f) => {
+                let qty = self.lot.round(fraction_qty(position, f));
+                return Ok((!qty.is_zero()).then(|| {
+                    OrderBuilder::default()
+                        .qty(qty)
+                        .time(decision.time)
+                        .sym(decision.sym.clone())
+                        .build()
+                        .unwrap()
+                }));
+            }
+        };
+        let Some(price) = self.price_source.price(decision, position) else {
+            log::warn!("no price to size the order, skip decision: {:?}", decision);
+            return Ok(None);
+        };
+
+        let current = position.map_or(Qty(0), |x| x.qty);
+        let qty = self.lot.round(self.target_qty(target, price) - current);
+        if qty.is_zero() {
+            return Ok(None);
+        }
+
+        let ord = OrderBuilder::default()
+            .qty(qty)
+            .time(decision.time)
+            .sym(decision.sym.clone())
+            .build()
+            .unwrap();
+        Ok(Some(ord))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::position::PositionSettings;
+
+    fn view(position: Option<&Position>) -> PortfolioView<'_> {
+        PortfolioView {
+            cash: f64::MAX,
+            position,
+        }
+    }
 
     #[test]
     fn test_default_make_order() {
@@ -154,21 +1029,32 @@ mod tests {
         let sym = "test".to_owned();
 
         let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
             time,
             sym: sym.clone(),
             kind: DecisionKind::Hold,
         };
-        let mut m = FixedSizeOrderManager { size: 10 };
-        assert!(matches!(m.make_order(&d, None), Ok(None)));
+        let mut m = FixedSizeOrderManager {
+            size: 10,
+            ..Default::default()
+        };
+        assert!(matches!(m.make_order(&d, &view(None)), Ok(None)));
 
         let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
             time,
             sym: sym.clone(),
             kind: DecisionKind::Buy,
         };
 
         let ord = m
-            .make_order(&d, None)
+            .make_order(&d, &view(None))
             .expect("should be Ok")
             .expect("should be Some");
 
@@ -179,19 +1065,813 @@ mod tests {
         assert!(matches!(ord.kind, OrderKind::Market));
 
         let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
             time,
             sym: sym.clone(),
             kind: DecisionKind::Sell,
         };
 
-        let mut p = Position::default();
-        p.qty = 10;
+        let p = Position {
+            qty: Qty(10),
+            ..Default::default()
+        };
 
-        let ord = m.make_order(&d, Some(&p)).unwrap().unwrap();
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
         assert_eq!(ord.time, time);
         assert_eq!(ord.sym, sym);
         assert_eq!(ord.qty, -10);
         assert_eq!(ord.time, time);
         assert!(matches!(ord.kind, OrderKind::Market));
     }
+
+    #[test]
+    fn test_fixed_value_sized_to_cash() {
+        let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let p = Position {
+            latest_market_close: Some(10.0),
+            ..Default::default()
+        };
+        let mut m = FixedValueOrderManager {
+            val: 1000.0,
+            ..Default::default()
+        };
+
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 100);
+
+        let view = PortfolioView {
+            cash: 500.0,
+            position: Some(&p),
+        };
+        let ord = m.make_order(&d, &view).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+    }
+
+    #[test]
+    fn test_rounding() {
+        let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let mut m = FixedValueOrderManager {
+            val: 100.0,
+            ..Default::default()
+        };
+        let qty = |m: &mut FixedValueOrderManager, close, cash| {
+            let p = Position {
+                latest_market_close: Some(close),
+                ..Default::default()
+            };
+            let view = PortfolioView {
+                cash,
+                position: Some(&p),
+            };
+            m.make_order(&d, &view).unwrap().map(|x| x.qty)
+        };
+        assert_eq!(qty(&mut m, 30.0, f64::MAX), Some(Qty(3)));
+
+        m.rounding = Rounding::Nearest;
+        assert_eq!(qty(&mut m, 40.0, f64::MAX), Some(Qty(3)));
+        // 2.5 rounds up to 3, more than 100 buys
+        assert_eq!(qty(&mut m, 40.0, 100.0), Some(Qty(2)));
+    }
+
+    #[test]
+    fn test_decision_strength() {
+        let mut d = Decision {
+            strength: 0.5,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let p = Position {
+            latest_market_close: Some(10.0),
+            ..Default::default()
+        };
+
+        let mut m = FixedSizeOrderManager {
+            size: 100,
+            ..Default::default()
+        };
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+
+        let mut m = FixedValueOrderManager {
+            val: 1000.0,
+            ..Default::default()
+        };
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+
+        // out of range values are clamped
+        d.strength = 2.0;
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 100);
+    }
+
+    #[test]
+    fn test_lot_rules_zero_lot() {
+        let lot = LotRules {
+            buy_lot: 0,
+            allow_odd_sell: false,
+        };
+        assert_eq!(lot.round(Qty(150)), 150);
+        assert_eq!(lot.round(Qty(-30)), -30);
+    }
+
+    #[test]
+    fn test_lot_rules() {
+        let mut m = FixedSizeOrderManager {
+            size: 150,
+            lot: LotRules::a_shares(),
+            ..Default::default()
+        };
+        let d = |kind| Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind,
+        };
+
+        let ord = m.make_order(&d(DecisionKind::Buy), &view(None)).unwrap();
+        assert_eq!(ord.unwrap().qty, 100);
+
+        let p = Position {
+            qty: Qty(30),
+            ..Default::default()
+        };
+        let ord = m
+            .make_order(&d(DecisionKind::Sell), &view(Some(&p)))
+            .unwrap();
+        assert_eq!(ord.unwrap().qty, -30);
+
+        m.lot.allow_odd_sell = false;
+        let ord = m
+            .make_order(&d(DecisionKind::Sell), &view(Some(&p)))
+            .unwrap();
+        assert!(ord.is_none());
+    }
+
+    #[test]
+    fn test_price_source() {
+        let mut d = Decision {
+            strength: 1.0,
+            price: Some(10.0),
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "new".into(),
+            kind: DecisionKind::Buy,
+        };
+        let p = Position {
+            latest_market_close: Some(20.0),
+            ..Default::default()
+        };
+        let mut m = FixedValueOrderManager {
+            val: 1000.0,
+            ..Default::default()
+        };
+
+        // never seen before, falls back to the decision price
+        let ord = m.make_order(&d, &view(None)).unwrap().unwrap();
+        assert_eq!(ord.qty, 100);
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+
+        m.price_source = PriceSource::DecisionPrice;
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 100);
+
+        d.price = None;
+        assert!(m.make_order(&d, &view(None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_target_exposure() {
+        let d = |target| Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Target(target),
+        };
+        let mut p = Position {
+            latest_market_close: Some(10.0),
+            ..Default::default()
+        };
+        let s = PositionSettings {
+            allow_short: true,
+            ..Default::default()
+        };
+        let mut m = TargetExposureOrderManager {
+            max_notional: 1000.0,
+            ..Default::default()
+        };
+
+        let ord = m.make_order(&d(1.0), &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, 100);
+        p.update_from_fill(
+            &Fill {
+                order_id: 0,
+                risk: None,
+                sym: "test".into(),
+                qty: ord.qty,
+                price: Price(10.0),
+                cost: 0.0,
+                time: chrono::Utc::now(),
+            },
+            &s,
+        )
+        .unwrap();
+        assert!(m.make_order(&d(1.0), &view(Some(&p))).unwrap().is_none());
+
+        let ord = m.make_order(&d(-0.5), &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, -150);
+    }
+
+    #[test]
+    fn test_entry_exit_kind() {
+        let d = |kind| Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind,
+        };
+        let p = Position {
+            qty: Qty(10),
+            latest_market_close: Some(10.0),
+            ..Default::default()
+        };
+        let mut m = FixedSizeOrderManager {
+            size: 10,
+            exit_kind: OrderStyle::Limit {
+                offset: 0.05,
+                tif: TimeInForce::GTC,
+            },
+            ..Default::default()
+        };
+
+        let ord = m
+            .make_order(&d(DecisionKind::Buy), &view(Some(&p)))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(ord.kind, OrderKind::Market));
+
+        let ord = m
+            .make_order(&d(DecisionKind::Sell), &view(Some(&p)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ord.qty, -10);
+        let OrderKind::Limit { limit, tif, .. } = ord.kind else {
+            panic!("sell should be a limit order");
+        };
+        assert!((limit - 10.5).abs() < 1e-9);
+        assert_eq!(tif, TimeInForce::GTC);
+
+        // closes stay market orders
+        let ord = m
+            .make_order(&d(DecisionKind::Close), &view(Some(&p)))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(ord.kind, OrderKind::Market));
+    }
+
+    #[test]
+    fn test_better_limit() {
+        let d = |kind| Decision {
+            strength: 1.0,
+            price: Some(100.0),
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind,
+        };
+        let p = Position {
+            qty: Qty(10),
+            latest_market_close: Some(90.0),
+            ..Default::default()
+        };
+        let better = OrderStyle::Better {
+            offset: Cost::Ratio(0.005),
+            tif: TimeInForce::Day,
+        };
+        let mut m = FixedValueOrderManager {
+            val: 1000.0,
+            price_source: PriceSource::DecisionPrice,
+            entry_kind: better,
+            exit_kind: better,
+            ..Default::default()
+        };
+
+        let ord = m
+            .make_order(&d(DecisionKind::Buy), &view(Some(&p)))
+            .unwrap()
+            .unwrap();
+        let OrderKind::Limit { limit, .. } = ord.kind else {
+            panic!("buy should be a limit order");
+        };
+        assert!((limit - 99.5).abs() < 1e-9);
+
+        let ord = m
+            .make_order(&d(DecisionKind::Sell), &view(Some(&p)))
+            .unwrap()
+            .unwrap();
+        let OrderKind::Limit { limit, .. } = ord.kind else {
+            panic!("sell should be a limit order");
+        };
+        assert!((limit - 100.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_no_position_policy() {
+        let d = Decision {
+            strength: 1.0,
+            price: Some(100.0),
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Close,
+        };
+        let flat = Position::default();
+        let mut m = FixedValueOrderManager {
+            val: 1000.0,
+            ..Default::default()
+        };
+        assert!(matches!(m.make_order(&d, &view(None)), Ok(None)));
+        assert!(matches!(m.make_order(&d, &view(Some(&flat))), Ok(None)));
+
+        m.no_position = NoPositionPolicy::Error;
+        assert!(matches!(
+            m.make_order(&d, &view(None)),
+            Err(ErrorRepr::Strategy(_))
+        ));
+        let d = Decision {
+            kind: DecisionKind::Sell,
+            ..d
+        };
+        assert!(matches!(
+            m.make_order(&d, &view(Some(&flat))),
+            Err(ErrorRepr::Strategy(_))
+        ));
+    }
+
+    #[test]
+    fn test_fraction_tolerance() {
+        let p = Position {
+            qty: Qty(100),
+            latest_market_close: Some(10.0),
+            ..Default::default()
+        };
+        // 100 * 0.29 = 28.999999999999996
+        assert_eq!(fraction_qty(Some(&p), 0.29), -29);
+
+        let mut m = FixedValueOrderManager {
+            val: 0.3,
+            ..Default::default()
+        };
+        let d = Decision {
+            strength: 1.0,
+            price: Some(0.1),
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let ord = m.make_order(&d, &view(None)).unwrap().unwrap();
+        assert_eq!(ord.qty, 3);
+    }
+
+    #[test]
+    fn test_oversell_policy() {
+        let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Sell,
+        };
+        let p = Position {
+            qty: Qty(100),
+            latest_market_close: Some(10.0),
+            ..Default::default()
+        };
+        let sell = |oversell| {
+            let mut m = FixedSizeOrderManager {
+                size: 150,
+                oversell,
+                ..Default::default()
+            };
+            m.make_order(&d, &view(Some(&p))).unwrap().map(|x| x.qty)
+        };
+
+        assert_eq!(sell(OversellPolicy::Clamp), Some(Qty(-100)));
+        assert_eq!(sell(OversellPolicy::Short), Some(Qty(-150)));
+        assert_eq!(sell(OversellPolicy::Reject), None);
+    }
+
+    #[test]
+    fn test_fixed_risk() {
+        let d = |stop| Decision {
+            strength: 1.0,
+            price: Some(10.0),
+            signal: None,
+            stop,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let mut m = FixedRiskOrderManager {
+            risk: 100.0,
+            price_source: PriceSource::DecisionPrice,
+            ..Default::default()
+        };
+
+        // a $2 stop distance
+        let ord = m.make_order(&d(Some(8.0)), &view(None)).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+        assert_eq!(ord.stop_distance, Some(2.0));
+        assert!(m.make_order(&d(None), &view(None)).unwrap().is_none());
+        assert!(m.make_order(&d(Some(11.0)), &view(None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_scaled_entry() {
+        let start = chrono::Utc::now();
+        let d = |i: i64, kind| Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: start + chrono::Duration::days(i),
+            sym: "test".into(),
+            kind,
+        };
+        let mut m = ScaledEntryOrderManager::new(300, 3);
+        let kinds = [
+            DecisionKind::Buy,
+            DecisionKind::Hold,
+            DecisionKind::Buy,
+            DecisionKind::Hold,
+        ];
+        let orders = kinds
+            .into_iter()
+            .enumerate()
+            .map(|(i, kind)| m.make_order(&d(i as i64, kind), &view(None)).unwrap())
+            .collect::<Vec<_>>();
+        for (i, ord) in orders[..3].iter().enumerate() {
+            let ord = ord.as_ref().unwrap();
+            assert_eq!(ord.qty, 100);
+            assert_eq!(ord.time, d(i as i64, DecisionKind::Hold).time);
+        }
+        // fully built
+        assert!(orders[3].is_none());
+        assert_eq!(m.remaining("test"), 0);
+
+        // a sell abandons the rest
+        let mut m = ScaledEntryOrderManager::new(250, 3);
+        assert_eq!(
+            m.make_order(&d(0, DecisionKind::Buy), &view(None))
+                .unwrap()
+                .unwrap()
+                .qty,
+            83
+        );
+        assert_eq!(m.remaining("test"), 167);
+        m.make_order(&d(1, DecisionKind::Sell), &view(None))
+            .unwrap();
+        assert_eq!(m.remaining("test"), 0);
+        assert!(m
+            .make_order(&d(2, DecisionKind::Hold), &view(None))
+            .unwrap()
+            .is_none());
+
+        // a buy only builds what is missing, nothing once fully held
+        let mut m = ScaledEntryOrderManager::new(300, 3);
+        let held = |qty| Position {
+            qty: Qty(qty),
+            ..Default::default()
+        };
+        let p = held(300);
+        assert!(m
+            .make_order(&d(0, DecisionKind::Buy), &view(Some(&p)))
+            .unwrap()
+            .is_none());
+        assert_eq!(m.remaining("test"), 0);
+        // the tranches fill as they go
+        let qty = [100, 200, 300]
+            .into_iter()
+            .enumerate()
+            .map(|(i, qty)| {
+                let p = held(qty);
+                let ord = m.make_order(&d(i as i64, DecisionKind::Buy), &view(Some(&p)));
+                ord.unwrap().map(|x| x.qty.0)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(qty, [Some(100), Some(100), None]);
+
+        // a tranche rounded down to nothing does not leave the build pending
+        let mut m = ScaledEntryOrderManager {
+            lot: LotRules::a_shares(),
+            ..ScaledEntryOrderManager::new(250, 3)
+        };
+        assert_eq!(
+            m.make_order(&d(0, DecisionKind::Buy), &view(None))
+                .unwrap()
+                .unwrap()
+                .qty,
+            200
+        );
+        assert_eq!(m.remaining("test"), 0);
+    }
+
+    #[test]
+    fn test_basket_allocator() {
+        let weights = [("a".to_owned(), 3.0), ("b".to_owned(), 1.0)].into();
+        let mut m = BasketAllocator::new(1000.0, weights);
+        let buy = |sym: &str, price| Decision {
+            strength: 1.0,
+            price: Some(price),
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: sym.into(),
+            kind: DecisionKind::Buy,
+        };
+        assert_eq!(m.allocation("a"), 750.0);
+        assert_eq!(m.allocation("b"), 250.0);
+
+        let fill = |ord: &Order, price| Fill {
+            order_id: ord.id,
+            risk: None,
+            time: ord.time,
+            qty: ord.qty,
+            sym: ord.sym.clone(),
+            price: Price(price),
+            cost: 0.0,
+        };
+        // rejected, the share of a is untouched
+        assert!(m
+            .make_order(&buy("a", 100.0), &view(None))
+            .unwrap()
+            .is_some());
+        assert_eq!(m.budget("a"), 750.0);
+        assert_eq!(m.budget("b"), 250.0);
+
+        // 7 * 100 of 750, the 50 left goes to b
+        let ord = m
+            .make_order(&buy("a", 100.0), &view(None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(ord.qty, 7);
+        m.update_from_fill(&fill(&ord, 100.0));
+        assert_eq!(m.budget("b"), 300.0);
+        let ord = m.make_order(&buy("b", 30.0), &view(None)).unwrap().unwrap();
+        assert_eq!(ord.qty, 10);
+        m.update_from_fill(&fill(&ord, 30.0));
+
+        // already invested
+        assert!(m
+            .make_order(&buy("a", 100.0), &view(None))
+            .unwrap()
+            .is_none());
+
+        let p = Position {
+            qty: Qty(7),
+            ..Default::default()
+        };
+        let sell = Decision {
+            kind: DecisionKind::Sell,
+            ..buy("a", 100.0)
+        };
+        let ord = m.make_order(&sell, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, -7);
+        m.update_from_fill(&fill(&ord, 100.0));
+        // b spent the 50 a left over, so a gets it back minus that
+        assert_eq!(m.budget("a"), 700.0);
+    }
+
+    #[test]
+    fn test_close_position() {
+        let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Close,
+        };
+        let fill = |qty| Fill {
+            order_id: 0,
+            risk: None,
+            sym: "test".into(),
+            qty: Qty(qty),
+            price: Price(1.0),
+            cost: 0.0,
+            time: chrono::Utc::now(),
+        };
+        let mut m = FixedValueOrderManager {
+            val: 1000.0,
+            lot: LotRules::a_shares(),
+            ..Default::default()
+        };
+
+        for qty in [30, -30] {
+            let mut p = Position::default();
+            let s = PositionSettings {
+                allow_short: true,
+                ..Default::default()
+            };
+            p.update_from_fill(&fill(qty), &s).unwrap();
+
+            let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+            assert_eq!(ord.qty, -qty);
+            p.update_from_fill(&fill(ord.qty.0), &s).unwrap();
+            assert_eq!(p.qty, 0);
+
+            assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_sell_fraction() {
+        let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::SellFraction(0.5),
+        };
+        let (mut p, s) = (Position::default(), PositionSettings::default());
+        p.update_from_fill(
+            &Fill {
+                order_id: 0,
+                risk: None,
+                sym: "test".into(),
+                qty: Qty(100),
+                price: Price(1.0),
+                cost: 0.0,
+                time: chrono::Utc::now(),
+            },
+            &s,
+        )
+        .unwrap();
+        p.latest_market_close = Some(1.0);
+
+        let mut m = FixedSizeOrderManager {
+            size: 100,
+            ..Default::default()
+        };
+        let ord = m.make_order(&d, &view(Some(&p))).unwrap().unwrap();
+        assert_eq!(ord.qty, -50);
+        p.update_from_fill(
+            &Fill {
+                qty: ord.qty,
+                ..p.stats.transactions[0].clone()
+            },
+            &s,
+        )
+        .unwrap();
+        assert_eq!(p.qty, 50);
+
+        // nothing to scale out of
+        assert!(m.make_order(&d, &view(None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_max_adds() {
+        let d = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let mut m = FixedSizeOrderManager {
+            size: 10,
+            max_adds: 1,
+            ..Default::default()
+        };
+        let (mut p, s) = (Position::default(), PositionSettings::default());
+        let fill = |qty| Fill {
+            order_id: 0,
+            risk: None,
+            sym: "test".into(),
+            qty: Qty(qty),
+            price: Price(1.0),
+            cost: 0.0,
+            time: chrono::Utc::now(),
+        };
+
+        // initial entry
+        assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_some());
+        p.update_from_fill(&fill(10), &s).unwrap();
+        // one add
+        assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_some());
+        p.update_from_fill(&fill(10), &s).unwrap();
+        assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_none());
+
+        p.update_from_fill(&fill(-20), &s).unwrap();
+        assert_eq!(p.entries, 0);
+        assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_some());
+
+        // partial fills of one order are a single entry
+        let part = |order_id| Fill {
+            order_id,
+            ..fill(5)
+        };
+        p.update_from_fill(&part(1), &s).unwrap();
+        p.update_from_fill(&part(1), &s).unwrap();
+        assert_eq!(p.entries, 1);
+        assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_some());
+        p.update_from_fill(&part(2), &s).unwrap();
+        assert_eq!(p.entries, 2);
+        assert!(m.make_order(&d, &view(Some(&p))).unwrap().is_none());
+
+        // covering a short is no entry, the long opened past it is
+        let s = PositionSettings {
+            allow_short: true,
+            ..Default::default()
+        };
+        let mut p = Position::default();
+        p.update_from_fill(&fill(-20), &s).unwrap();
+        p.update_from_fill(&fill(10), &s).unwrap();
+        assert_eq!(p.entries, 0);
+        p.update_from_fill(&fill(20), &s).unwrap();
+        assert_eq!(p.entries, 1);
+    }
+
+    #[test]
+    fn test_inverse_volatility_weights() {
+        let mut m = InverseVolatilityOrderManager::new(10_000.0, 10);
+        for i in 0..20 {
+            let delta = if i % 2 == 0 { 0.0 } else { 1.0 };
+            for (sym, scale) in [("calm", 1.0), ("wild", 10.0)] {
+                m.update_from_market(&Bar {
+                    sym: sym.into(),
+                    close: 100.0 + delta * scale,
+                    ..Default::default()
+                });
+            }
+        }
+
+        let weights = m.weights();
+        assert!(weights["calm"] > weights["wild"]);
+        assert!((weights["calm"] + weights["wild"] - 1.0).abs() < 1e-9);
+
+        let mut make = |sym: &str| {
+            let d = Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                time: chrono::Utc::now(),
+                sym: sym.into(),
+                kind: DecisionKind::Buy,
+            };
+            let p = Position {
+                sym: sym.into(),
+                latest_market_close: Some(100.0),
+                ..Default::default()
+            };
+            m.make_order(&d, &view(Some(&p))).unwrap().unwrap().qty
+        };
+        assert!(make("calm") > make("wild"));
+    }
 }
@@ -3,18 +3,126 @@ use serde::Serialize;
 
 use crate::{
     broker::Wallet,
-    data::{Bar, Symbol},
+    data::{Bar, DateTime, Symbol},
     errors::ErrorRepr,
-    order::{Fill, OrderAllocator, OrderManager},
-    position::Position,
+    event::SkipReason,
+    metrics,
+    order::{Fill, Order, OrderAllocator, OrderBuilder, OrderManager},
+    position::{MarkSource, Position, PositionSettings, Trade, Valuation},
+    strategy::{Decision, DecisionKind},
+    units::Qty,
+    TOLERANCE,
 };
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+/// what an order manager can see of the portfolio
+#[derive(Debug, Clone, Copy)]
+pub struct PortfolioView<'a> {
+    pub cash: f64,
+    pub position: Option<&'a Position>,
+}
 
 pub trait PositionManager {
     fn update_from_market(&mut self, data: &Bar) -> Result<(), ErrorRepr>;
     fn update_from_fill(&mut self, fill: &Fill) -> Result<(), ErrorRepr>;
+    /// book a fill that happened outside of the simulation, e.g. on a live account, paying
+    /// for it like a broker would. there is no buying power check, the cash may go negative
+    fn apply_external_fill(&mut self, fill: &Fill) -> Result<(), ErrorRepr>;
+    /// called once all events at `time` have been processed
+    fn mark_timestamp(&mut self, _: DateTime) {}
+    /// the round trip `fill` closed, called right after it was applied
+    fn closed_trade(&self, _: &Fill) -> Option<Trade> {
+        None
+    }
+    /// one line describing the state of the portfolio, for the log
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
+/// what to do with a fill selling more than the position holds
+#[derive(Debug, Clone, Copy, Default)]
+pub enum FillPolicy {
+    /// refuse the whole fill
+    #[default]
+    Reject,
+    /// keep the part flattening the position and refund the rest
+    Clamp,
+}
+
+/// what to do with a buy fill the broker paid for beyond the cash, e.g. a custom broker
+/// that does not consult the wallet
+#[derive(Debug, Clone, Copy, Default)]
+pub enum CashPolicy {
+    /// let the cash go negative
+    #[default]
+    Allow,
+    /// keep the affordable part, costs included, and refund the rest
+    Clamp,
+}
+
+/// how to mark an open position whose symbol has no bar at a timestamp, e.g. after a
+/// delisting. relies on the gamblers stepping in time order, see `Casino::run_ordered`
+#[derive(Debug, Clone, Copy, Default)]
+pub enum StalePricePolicy {
+    /// keep marking at the last close
+    #[default]
+    Carry,
+    /// keep marking at the last close and flag the position as stale
+    Flag,
+    /// flag the position as stale and mark it at this fraction of the last close,
+    /// e.g. 0 for a worthless delisting
+    Recover(f64),
+}
+
+/// sort key of `SimplePortfolio::positions_sorted_by`
+#[derive(Debug, Clone, Copy)]
+pub enum PositionKey {
+    /// largest first
+    Pnl,
+    /// largest first
+    PnlRatio,
+    /// largest absolute market value first
+    Exposure,
+    /// largest sharpe ratio of the pnl changes first
+    Sharpe,
+    /// alphabetical
+    Symbol,
+}
+
+/// scales the size of every position so that the volatility of the portfolio tracks
+/// `target`, levering up in calm periods and down in turbulent ones. entries are sized
+/// with the current leverage and the open positions resized on every bar it moved
+#[derive(Debug, Clone, Copy)]
+pub struct VolTarget {
+    /// per-period volatility of the returns of the equity curve to aim for
+    pub target: f64,
+    /// number of trailing returns the realized volatility is measured over
+    pub window: usize,
+    /// cap of the scaling, e.g. 2 to at most double the sizes
+    pub max_leverage: f64,
+    /// relative change of the leverage a position is left alone within, to avoid
+    /// trading on every bar
+    pub band: f64,
 }
 
+/// equity of the portfolio at the end of a timestamp, what a `LiquidationTrigger` sees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EquityView {
+    pub time: DateTime,
+    pub equity: f64,
+    /// highest equity so far, at least the initial cash
+    pub peak: f64,
+    /// decline of the equity from the peak, as a ratio
+    pub drawdown: f64,
+}
+
+/// decides from the equity of the portfolio whether to flatten the whole book
+pub type LiquidationTrigger = Arc<dyn Fn(&EquityView) -> bool + Send + Sync>;
+
 #[derive(Builder, Clone)]
 pub struct SimplePortfolio<T> {
     #[builder(setter(custom))]
@@ -23,8 +131,125 @@ pub struct SimplePortfolio<T> {
     pub cash: f64,
     // TODO extract order_manager
     order_manager: T,
+    /// sells without a long position open or add to shorts, otherwise they are ignored
+    #[builder(default)]
+    pub allow_short: bool,
+    /// a sell closes the whole long position instead of selling its size, and sells
+    /// while flat are ignored, so that a long-only strategy flipping its signal goes
+    /// flat and stays there until it buys again
+    #[builder(default)]
+    pub flat_on_reverse: bool,
+    #[builder(default)]
+    pub fill_policy: FillPolicy,
+    #[builder(default)]
+    pub cash_policy: CashPolicy,
+    #[builder(default)]
+    pub stale_price_policy: StalePricePolicy,
+    /// positions left smaller than this by a fill reducing them are closed at the fill
+    /// price, e.g. odd lots after selling a fraction
+    #[builder(default)]
+    pub dust_threshold: Qty,
+    /// reject new buys once this many positions are open
+    #[builder(default)]
+    pub max_open_positions: Option<usize>,
+    /// open at most this many fresh positions per timestamp, the strongest decisions
+    /// first. the orders are ranked when executed, so it needs them to execute after
+    /// every decision of the timestamp is made, i.e. without `same_bar_fill` and with the
+    /// gamblers stepping in time order, see `Casino::run_ordered`
+    #[builder(default)]
+    pub max_new_positions_per_bar: Option<usize>,
+    /// symbol and strength of the decisions opening a position, for the last two
+    /// timestamps
+    #[builder(setter(skip))]
+    entry_requests: VecDeque<(DateTime, Vec<(Symbol, f64)>)>,
+    /// ignore sells and closes until a position has been held this many bars
+    #[builder(default)]
+    pub min_holding_bars: usize,
+    /// ignore buys for this many bars after a losing round trip
+    #[builder(default)]
+    pub cooldown_bars: usize,
+    /// reject buys while gross exposure over equity is above this
+    #[builder(default)]
+    pub max_leverage: Option<f64>,
+    /// while above `max_leverage`, answer any decision but an exit on a held symbol with
+    /// an order shrinking its position back to the cap
+    #[builder(default)]
+    pub deleverage: bool,
+    #[builder(default)]
+    pub vol_target: Option<VolTarget>,
+    /// leverage every position was last sized with by `vol_target`
+    #[builder(setter(skip))]
+    vol_scales: HashMap<Symbol, f64>,
+    /// quoted spread to mark positions at bid and ask instead of the close
+    #[builder(default)]
+    pub mark_spread: f64,
+    /// price of the bar the positions and so the equity curve are marked at
+    #[builder(default)]
+    pub mark_price: MarkSource,
+    /// prices to mark and size positions with until their first bar
+    #[builder(default)]
+    pub initial_prices: HashMap<Symbol, f64>,
+    /// how the positions are marked and their closed lots settled, linearly by default
+    #[builder(default, setter(custom))]
+    pub valuation: Option<Arc<dyn Valuation>>,
+    #[builder(setter(skip))]
+    liquidation_trigger: Option<LiquidationTrigger>,
+    /// highest equity at the end of a timestamp
+    #[builder(setter(skip))]
+    peak_equity: f64,
+    /// the liquidation trigger fired, every decision closes its position from now on
+    #[builder(setter(skip))]
+    pub liquidating: bool,
+    /// while liquidating, still open positions in flat symbols instead of ignoring the
+    /// entries, e.g. to keep trading after a partial kill-switch
+    #[builder(default)]
+    pub entries_while_liquidating: bool,
     #[builder(setter(skip))]
     pub positions: HashMap<Symbol, Position>,
+    /// total equity, one point per timestamp
+    #[builder(setter(skip))]
+    pub equity_curve: Vec<(DateTime, f64)>,
+    /// cash at every point of the equity curve
+    #[builder(setter(skip))]
+    pub cash_curve: Vec<(DateTime, f64)>,
+    /// fraction of the unrealized gains left out of the risk equity, e.g. 0.5 to count
+    /// paper profits at half for the risk drawdown. the reported equity is unaffected
+    #[builder(default)]
+    pub unrealized_gain_discount: f64,
+    /// equity with the unrealized gains discounted, one point per timestamp. only kept
+    /// with an `unrealized_gain_discount`
+    #[builder(setter(skip))]
+    pub risk_equity_curve: Vec<(DateTime, f64)>,
+    /// cash received from dividends
+    #[builder(setter(skip))]
+    pub income: f64,
+    /// external deposits, positive, and withdrawals, negative
+    #[builder(setter(skip))]
+    pub cash_flows: Vec<(DateTime, f64)>,
+    /// commission paid on all fills
+    #[builder(setter(skip))]
+    pub fees_paid: f64,
+    /// orders rejected, canceled, expired or left unfilled, as reported by the gamblers
+    #[builder(setter(skip))]
+    pub rejections: usize,
+    /// accumulate sharpe and drawdown on the fly instead of keeping the equity curve
+    #[builder(default)]
+    pub streaming_stats: bool,
+    /// per-period risk-free rate subtracted from returns for the sharpe ratio
+    #[builder(default)]
+    pub risk_free_rate: f64,
+    /// time-varying per-period risk-free rate, sorted by time. a rate holds from its time
+    /// until the next one, `risk_free_rate` applies before the first
+    #[builder(default)]
+    pub risk_free: Vec<(DateTime, f64)>,
+    /// closes of an index, sorted by time, the stats are compared against
+    #[builder(default)]
+    pub benchmark: Vec<(DateTime, f64)>,
+    #[builder(setter(skip))]
+    streaming: metrics::StreamingStats,
+    /// latest equity point, pushed to `streaming` once its timestamp is over
+    #[builder(setter(skip))]
+    last_equity: Option<(DateTime, f64)>,
 }
 
 impl<T> SimplePortfolioBuilder<T> {
@@ -33,38 +258,643 @@ impl<T> SimplePortfolioBuilder<T> {
         self.init_cash = Some(value);
         self
     }
+
+    pub fn valuation(&mut self, v: impl Valuation + 'static) -> &mut Self {
+        self.valuation = Some(Some(Arc::new(v)));
+        self
+    }
 }
 
 impl<T> SimplePortfolio<T> {
     fn get_position_mut(&mut self, sym: &str) -> &mut Position {
-        self.positions.entry(sym.to_owned()).or_insert_with(|| {
-            let mut p = Position::default();
-            p.sym = sym.to_owned();
-            p
-        })
+        let initial_price = self.initial_prices.get(sym).copied();
+        self.positions
+            .entry(sym.to_owned())
+            .or_insert_with(|| Position {
+                sym: sym.to_owned(),
+                latest_market_close: initial_price,
+                ..Default::default()
+            })
+    }
+
+    /// the settings the positions are booked and marked with
+    pub fn position_settings(&self) -> PositionSettings {
+        PositionSettings {
+            allow_short: self.allow_short,
+            mark_source: self.mark_price,
+            mark_spread: self.mark_spread,
+            valuation: self.valuation.clone(),
+        }
+    }
+
+    /// check `f` against the equity at the end of every timestamp. once it fires, the
+    /// gamblers close their open positions on the next bar, any decision on a held
+    /// symbol is answered with a market order closing it and any other decision is
+    /// rejected
+    pub fn set_liquidation_trigger(
+        &mut self,
+        f: impl Fn(&EquityView) -> bool + Send + Sync + 'static,
+    ) {
+        self.liquidation_trigger = Some(Arc::new(f));
+    }
+
+    /// flag the open positions without a bar at `time` and mark them per the policy
+    fn mark_stale(&mut self, time: DateTime) {
+        let recovery = match self.stale_price_policy {
+            StalePricePolicy::Carry => return,
+            StalePricePolicy::Flag => None,
+            StalePricePolicy::Recover(x) => Some(x),
+        };
+        let settings = self.position_settings();
+        for pos in self.positions.values_mut() {
+            let behind = pos.latest_market_time.is_some_and(|x| x < time);
+            if pos.stale || !behind || pos.qty.is_zero() {
+                continue;
+            }
+            log::warn!("no bar of {} at {}, position is stale", pos.sym, time);
+            pos.mark_stale(recovery, &settings);
+        }
+    }
+
+    /// a sell with no long position to reduce
+    fn is_short_sell(&self, decision: &Decision) -> bool {
+        let qty = self.positions.get(&decision.sym).map_or(Qty(0), |x| x.qty);
+        matches!(decision.kind, DecisionKind::Sell) && qty <= 0
+    }
+
+    /// cash plus positions marked at their latest close
+    pub fn equity(&self) -> f64 {
+        self.cash + self.net_exposure()
+    }
+
+    /// risk-free rate of the period ending at `time`
+    fn risk_free_at(&self, time: DateTime) -> f64 {
+        match self.risk_free.partition_point(|x| x.0 <= time) {
+            0 => self.risk_free_rate,
+            i => self.risk_free[i - 1].1,
+        }
+    }
+
+    /// per-period returns of the equity curve in excess of the risk-free rate
+    fn excess_returns(&self) -> Vec<f64> {
+        self.equity_curve
+            .windows(2)
+            .map(|w| w[1].1 / w[0].1 - 1.0 - self.risk_free_at(w[1].0))
+            .collect()
+    }
+
+    /// equity less `unrealized_gain_discount` of the unrealized gains, losses count in full
+    pub fn risk_equity(&self) -> f64 {
+        let settings = self.position_settings();
+        let gains = self
+            .positions
+            .values()
+            .map(|x| x.unrealized_pnl(&settings).max(0.0))
+            .sum::<f64>();
+        self.equity() - self.unrealized_gain_discount * gains
+    }
+
+    fn record_equity(&mut self, time: DateTime) {
+        if self.unrealized_gain_discount != 0.0 {
+            let risk_equity = self.risk_equity();
+            match self.risk_equity_curve.last_mut() {
+                Some((t, v)) if *t == time => *v = risk_equity,
+                _ => self.risk_equity_curve.push((time, risk_equity)),
+            }
+        }
+        let equity = self.equity();
+        if self.streaming_stats {
+            match self.last_equity {
+                Some((t, _)) if t == time => {}
+                Some((t, v)) => {
+                    let rf = self.risk_free_at(t);
+                    self.streaming.push_excess(v, rf)
+                }
+                None => {}
+            }
+            self.last_equity = Some((time, equity));
+            return;
+        }
+        match self.equity_curve.last_mut() {
+            Some((t, v)) if *t == time => *v = equity,
+            _ => self.equity_curve.push((time, equity)),
+        }
+        match self.cash_curve.last_mut() {
+            Some((t, v)) if *t == time => *v = self.cash,
+            _ => self.cash_curve.push((time, self.cash)),
+        }
+    }
+
+    /// mark-to-market gain of all open positions over their cost basis
+    pub fn unrealized_pnl(&self) -> f64 {
+        let settings = self.position_settings();
+        self.positions
+            .values()
+            .map(|x| x.unrealized_pnl(&settings))
+            .sum()
+    }
+
+    /// add external cash, recorded as a flow so that it does not count as performance
+    pub fn deposit(&mut self, time: DateTime, amount: f64) {
+        self.cash += amount;
+        self.cash_flows.push((time, amount));
+    }
+
+    /// take external cash out, failing if the cash is short of `amount`
+    pub fn withdraw(&mut self, time: DateTime, amount: f64) -> Result<(), ErrorRepr> {
+        if amount > self.cash {
+            return Err(ErrorRepr::OutOfBounds(format!(
+                "cannot withdraw {:.2} with cash {:.2}",
+                amount, self.cash
+            )));
+        }
+        self.cash -= amount;
+        self.cash_flows.push((time, -amount));
+        Ok(())
+    }
+
+    /// a market order closing the position of `sym` at `time`, to be sent outside of the
+    /// strategy, e.g. from an event hook
+    pub fn flatten(&self, sym: &str, time: DateTime) -> Option<Order> {
+        let qty = self.positions.get(sym).map_or(Qty(0), |x| x.qty);
+        if qty.is_zero() {
+            return None;
+        }
+        let ord = OrderBuilder::default()
+            .sym(sym.to_owned())
+            .qty(-qty)
+            .time(time)
+            .build()
+            .unwrap();
+        Some(ord)
+    }
+
+    /// signed market value of every position at its mark price
+    fn market_values(&self) -> impl Iterator<Item = f64> + '_ {
+        let settings = self.position_settings();
+        self.positions
+            .values()
+            .map(move |x| x.market_value(&settings))
+    }
+
+    /// sum of the absolute market values of the positions
+    pub fn gross_exposure(&self) -> f64 {
+        self.market_values().map(f64::abs).sum()
+    }
+
+    /// sum of the signed market values of the positions, shorts count negative
+    pub fn net_exposure(&self) -> f64 {
+        self.market_values().sum()
+    }
+
+    /// `gross_exposure` as a fraction of equity, 0 once the equity is gone
+    pub fn gross_exposure_ratio(&self) -> f64 {
+        per_equity(self.gross_exposure(), self.equity())
+    }
+
+    /// `net_exposure` as a fraction of equity, 0 once the equity is gone
+    pub fn net_exposure_ratio(&self) -> f64 {
+        per_equity(self.net_exposure(), self.equity())
+    }
+
+    pub fn positions_sorted_by(&self, key: PositionKey) -> Vec<Position> {
+        let mut positions = self.positions.values().cloned().collect::<Vec<_>>();
+        let settings = self.position_settings();
+        let exposure = |x: &Position| x.market_value(&settings).abs();
+        match key {
+            PositionKey::Pnl => {
+                positions.sort_by(|a, b| b.pnl(&settings).total_cmp(&a.pnl(&settings)))
+            }
+            PositionKey::PnlRatio => {
+                positions.sort_by(|a, b| b.stats.pnl_ratio.total_cmp(&a.stats.pnl_ratio))
+            }
+            PositionKey::Exposure => positions.sort_by(|a, b| exposure(b).total_cmp(&exposure(a))),
+            PositionKey::Sharpe => {
+                positions.sort_by(|a, b| b.stats.sharpe().total_cmp(&a.stats.sharpe()))
+            }
+            PositionKey::Symbol => positions.sort_by(|a, b| a.sym.cmp(&b.sym)),
+        }
+        positions
+    }
+
+    pub fn open_positions(&self) -> usize {
+        self.positions.values().filter(|x| x.qty != 0).count()
+    }
+
+    fn is_position_limit_reached(&self, decision: &Decision) -> bool {
+        let Some(max) = self.max_open_positions else {
+            return false;
+        };
+        let is_open = self
+            .positions
+            .get(&decision.sym)
+            .is_some_and(|x| x.qty != 0);
+        matches!(decision.kind, DecisionKind::Buy) && !is_open && self.open_positions() >= max
+    }
+
+    /// remember a decision opening a position to rank it against the others of its time
+    fn request_entry(&mut self, decision: &Decision) {
+        if self.max_new_positions_per_bar.is_none() {
+            return;
+        }
+        let entry = (decision.sym.clone(), decision.strength);
+        match self
+            .entry_requests
+            .iter_mut()
+            .find(|x| x.0 == decision.time)
+        {
+            Some((_, requests)) => requests.push(entry),
+            None => {
+                self.entry_requests.push_back((decision.time, vec![entry]));
+                if self.entry_requests.len() > 2 {
+                    self.entry_requests.pop_front();
+                }
+            }
+        }
+    }
+
+    fn is_cooling_down(&self, decision: &Decision) -> bool {
+        let since = self
+            .positions
+            .get(&decision.sym)
+            .and_then(|x| x.bars_since_loss());
+        matches!(decision.kind, DecisionKind::Buy) && since.is_some_and(|x| x < self.cooldown_bars)
+    }
+
+    /// a market order resizing the position of `sym` to the current `vol_leverage`, if it
+    /// moved out of the band since the position was last sized
+    fn resize_order(&mut self, sym: &str, time: DateTime) -> Option<Order> {
+        let vt = self.vol_target?;
+        let qty = self.positions.get(sym).map_or(Qty(0), |x| x.qty);
+        if qty.is_zero() {
+            self.vol_scales.remove(sym);
+            return None;
+        }
+        let scale = *self.vol_scales.get(sym)?;
+        let leverage = self.vol_leverage();
+        if (leverage / scale - 1.0).abs() <= vt.band {
+            return None;
+        }
+        let target = Qty::whole(f64::from(qty) * leverage / scale);
+        if target == qty {
+            return None;
+        }
+        self.vol_scales.insert(sym.to_owned(), leverage);
+        let ord = OrderBuilder::default()
+            .sym(sym.to_owned())
+            .qty(target - qty)
+            .time(time)
+            .build()
+            .unwrap();
+        Some(ord)
+    }
+
+    /// factor the positions are sized with by `vol_target`: the target over the realized
+    /// volatility of the trailing returns, 1 until there are enough of them
+    pub fn vol_leverage(&self) -> f64 {
+        let Some(vt) = self.vol_target else {
+            return 1.0;
+        };
+        let start = self.equity_curve.len().saturating_sub(vt.window + 1);
+        let equity = self.equity_curve[start..]
+            .iter()
+            .map(|x| x.1)
+            .collect::<Vec<_>>();
+        let realized = metrics::std(&metrics::returns(&equity));
+        if equity.len() <= vt.window || realized <= 0.0 {
+            return 1.0;
+        }
+        (vt.target / realized).min(vt.max_leverage)
+    }
+
+    /// fraction of the positions to sell to get back to `max_leverage`, if above it
+    fn excess_leverage(&self) -> Option<f64> {
+        let max = self.max_leverage?;
+        if self.equity() <= 0.0 {
+            // no equity left to carry any of it
+            return (self.gross_exposure() > 0.0).then_some(1.0);
+        }
+        let ratio = self.gross_exposure_ratio();
+        (ratio > max).then(|| 1.0 - max / ratio)
+    }
+
+    /// an order shrinking the position of `sym` by `fraction`, rounded away from zero
+    fn reduce_order(&self, sym: &str, fraction: f64, time: DateTime) -> Option<Order> {
+        let qty = self.positions.get(sym).map_or(Qty(0), |x| x.qty);
+        // tolerate rounding noise so that an exact fraction is not rounded up a share
+        let reduce =
+            Qty((f64::from(qty.abs()) * fraction - TOLERANCE).ceil() as i32).min(qty.abs());
+        if reduce.is_zero() {
+            return None;
+        }
+        let ord = OrderBuilder::default()
+            .sym(sym.to_owned())
+            .qty(Qty(-reduce.0 * qty.signum()))
+            .time(time)
+            .build()
+            .unwrap();
+        Some(ord)
+    }
+
+    fn is_held_too_short(&self, decision: &Decision) -> bool {
+        let held = self
+            .positions
+            .get(&decision.sym)
+            .and_then(|x| x.bars_held());
+        matches!(
+            decision.kind,
+            DecisionKind::Sell | DecisionKind::SellFraction(_) | DecisionKind::Close
+        ) && held.is_some_and(|x| x < self.min_holding_bars)
     }
 }
 
-impl<T> PositionManager for SimplePortfolio<T> {
+impl<T: OrderManager> PositionManager for SimplePortfolio<T> {
     fn update_from_fill(&mut self, fill: &Fill) -> Result<(), ErrorRepr> {
+        let (allow_short, fill_policy) = (self.allow_short, self.fill_policy);
         let pos = self.get_position_mut(&fill.sym);
-        pos.update_from_fill(fill)
+        let held = pos.qty;
+        let fill = match fill_policy {
+            FillPolicy::Clamp if !allow_short && held + fill.qty < 0 => {
+                let excess = fill.qty + held;
+                let excess_cost = fill.cost * excess.0 as f64 / fill.qty.0 as f64;
+                log::warn!("oversized fill clamped by {}: {:?}", excess, fill);
+                // undo what the broker paid for the excess
+                self.cash += (excess * fill.price).0 + excess_cost;
+                &Fill {
+                    qty: -held,
+                    cost: fill.cost - excess_cost,
+                    ..fill.clone()
+                }
+            }
+            _ => fill,
+        };
+        let affordable;
+        let fill = match self.cash_policy {
+            CashPolicy::Clamp if fill.qty > 0 && self.cash < -TOLERANCE => {
+                let budget = self.cash + (fill.qty * fill.price).0 + fill.cost;
+                let per_unit = fill.price.0 + fill.cost / f64::from(fill.qty);
+                let qty = Qty((budget / per_unit).floor().max(0.0) as i32);
+                let cost = fill.cost * f64::from(qty) / f64::from(fill.qty);
+                log::warn!("over-budget fill clamped to {}: {:?}", qty, fill);
+                self.cash = budget - (qty * fill.price).0 - cost;
+                if qty.is_zero() {
+                    return Ok(());
+                }
+                affordable = Fill {
+                    qty,
+                    cost,
+                    ..fill.clone()
+                };
+                &affordable
+            }
+            _ => fill,
+        };
+        let (dust_threshold, settings) = (self.dust_threshold, self.position_settings());
+        let pos = self.get_position_mut(&fill.sym);
+        let (settled, before) = (pos.stats.valuation_pnl, pos.qty);
+        pos.update_from_fill(fill, &settings)?;
+        let dust = pos.qty;
+        // only what is left of a reduced position, a small new one is kept
+        if !dust.is_zero() && dust.abs() < before.abs() && dust.abs() < dust_threshold {
+            log::debug!("sweeping {} {} of dust", dust, fill.sym);
+            pos.update_from_fill(
+                &Fill {
+                    qty: -dust,
+                    cost: 0.0,
+                    ..fill.clone()
+                },
+                &settings,
+            )?;
+            self.cash += (dust * fill.price).0;
+        }
+        // the broker settles at `qty * price`, the valuation pays the rest of the pnl
+        self.cash += self.positions[&fill.sym].stats.valuation_pnl - settled;
+        self.fees_paid += fill.cost;
+        self.order_manager.update_from_fill(fill);
+        Ok(())
+    }
+
+    fn apply_external_fill(&mut self, fill: &Fill) -> Result<(), ErrorRepr> {
+        self.cash -= (fill.qty * fill.price).0 + fill.cost;
+        self.update_from_fill(fill)
     }
 
     fn update_from_market(&mut self, data: &Bar) -> Result<(), ErrorRepr> {
+        let settings = self.position_settings();
         let pos = self.get_position_mut(&data.sym);
-        pos.update_from_market(data.clone());
+        let income = f64::from(pos.qty) * data.dividend;
+        pos.update_from_market(data.clone(), &settings);
+        self.cash += income;
+        self.income += income;
+        self.order_manager.update_from_market(data);
         Ok(())
     }
+
+    fn mark_timestamp(&mut self, time: DateTime) {
+        self.mark_stale(time);
+        self.record_equity(time);
+        let equity = self.equity();
+        // the starting capital counts as a peak
+        self.peak_equity = self.peak_equity.max(self.init_cash).max(equity);
+        if let (false, Some(f)) = (self.liquidating, &self.liquidation_trigger) {
+            let view = EquityView {
+                time,
+                equity,
+                peak: self.peak_equity,
+                drawdown: 1.0 - equity / self.peak_equity,
+            };
+            if f(&view) {
+                log::warn!("liquidation triggered at {}", time);
+                self.liquidating = true;
+            }
+        }
+    }
+
+    fn closed_trade(&self, fill: &Fill) -> Option<Trade> {
+        let pos = self.positions.get(&fill.sym)?;
+        if pos.trade_closed {
+            pos.trades.last().cloned()
+        } else {
+            None
+        }
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some(self.stats().summary())
+    }
 }
 
 impl<T: OrderManager> OrderAllocator for SimplePortfolio<T> {
-    fn allocate_order(
-        &mut self,
-        decision: &crate::strategy::Decision,
-    ) -> Result<Option<crate::order::Order>, ErrorRepr> {
-        self.order_manager
-            .make_order(decision, self.positions.get(&decision.sym))
+    fn allocate_order(&mut self, decision: &Decision) -> Result<Option<Order>, ErrorRepr> {
+        if self.liquidating {
+            let is_flat = self
+                .positions
+                .get(&decision.sym)
+                .is_none_or(|x| x.qty.is_zero());
+            let is_entry = matches!(decision.kind, DecisionKind::Buy | DecisionKind::Target(_));
+            if !(self.entries_while_liquidating && is_flat && is_entry) {
+                if is_flat && is_entry {
+                    log::debug!("liquidating, ignore entry: {:?}", decision);
+                }
+                return Ok(self.reduce_order(&decision.sym, 1.0, decision.time));
+            }
+        }
+        if let Some(excess) = self.excess_leverage() {
+            // exits go through as they are, they reduce the book as well
+            let is_exit = matches!(
+                decision.kind,
+                DecisionKind::Sell | DecisionKind::Close | DecisionKind::SellFraction(_)
+            );
+            if self.deleverage && !is_exit {
+                if let Some(ord) = self.reduce_order(&decision.sym, excess, decision.time) {
+                    log::warn!("max leverage exceeded, force reduction: {:?}", ord);
+                    return Ok(Some(ord));
+                }
+            }
+            if matches!(decision.kind, DecisionKind::Buy | DecisionKind::Target(_)) {
+                log::warn!("max leverage exceeded, reject decision: {:?}", decision);
+                return Ok(None);
+            }
+        }
+        if self.is_position_limit_reached(decision) {
+            log::warn!(
+                "max open positions reached, reject decision: {:?}",
+                decision
+            );
+            return Ok(None);
+        }
+        if self.is_cooling_down(decision) {
+            log::debug!("cooling down after a loss, ignore decision: {:?}", decision);
+            return Ok(None);
+        }
+        if self.is_held_too_short(decision) {
+            log::debug!(
+                "min holding period not reached, ignore decision: {:?}",
+                decision
+            );
+            return Ok(None);
+        }
+        let clamped;
+        let decision = match decision.kind {
+            DecisionKind::Target(x) if x < 0.0 && !self.allow_short => {
+                log::info!("shorts not allowed, clamp target to 0: {:?}", decision);
+                clamped = Decision {
+                    kind: DecisionKind::Target(0.0),
+                    ..decision.clone()
+                };
+                &clamped
+            }
+            DecisionKind::Target(x) if self.vol_target.is_some() => {
+                clamped = Decision {
+                    kind: DecisionKind::Target(x * self.vol_leverage()),
+                    ..decision.clone()
+                };
+                let leverage = self.vol_leverage();
+                self.vol_scales.insert(decision.sym.clone(), leverage);
+                &clamped
+            }
+            DecisionKind::Sell if self.flat_on_reverse => {
+                let held = self.positions.get(&decision.sym).map_or(Qty(0), |x| x.qty);
+                if held <= Qty(0) {
+                    log::debug!("flat on reverse, ignore decision: {:?}", decision);
+                    return Ok(None);
+                }
+                clamped = Decision {
+                    kind: DecisionKind::Close,
+                    ..decision.clone()
+                };
+                &clamped
+            }
+            _ => decision,
+        };
+
+        let is_short_sell = self.is_short_sell(decision);
+        if is_short_sell && !self.allow_short {
+            log::info!("no position to sell, ignore decision: {:?}", decision);
+            return Ok(None);
+        }
+
+        if self.initial_prices.contains_key(&decision.sym) {
+            // let the order manager size it with the initial price
+            self.get_position_mut(&decision.sym);
+        }
+        let view = PortfolioView {
+            cash: self.cash,
+            position: self.positions.get(&decision.sym),
+        };
+        let ord = if is_short_sell {
+            // shorts are sized like buys
+            let buy = Decision {
+                kind: DecisionKind::Buy,
+                ..decision.clone()
+            };
+            self.order_manager.make_order(&buy, &view)?.map(|mut x| {
+                x.qty = -x.qty;
+                x
+            })
+        } else {
+            self.order_manager.make_order(decision, &view)?
+        };
+        let is_sized = is_short_sell || matches!(decision.kind, DecisionKind::Buy);
+        let ord = match ord {
+            Some(mut x) if is_sized && self.vol_target.is_some() => {
+                let leverage = self.vol_leverage();
+                x.qty = Qty::whole(f64::from(x.qty) * leverage);
+                self.vol_scales.insert(decision.sym.clone(), leverage);
+                (!x.qty.is_zero()).then_some(x)
+            }
+            x => x,
+        };
+        let is_entry = self
+            .positions
+            .get(&decision.sym)
+            .is_none_or(|x| x.qty.is_zero());
+        if is_entry && ord.as_ref().is_some_and(|x| !x.qty.is_zero()) {
+            self.request_entry(decision);
+        }
+        Ok(ord.map(|mut x| {
+            x.time = decision.time;
+            x
+        }))
+    }
+
+    fn record_skip(&mut self, reason: SkipReason) {
+        use SkipReason::*;
+        if matches!(reason, Unfilled | Expired | Rejected | Canceled) {
+            self.rejections += 1;
+        }
+    }
+
+    fn flatten(&self, sym: &str, time: DateTime) -> Option<Order> {
+        SimplePortfolio::flatten(self, sym, time)
+    }
+
+    fn is_liquidating(&self) -> bool {
+        self.liquidating
+    }
+
+    fn rebalance(&mut self, sym: &str, time: DateTime) -> Option<Order> {
+        self.resize_order(sym, time)
+    }
+
+    fn admit(&mut self, ord: &Order) -> bool {
+        let Some(max) = self.max_new_positions_per_bar else {
+            return true;
+        };
+        let Some((_, requests)) = self.entry_requests.iter().find(|x| x.0 == ord.time) else {
+            return true;
+        };
+        let Some(i) = requests.iter().position(|x| x.0 == ord.sym) else {
+            return true;
+        };
+        // stronger ones first, then the earlier ones
+        let strength = requests[i].1;
+        let rank = requests
+            .iter()
+            .enumerate()
+            .filter(|(j, x)| x.1 > strength || (x.1 == strength && *j < i))
+            .count();
+        rank < max
     }
 }
 
@@ -75,6 +905,9 @@ impl<T> Wallet for SimplePortfolio<T> {
     fn set_balance(&mut self, money: f64) {
         self.cash = money;
     }
+    fn allows_short(&self) -> bool {
+        self.allow_short
+    }
 }
 
 pub trait Statistics {
@@ -82,101 +915,2290 @@ pub trait Statistics {
     fn stats(&self) -> Self::Stats;
 }
 
-#[derive(Debug, Default, Serialize)]
+/// calendar buckets the pnl is attributed to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Period {
+    #[default]
+    Year,
+    Quarter,
+}
+
+impl Period {
+    /// label of the period `time` falls in, e.g. `2020` or `2020Q3`, sorting
+    /// chronologically
+    pub fn label(&self, time: DateTime) -> String {
+        use chrono::Datelike;
+        match self {
+            Period::Year => time.year().to_string(),
+            Period::Quarter => format!("{}Q{}", time.year(), time.month0() / 3 + 1),
+        }
+    }
+}
+
+/// closed round trips as one vector per field, aligned by index, e.g. for a dataframe
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TradesColumns {
+    pub sym: Vec<Symbol>,
+    pub entry_time: Vec<DateTime>,
+    pub exit_time: Vec<DateTime>,
+    pub bars: Vec<usize>,
+    pub qty: Vec<i32>,
+    pub pnl: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct PortfolioStats {
     pub pnl: f64,
+    /// pnl before fees
+    pub gross_pnl: f64,
+    pub fees_paid: f64,
+    /// orders rejected, canceled, expired or left unfilled
+    pub rejections: usize,
+    /// pnl of closed quantities
+    pub realized_pnl: f64,
+    /// pnl of positions still open
+    pub unrealized_pnl: f64,
+    /// signed market value of the positions at their mark price
+    pub market_value: f64,
     pub init_cash: f64,
     pub cash: f64,
     pub pnl_ratio: f64,
+    /// per-period sharpe ratio of the equity curve
+    pub sharpe: f64,
+    /// largest decline of the equity curve from its peak, as a ratio
+    pub max_drawdown: f64,
+    /// largest decline of the equity curve from its peak, in currency. not necessarily
+    /// the same decline as `max_drawdown`
+    pub max_drawdown_abs: f64,
+    /// `max_drawdown` of the risk equity, with the unrealized gains discounted
+    pub risk_max_drawdown: f64,
+    /// cash received from dividends
+    pub income: f64,
+    pub cash_flows: Vec<(DateTime, f64)>,
+    /// return of the equity including income and external cash flows
+    pub total_return: f64,
+    /// return of the equity from price changes only
+    pub price_return: f64,
     pub positions: Vec<Position>,
+    pub equity_curve: Vec<(DateTime, f64)>,
+    /// cash at every point of `equity_curve`
+    pub cash_curve: Vec<(DateTime, f64)>,
+    /// empty without an unrealized gain discount
+    pub risk_equity_curve: Vec<(DateTime, f64)>,
+    /// comparison against the portfolio's benchmark, if it has one
+    pub benchmark: Option<BenchmarkStats>,
+}
+
+/// performance of the equity curve relative to a benchmark over the span they overlap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct BenchmarkStats {
+    /// per-period return not explained by the benchmark
+    pub alpha: f64,
+    pub beta: f64,
+    /// total return minus the benchmark's
+    pub excess_return: f64,
 }
 
 impl PortfolioStats {
     pub fn printstd(&self) {
         println!("{:#?}", self);
     }
-}
 
-impl<T> Statistics for SimplePortfolio<T> {
-    type Stats = PortfolioStats;
+    fn equity(&self) -> Vec<f64> {
+        self.equity_curve.iter().map(|x| x.1).collect()
+    }
 
-    fn stats(&self) -> Self::Stats {
-        let mut positions = self.positions.values().cloned().collect::<Vec<_>>();
-        positions.sort_by(|a, b| b.stats.pnl_ratio.partial_cmp(&a.stats.pnl_ratio).unwrap());
+    /// a copy with the amounts rounded to `decimals` for display, e.g. 2 for cents
+    pub fn rounded(&self, decimals: i32) -> PortfolioStats {
+        let scale = 10f64.powi(decimals);
+        let round = |x: f64| (x * scale).round() / scale;
+        let mut stats = self.clone();
+        for x in [
+            &mut stats.pnl,
+            &mut stats.gross_pnl,
+            &mut stats.fees_paid,
+            &mut stats.realized_pnl,
+            &mut stats.unrealized_pnl,
+            &mut stats.init_cash,
+            &mut stats.cash,
+            &mut stats.max_drawdown_abs,
+            &mut stats.income,
+        ] {
+            *x = round(*x);
+        }
+        stats.cash_flows.iter_mut().for_each(|x| x.1 = round(x.1));
+        stats
+    }
+
+    /// final equity, total return, number of round trips and max drawdown in one line
+    pub fn summary(&self) -> String {
+        format!(
+            "equity {:.2}, return {:.2}%, {} trades, max drawdown {:.2}%",
+            self.init_cash * (1.0 + self.total_return),
+            self.total_return * 100.0,
+            self.trades().len(),
+            self.max_drawdown * 100.0
+        )
+    }
+
+    /// one report over several portfolios: amounts are summed and the equity curves are
+    /// added up at every timestamp, each portfolio holding its last point in between
+    pub fn combine(parts: Vec<PortfolioStats>) -> PortfolioStats {
+        let mut times = parts
+            .iter()
+            .flat_map(|x| x.equity_curve.iter().map(|p| p.0))
+            .collect::<Vec<_>>();
+        times.sort();
+        times.dedup();
+        let add_up = |curve: fn(&PortfolioStats) -> &[(DateTime, f64)]| {
+            times
+                .iter()
+                .map(|&t| {
+                    let value = parts
+                        .iter()
+                        .map(|x| match curve(x).partition_point(|p| p.0 <= t) {
+                            0 => x.init_cash,
+                            i => curve(x)[i - 1].1,
+                        })
+                        .sum();
+                    (t, value)
+                })
+                .collect::<Vec<_>>()
+        };
+        let equity_curve = add_up(|x| &x.equity_curve);
+        let cash_curve = add_up(|x| &x.cash_curve);
+        let equity = equity_curve.iter().map(|x| x.1).collect::<Vec<_>>();
+
+        let sum = |f: fn(&PortfolioStats) -> f64| parts.iter().map(f).sum::<f64>();
+        let (pnl, init_cash, income) = (sum(|x| x.pnl), sum(|x| x.init_cash), sum(|x| x.income));
+        let end = sum(|x| x.init_cash * (1.0 + x.total_return));
+        let mut cash_flows = parts
+            .iter()
+            .flat_map(|x| x.cash_flows.iter().cloned())
+            .collect::<Vec<_>>();
+        cash_flows.sort_by_key(|x| x.0);
 
-        let pnl = positions.iter().map(|x| x.stats.pnl).sum();
         PortfolioStats {
             pnl,
-            init_cash: self.init_cash,
-            cash: self.cash,
-            pnl_ratio: pnl / self.init_cash,
-            positions,
+            gross_pnl: sum(|x| x.gross_pnl),
+            fees_paid: sum(|x| x.fees_paid),
+            rejections: parts.iter().map(|x| x.rejections).sum(),
+            realized_pnl: sum(|x| x.realized_pnl),
+            unrealized_pnl: sum(|x| x.unrealized_pnl),
+            init_cash,
+            cash: sum(|x| x.cash),
+            pnl_ratio: pnl / init_cash,
+            sharpe: metrics::sharpe(&metrics::returns(&equity)),
+            max_drawdown: metrics::max_drawdown(&equity),
+            max_drawdown_abs: metrics::max_drawdown_abs(&equity),
+            // the risk curves are not combined, keep the worst part
+            risk_max_drawdown: parts
+                .iter()
+                .map(|x| x.risk_max_drawdown)
+                .fold(0.0, f64::max),
+            income,
+            cash_flows,
+            total_return: end / init_cash - 1.0,
+            price_return: (end - income) / init_cash - 1.0,
+            market_value: sum(|x| x.market_value),
+            positions: parts.into_iter().flat_map(|x| x.positions).collect(),
+            equity_curve,
+            cash_curve,
+            risk_equity_curve: vec![],
+            // the benchmarks of the parts may differ
+            benchmark: None,
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// pnl of every symbol, largest first. the top and bottom contributors are the ends
+    pub fn pnl_by_symbol(&self) -> Vec<(Symbol, f64)> {
+        let mut pnls = HashMap::<&Symbol, f64>::new();
+        for pos in &self.positions {
+            *pnls.entry(&pos.sym).or_default() += pos.stats.pnl;
+        }
+        let mut pnls = pnls
+            .into_iter()
+            .map(|(sym, pnl)| (sym.clone(), pnl))
+            .collect::<Vec<_>>();
+        pnls.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        pnls
+    }
 
-    use super::*;
+    /// pnl of every calendar `period` the equity curve covers, in order: the change of the
+    /// equity from the end of the prior period, or the initial cash, net of the external
+    /// cash flows
+    pub fn pnl_by_period(&self, period: Period) -> Vec<(String, f64)> {
+        let mut pnls: Vec<(String, f64)> = vec![];
+        let mut prev = self.init_cash;
+        let mut flows = self.cash_flows.iter().peekable();
+        for &(time, equity) in &self.equity_curve {
+            let mut flow = 0.0;
+            while let Some(x) = flows.next_if(|x| x.0 <= time) {
+                flow += x.1;
+            }
+            let label = period.label(time);
+            let pnl = equity - prev - flow;
+            match pnls.last_mut() {
+                Some(last) if last.0 == label => last.1 += pnl,
+                _ => pnls.push((label, pnl)),
+            }
+            prev = equity;
+        }
+        pnls
+    }
 
-    #[test]
-    fn test_portfolio_handle_fill() {
-        let mut p = SimplePortfolioBuilder::<Option<()>>::default()
-            .cash(50.0)
-            .order_manager(None)
-            .build()
-            .unwrap();
+    /// round trips of all positions in the order they were closed
+    pub fn trades(&self) -> Vec<&Trade> {
+        let mut trades = self
+            .positions
+            .iter()
+            .flat_map(|x| &x.trades)
+            .collect::<Vec<_>>();
+        trades.sort_by_key(|x| x.exit_time);
+        trades
+    }
 
-        // let fill = build_test_fill(10, 5.0, 1.0);
-        // assert!(matches!(p.update_from_fill(&fill), Err(_)));
+    /// `trades` in columns
+    pub fn trades_columnar(&self) -> TradesColumns {
+        let mut cols = TradesColumns::default();
+        for trade in self.trades() {
+            cols.sym.push(trade.sym.clone());
+            cols.entry_time.push(trade.entry_time);
+            cols.exit_time.push(trade.exit_time);
+            cols.bars.push(trade.bars);
+            cols.qty.push(trade.qty.0);
+            cols.pnl.push(trade.pnl);
+        }
+        cols
+    }
 
-        let fill = build_test_fill(10, 5.0, 0.0);
-        assert!(matches!(p.update_from_fill(&fill), Ok(_)));
-        // assert_eq!(p.cash, 0.0);
-        assert_eq!(p.init_cash, 50.0);
+    /// longest run of consecutive round trips satisfying `f`
+    fn longest_streak(&self, f: impl Fn(&Trade) -> bool) -> usize {
+        let (mut longest, mut cur) = (0, 0);
+        for trade in self.trades() {
+            cur = if f(trade) { cur + 1 } else { 0 };
+            longest = longest.max(cur);
+        }
+        longest
+    }
 
-        let pos = &p.positions[&fill.sym];
-        assert_eq!(pos.qty, 10);
-        assert_eq!(pos.stats.qty_sold, 0);
-        assert_eq!(pos.stats.qty_bought, 10);
-        assert_eq!(pos.stats.value_sold, 0.0);
-        assert_eq!(pos.stats.value_bought, 50.0);
-        assert_eq!(pos.stats.cost, 0.0);
+    /// longest run of profitable round trips, 0 without trades
+    pub fn max_consecutive_wins(&self) -> usize {
+        self.longest_streak(|x| x.pnl > 0.0)
+    }
 
-        let fill = build_test_fill(-5, 6.0, 1.0);
+    /// longest run of losing round trips, 0 without trades
+    pub fn max_consecutive_losses(&self) -> usize {
+        self.longest_streak(|x| x.pnl < 0.0)
+    }
 
-        assert!(matches!(p.update_from_fill(&fill), Ok(_)));
-        // assert_eq!(p.cash, 29.0);
-        let pos = &p.positions[&fill.sym];
-        assert_eq!(p.init_cash, 50.0);
-        assert_eq!(pos.qty, 5);
-        assert_eq!(pos.stats.qty_sold, 5);
-        assert_eq!(pos.stats.qty_bought, 10);
-        assert_eq!(pos.stats.value_sold, 30.0);
-        assert_eq!(pos.stats.value_bought, 50.0);
-        assert_eq!(pos.stats.cost, 1.0);
-        assert_eq!(
-            p.positions[&fill.sym].pnl(),
-            29.0 + 80.0 / 15.0 * 5.0 - 50.0
+    /// longest run of points of the equity curve without any open position, reconstructed
+    /// from the round trips and the positions still open
+    pub fn max_flat_streak(&self) -> usize {
+        let mut held = self
+            .trades()
+            .iter()
+            .map(|x| (x.entry_time, Some(x.exit_time)))
+            .collect::<Vec<_>>();
+        held.extend(
+            self.positions
+                .iter()
+                .filter(|x| !x.qty.is_zero())
+                .filter_map(|x| x.entry_time.map(|t| (t, None))),
         );
+        let mut longest = 0;
+        let mut streak = 0;
+        for (time, _) in &self.equity_curve {
+            let invested = held
+                .iter()
+                .any(|(entry, exit)| entry <= time && exit.is_none_or(|x| *time < x));
+            streak = if invested { 0 } else { streak + 1 };
+            longest = longest.max(streak);
+        }
+        longest
+    }
 
-        let stats = p.stats();
-        assert_eq!(p.positions[&fill.sym].pnl(), stats.pnl);
+    /// average pnl per round trip, `win_rate * avg_win - loss_rate * avg_loss`. 0 without
+    /// trades
+    pub fn expectancy(&self) -> f64 {
+        let trades = self.trades();
+        if trades.is_empty() {
+            return 0.0;
+        }
+        let n = trades.len() as f64;
+        let side = |f: fn(f64) -> bool| {
+            let pnls = trades
+                .iter()
+                .map(|x| x.pnl)
+                .filter(|x| f(*x))
+                .collect::<Vec<_>>();
+            (pnls.len() as f64 / n, metrics::mean(&pnls).abs())
+        };
+        let ((win_rate, avg_win), (loss_rate, avg_loss)) = (side(|x| x > 0.0), side(|x| x < 0.0));
+        win_rate * avg_win - loss_rate * avg_loss
+    }
 
-        let mut bar = Bar::default();
-        bar.sym = "test".into();
+    /// expectancy in multiples of the `risk` taken per trade
+    pub fn expectancy_r(&self, risk: f64) -> f64 {
+        self.expectancy() / risk
+    }
 
-        p.update_from_market(&bar).unwrap();
-        assert_eq!(p.positions[&bar.sym].latest_market_close, Some(bar.close));
+    fn trade_pnls(&self) -> Vec<f64> {
+        self.trades().iter().map(|x| x.pnl).collect()
+    }
+
+    /// mean pnl of the round trips, 0 without trades
+    pub fn avg_trade_pnl(&self) -> f64 {
+        metrics::mean(&self.trade_pnls())
+    }
+
+    /// sample standard deviation of the pnl of the round trips, 0 with fewer than 2 trades
+    pub fn trade_pnl_stddev(&self) -> f64 {
+        metrics::std(&self.trade_pnls())
+    }
+
+    /// pnl of the round trips with a recorded risk in multiples of it, see
+    /// `Trade::r_multiple`
+    pub fn r_multiples(&self) -> Vec<f64> {
+        self.trades()
+            .iter()
+            .filter_map(|x| x.r_multiple())
+            .collect()
+    }
+
+    /// distribution of the r-multiples as the lower edge of each bin of `bin_width` and
+    /// its count, sorted by the edge. empty bins are left out
+    pub fn r_multiple_histogram(&self, bin_width: f64) -> Vec<(f64, usize)> {
+        let mut bins = std::collections::BTreeMap::<i64, usize>::new();
+        for r in self.r_multiples() {
+            *bins.entry((r / bin_width).floor() as i64).or_default() += 1;
+        }
+        bins.into_iter()
+            .map(|(i, n)| (i as f64 * bin_width, n))
+            .collect()
+    }
+
+    /// annualized volatility of the returns of the equity curve, see
+    /// `metrics::annualized_volatility`
+    pub fn volatility(&self, time_weighted: bool) -> f64 {
+        metrics::annualized_volatility(&self.equity_curve, time_weighted)
+    }
+
+    /// ulcer index of the equity curve, see `metrics::ulcer_index`
+    pub fn ulcer_index(&self) -> f64 {
+        metrics::ulcer_index(&self.equity())
+    }
+
+    /// martin ratio, the total return over the ulcer index. 0 without a drawdown
+    pub fn martin_ratio(&self) -> f64 {
+        let ui = self.ulcer_index();
+        if ui == 0.0 {
+            0.0
+        } else {
+            self.total_return / ui
+        }
+    }
+
+    /// fraction of the equity deployed outside of cash at every point of the equity curve,
+    /// 0 where the equity is gone
+    pub fn cash_utilization_series(&self) -> Vec<(DateTime, f64)> {
+        self.equity_curve
+            .iter()
+            .zip(&self.cash_curve)
+            .map(|(&(t, equity), &(_, cash))| (t, per_equity(equity - cash, equity)))
+            .collect()
+    }
+
+    /// mean of `cash_utilization_series`, 0 without an equity curve
+    pub fn avg_cash_utilization(&self) -> f64 {
+        let series = self.cash_utilization_series();
+        metrics::mean(&series.iter().map(|x| x.1).collect::<Vec<_>>())
+    }
+
+    /// longest time the equity curve took to recover from a drawdown and how long it has
+    /// been in the current one, in points of the curve
+    pub fn max_recovery_bars(&self) -> metrics::Recovery {
+        metrics::max_recovery(&self.equity())
+    }
+
+    /// continuous kelly leverage of the equity curve, the mean of the per-period returns
+    /// over their variance. 0 if they have no variance
+    pub fn kelly_leverage(&self) -> f64 {
+        let rets = metrics::returns(&self.equity());
+        let var = metrics::std(&rets).powi(2);
+        if var == 0.0 {
+            0.0
+        } else {
+            metrics::mean(&rets) / var
+        }
+    }
+
+    /// time-weighted return of the equity curve, chaining the returns between points with
+    /// each external cash flow taken out of the point it falls in. 0 without a curve
+    pub fn twr(&self) -> f64 {
+        let mut flows = self.cash_flows.iter().peekable();
+        let mut growth = 1.0;
+        for w in self.equity_curve.windows(2) {
+            // flows up to the first point are part of the starting equity
+            while flows.next_if(|x| x.0 <= w[0].0).is_some() {}
+            let mut flow = 0.0;
+            while let Some((_, v)) = flows.next_if(|x| x.0 <= w[1].0) {
+                flow += v;
+            }
+            growth *= (w[1].1 - flow) / w[0].1;
+        }
+        growth - 1.0
+    }
+
+    /// length of the equity curve in years, 0 without a curve
+    fn years(&self) -> f64 {
+        match (self.equity_curve.first(), self.equity_curve.last()) {
+            (Some(first), Some(last)) => {
+                (last.0 - first.0).num_seconds() as f64 / metrics::SECS_PER_YEAR
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// value traded each year over the average equity, counting a buy and its sell as
+    /// one turn. 0 without a curve spanning some time
+    pub fn turnover(&self) -> f64 {
+        let years = self.years();
+        let equity = self.equity();
+        if years == 0.0 || equity.is_empty() {
+            return 0.0;
+        }
+        let traded: f64 = self
+            .positions
+            .iter()
+            .map(|x| x.stats.value_bought + x.stats.value_sold)
+            .sum();
+        let avg_equity = equity.iter().sum::<f64>() / equity.len() as f64;
+        traded / 2.0 / avg_equity / years
+    }
+
+    /// annualized return lost to the costs charged on fills, in percentage points of the
+    /// initial cash. slippage is already in the fill prices and only shows up here when
+    /// the broker charges it as a cost. 0 without a curve spanning some time
+    pub fn cost_drag(&self) -> f64 {
+        let years = self.years();
+        if years == 0.0 {
+            return 0.0;
+        }
+        self.fees_paid / self.init_cash / years * 100.0
+    }
+
+    /// check that cash plus the positions marked at their latest close equals the initial
+    /// cash plus pnl, income and external cash flows, e.g. to catch costs counted twice
+    pub fn reconcile(&self) -> Result<(), String> {
+        let held = self.cash + self.market_value;
+        let flows: f64 = self.cash_flows.iter().map(|x| x.1).sum();
+        let expected = self.init_cash + self.pnl + self.income + flows;
+        let tolerance = TOLERANCE * self.init_cash.abs().max(1.0);
+        if (held - expected).abs() > tolerance {
+            return Err(format!(
+                "cash {:.4} + positions {:.4} = {:.4} != init cash {:.4} + pnl {:.4} + income {:.4} + flows {:.4} = {:.4}",
+                self.cash, self.market_value, held, self.init_cash, self.pnl, self.income, flows, expected
+            ));
+        }
+        Ok(())
+    }
+
+    /// return over every window of `window` points of the equity curve
+    pub fn rolling_return(&self, window: usize) -> Vec<f64> {
+        metrics::rolling(&self.equity(), window, |w| w[w.len() - 1] / w[0] - 1.0)
+    }
+
+    /// per-period sharpe over every window of `window` points of the equity curve
+    pub fn rolling_sharpe(&self, window: usize) -> Vec<f64> {
+        metrics::rolling(&self.equity(), window, |w| {
+            metrics::sharpe(&metrics::returns(w))
+        })
+    }
+
+    /// equity points with the benchmark price at their time, sampled as in `rolling_beta`
+    fn aligned(&self, benchmark: &[(DateTime, f64)]) -> Vec<(DateTime, f64, f64)> {
+        self.equity_curve
+            .iter()
+            .filter_map(|&(t, v)| match benchmark.partition_point(|x| x.0 <= t) {
+                0 => None,
+                i => Some((t, v, benchmark[i - 1].1)),
+            })
+            .collect()
+    }
+
+    /// alpha, beta and excess return against the `benchmark` prices, sampled as in
+    /// `rolling_beta`. None if they overlap on fewer than 2 points
+    pub fn compare(&self, benchmark: &[(DateTime, f64)]) -> Option<BenchmarkStats> {
+        let aligned = self.aligned(benchmark);
+        if aligned.len() < 2 {
+            return None;
+        }
+        let (first, last) = (aligned[0], aligned[aligned.len() - 1]);
+        let (rets, bench): (Vec<_>, Vec<_>) = aligned
+            .windows(2)
+            .map(|w| (w[1].1 / w[0].1 - 1.0, w[1].2 / w[0].2 - 1.0))
+            .unzip();
+        let beta = metrics::beta(&rets, &bench);
+        Some(BenchmarkStats {
+            alpha: metrics::mean(&rets) - beta * metrics::mean(&bench),
+            beta,
+            excess_return: last.1 / first.1 - last.2 / first.2,
+        })
+    }
+
+    /// beta against the `benchmark` prices over every window of `window` returns, at
+    /// the time the window ends. the benchmark is sampled at the equity timestamps,
+    /// holding its last price in between, and points before it starts are dropped.
+    /// empty if there are fewer returns than `window` or `window` is below 2
+    pub fn rolling_beta(
+        &self,
+        benchmark: &[(DateTime, f64)],
+        window: usize,
+    ) -> Vec<(DateTime, f64)> {
+        let aligned = self.aligned(benchmark);
+        if window < 2 || aligned.len() <= window {
+            return vec![];
+        }
+        let rets = aligned
+            .windows(2)
+            .map(|w| (w[1].0, w[1].1 / w[0].1 - 1.0, w[1].2 / w[0].2 - 1.0))
+            .collect::<Vec<_>>();
+        rets.windows(window)
+            .map(|w| {
+                let (r, b): (Vec<_>, Vec<_>) = w.iter().map(|x| (x.1, x.2)).unzip();
+                (w[window - 1].0, metrics::beta(&r, &b))
+            })
+            .collect()
+    }
+}
+
+impl<T> Statistics for SimplePortfolio<T> {
+    type Stats = PortfolioStats;
+
+    fn stats(&self) -> Self::Stats {
+        let positions = self.positions_sorted_by(PositionKey::PnlRatio);
+        let settings = self.position_settings();
+
+        let pnl = positions.iter().map(|x| x.stats.pnl).sum();
+        let (sharpe, max_drawdown, max_drawdown_abs) = if self.streaming_stats {
+            let mut streaming = self.streaming;
+            if let Some((t, v)) = self.last_equity {
+                streaming.push_excess(v, self.risk_free_at(t));
+            }
+            (
+                streaming.sharpe(),
+                streaming.max_drawdown(),
+                streaming.max_drawdown_abs(),
+            )
+        } else {
+            let equity = self.equity_curve.iter().map(|x| x.1).collect::<Vec<_>>();
+            (
+                metrics::sharpe(&self.excess_returns()),
+                metrics::max_drawdown(&equity),
+                metrics::max_drawdown_abs(&equity),
+            )
+        };
+        let risk_max_drawdown = if self.risk_equity_curve.is_empty() {
+            max_drawdown
+        } else {
+            let risk_equity = self.risk_equity_curve.iter().map(|x| x.1);
+            metrics::max_drawdown(&risk_equity.collect::<Vec<_>>())
+        };
+        let mut stats = PortfolioStats {
+            sharpe,
+            max_drawdown,
+            max_drawdown_abs,
+            risk_max_drawdown,
+            pnl,
+            gross_pnl: pnl + self.fees_paid,
+            fees_paid: self.fees_paid,
+            rejections: self.rejections,
+            realized_pnl: positions.iter().map(|x| x.realized_pnl(&settings)).sum(),
+            unrealized_pnl: positions.iter().map(|x| x.unrealized_pnl(&settings)).sum(),
+            market_value: self.net_exposure(),
+            init_cash: self.init_cash,
+            cash: self.cash,
+            pnl_ratio: pnl / self.init_cash,
+            income: self.income,
+            cash_flows: self.cash_flows.clone(),
+            total_return: self.equity() / self.init_cash - 1.0,
+            price_return: (self.equity() - self.income) / self.init_cash - 1.0,
+            positions,
+            equity_curve: self.equity_curve.clone(),
+            cash_curve: self.cash_curve.clone(),
+            risk_equity_curve: self.risk_equity_curve.clone(),
+            benchmark: None,
+        };
+        if !self.benchmark.is_empty() {
+            // None when streaming, there is no equity curve to compare
+            stats.benchmark = stats.compare(&self.benchmark);
+        }
+        stats
+    }
+}
+
+/// `value` as a fraction of `equity`, 0 once the equity is gone
+fn per_equity(value: f64, equity: f64) -> f64 {
+    if equity <= 0.0 {
+        0.0
+    } else {
+        value / equity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        order::{FixedRiskOrderManager, PriceSource},
+        units::Price,
+    };
+
+    #[test]
+    fn test_record_skip() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(100.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for reason in [
+            SkipReason::NoOrder,
+            SkipReason::Vetoed,
+            SkipReason::Rejected,
+        ] {
+            p.record_skip(reason);
+        }
+        assert_eq!(p.rejections, 1);
+    }
+
+    #[test]
+    fn test_portfolio_handle_fill() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(50.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+
+        // let fill = build_test_fill(10, 5.0, 1.0);
+        // assert!(matches!(p.update_from_fill(&fill), Err(_)));
+
+        let fill = build_test_fill(10, 5.0, 0.0);
+        assert!(p.update_from_fill(&fill).is_ok());
+        // assert_eq!(p.cash, 0.0);
+        assert_eq!(p.init_cash, 50.0);
+
+        let pos = &p.positions[&fill.sym];
+        assert_eq!(pos.qty, 10);
+        assert_eq!(pos.stats.qty_sold, 0);
+        assert_eq!(pos.stats.qty_bought, 10);
+        assert_eq!(pos.stats.value_sold, 0.0);
+        assert_eq!(pos.stats.value_bought, 50.0);
+        assert_eq!(pos.stats.cost, 0.0);
+
+        let fill = build_test_fill(-5, 6.0, 1.0);
+
+        assert!(p.update_from_fill(&fill).is_ok());
+        // assert_eq!(p.cash, 29.0);
+        let pos = &p.positions[&fill.sym];
+        assert_eq!(p.init_cash, 50.0);
+        assert_eq!(pos.qty, 5);
+        assert_eq!(pos.stats.qty_sold, 5);
+        assert_eq!(pos.stats.qty_bought, 10);
+        assert_eq!(pos.stats.value_sold, 30.0);
+        assert_eq!(pos.stats.value_bought, 50.0);
+        assert_eq!(pos.stats.cost, 1.0);
+        assert_eq!(
+            p.positions[&fill.sym].pnl(&p.position_settings()),
+            29.0 + 80.0 / 15.0 * 5.0 - 50.0
+        );
+
+        let stats = p.stats();
+        assert_eq!(
+            p.positions[&fill.sym].pnl(&p.position_settings()),
+            stats.pnl
+        );
+
+        let bar = Bar {
+            sym: "test".into(),
+            ..Default::default()
+        };
+
+        p.update_from_market(&bar).unwrap();
+        assert_eq!(p.positions[&bar.sym].latest_market_close, Some(bar.close));
+    }
+
+    #[test]
+    fn test_max_open_positions() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .max_open_positions(Some(2))
+            .build()
+            .unwrap();
+
+        for sym in ["a", "b"] {
+            let mut fill = build_test_fill(10, 5.0, 0.0);
+            fill.sym = sym.into();
+            p.update_from_fill(&fill).unwrap();
+        }
+        assert_eq!(p.open_positions(), 2);
+
+        let decision = |sym: &str, kind| Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: sym.into(),
+            kind,
+            time: chrono::Utc::now(),
+        };
+
+        let ord = p.allocate_order(&decision("c", DecisionKind::Buy)).unwrap();
+        assert!(ord.is_none());
+
+        let ord = p.allocate_order(&decision("a", DecisionKind::Buy)).unwrap();
+        assert_eq!(ord.unwrap().qty, 10);
+
+        let ord = p
+            .allocate_order(&decision("b", DecisionKind::Sell))
+            .unwrap();
+        assert_eq!(ord.unwrap().qty, -10);
+    }
+
+    #[test]
+    fn test_min_holding_bars() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .min_holding_bars(2)
+            .build()
+            .unwrap();
+        let bar = Bar {
+            sym: "test".into(),
+            ..Default::default()
+        };
+        let sell = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: "test".into(),
+            kind: DecisionKind::Sell,
+            time: chrono::Utc::now(),
+        };
+
+        let close = Decision {
+            kind: DecisionKind::Close,
+            ..sell.clone()
+        };
+
+        p.update_from_market(&bar).unwrap();
+        p.update_from_fill(&build_test_fill(10, 5.0, 0.0)).unwrap();
+        assert!(p.allocate_order(&sell).unwrap().is_none());
+        assert!(p.allocate_order(&close).unwrap().is_none());
+
+        p.update_from_market(&bar).unwrap();
+        assert!(p.allocate_order(&sell).unwrap().is_none());
+        assert!(p.allocate_order(&close).unwrap().is_none());
+
+        p.update_from_market(&bar).unwrap();
+        assert_eq!(p.allocate_order(&sell).unwrap().unwrap().qty, -10);
+        assert_eq!(p.allocate_order(&close).unwrap().unwrap().qty, -10);
+    }
+
+    #[test]
+    fn test_cooldown_bars() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .cooldown_bars(2)
+            .build()
+            .unwrap();
+        let bar = Bar {
+            sym: "test".into(),
+            ..Default::default()
+        };
+        let buy = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+            time: chrono::Utc::now(),
+        };
+
+        p.update_from_market(&bar).unwrap();
+        p.update_from_fill(&build_test_fill(10, 5.0, 0.0)).unwrap();
+        p.update_from_fill(&build_test_fill(-10, 4.0, 0.0)).unwrap();
+        assert!(p.allocate_order(&buy).unwrap().is_none());
+
+        p.update_from_market(&bar).unwrap();
+        assert!(p.allocate_order(&buy).unwrap().is_none());
+
+        p.update_from_market(&bar).unwrap();
+        assert_eq!(p.allocate_order(&buy).unwrap().unwrap().qty, 10);
+
+        // a winning round trip does not cool down
+        p.update_from_fill(&build_test_fill(10, 5.0, 0.0)).unwrap();
+        p.update_from_fill(&build_test_fill(-10, 6.0, 0.0)).unwrap();
+        assert!(p.allocate_order(&buy).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_total_return() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let bar = |dividend| Bar {
+            sym: "test".into(),
+            close: 10.0,
+            dividend,
+            ..Default::default()
+        };
+
+        p.update_from_market(&bar(0.0)).unwrap();
+        p.update_from_fill(&build_test_fill(100, 10.0, 0.0))
+            .unwrap();
+        p.cash -= 1000.0;
+        p.update_from_market(&bar(0.5)).unwrap();
+        p.update_from_market(&bar(0.0)).unwrap();
+
+        let stats = p.stats();
+        assert_eq!(stats.income, 50.0);
+        assert_eq!(p.cash, 50.0);
+        assert!(stats.price_return.abs() < 1e-9);
+        assert!((stats.total_return - stats.price_return - 0.5 / 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_initial_prices() {
+        let build = |initial_prices| {
+            SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedValueOrderManager {
+                    val: 500.0,
+                    ..Default::default()
+                })
+                .initial_prices(initial_prices)
+                .build()
+                .unwrap()
+        };
+        let decision = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: "new".into(),
+            kind: DecisionKind::Buy,
+            time: chrono::Utc::now(),
+        };
+
+        // nothing to size with
+        let mut p = build(HashMap::new());
+        assert!(p.allocate_order(&decision).unwrap().is_none());
+
+        let mut p = build(HashMap::from([("new".to_owned(), 10.0)]));
+        let ord = p.allocate_order(&decision).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+    }
+
+    #[test]
+    fn test_liquidation_trigger() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager {
+                size: 10,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        // equity floor
+        p.set_liquidation_trigger(|x| x.equity < 950.0);
+        let decision = |sym: &str, kind| Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: sym.into(),
+            kind,
+            time: chrono::Utc::now(),
+        };
+        let mark = |p: &mut SimplePortfolio<_>, close| {
+            for sym in ["a", "b"] {
+                p.update_from_market(&Bar {
+                    sym: sym.into(),
+                    close,
+                    ..Default::default()
+                })
+                .unwrap();
+            }
+            p.mark_timestamp(chrono::Utc::now());
+        };
+        for sym in ["a", "b"] {
+            let mut fill = build_test_fill(10, 10.0, 0.0);
+            fill.sym = sym.into();
+            p.pay((fill.qty * fill.price).0).unwrap();
+            p.update_from_fill(&fill).unwrap();
+        }
+
+        mark(&mut p, 9.0);
+        assert!(!p.liquidating);
+        // equity of 1000 - 2 * 10 * 3 is below the floor
+        mark(&mut p, 7.0);
+        assert!(p.liquidating);
+
+        for (sym, kind) in [("a", DecisionKind::Buy), ("b", DecisionKind::Hold)] {
+            let ord = p.allocate_order(&decision(sym, kind)).unwrap().unwrap();
+            assert_eq!(ord.qty, -10);
+            assert!(matches!(ord.kind, crate::order::OrderKind::Market));
+        }
+        let ord = p.allocate_order(&decision("c", DecisionKind::Buy)).unwrap();
+        assert!(ord.is_none());
+    }
+
+    #[test]
+    fn test_entries_while_liquidating() {
+        let run = |entries_while_liquidating| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .entries_while_liquidating(entries_while_liquidating)
+                .order_manager(crate::order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .build()
+                .unwrap();
+            p.set_liquidation_trigger(|x| x.drawdown > 0.05);
+            let fill = build_test_fill(10, 10.0, 0.0);
+            p.pay((fill.qty * fill.price).0).unwrap();
+            p.update_from_fill(&fill).unwrap();
+            p.update_from_market(&Bar {
+                sym: "test".into(),
+                close: 1.0,
+                ..Default::default()
+            })
+            .unwrap();
+            p.mark_timestamp(chrono::Utc::now());
+            assert!(p.liquidating);
+
+            let decision = |kind| Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                sym: "test".into(),
+                kind,
+                time: chrono::Utc::now(),
+            };
+            let ord = p.allocate_order(&decision(DecisionKind::Buy)).unwrap();
+            assert_eq!(ord.unwrap().qty, -10);
+            p.update_from_fill(&build_test_fill(-10, 1.0, 0.0)).unwrap();
+            // the kill-switch just flattened it
+            p.allocate_order(&decision(DecisionKind::Buy))
+                .unwrap()
+                .map(|x| x.qty)
+        };
+        assert_eq!(run(false), None);
+        assert_eq!(run(true), Some(Qty(10)));
+    }
+
+    #[test]
+    fn test_order_time_from_decision() {
+        #[derive(Clone)]
+        struct Untimed;
+
+        impl OrderManager for Untimed {
+            fn make_order(
+                &mut self,
+                decision: &Decision,
+                _: &PortfolioView,
+            ) -> Result<Option<crate::order::Order>, ErrorRepr> {
+                Ok(crate::order::OrderBuilder::default()
+                    .sym(decision.sym.clone())
+                    .qty(1)
+                    .time(decision.time)
+                    .build()
+                    .ok())
+            }
+        }
+
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(Untimed)
+            .build()
+            .unwrap();
+        let time = "2020-01-02T00:00:00Z".parse().unwrap();
+        let decision = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+            time,
+        };
+
+        let ord = p.allocate_order(&decision).unwrap().unwrap();
+        assert_eq!(ord.time, time);
+    }
+
+    #[test]
+    fn test_fees_paid() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let fills = [
+            build_test_fill(10, 5.0, 0.5),
+            build_test_fill(10, 6.0, 0.25),
+            build_test_fill(-20, 7.0, 1.0),
+        ];
+        for fill in &fills {
+            p.update_from_fill(fill).unwrap();
+        }
+
+        let stats = p.stats();
+        assert_eq!(stats.fees_paid, fills.iter().map(|x| x.cost).sum::<f64>());
+        assert_eq!(stats.gross_pnl, stats.pnl + 1.75);
+        assert_eq!(stats.gross_pnl, 140.0 - 110.0);
+    }
+
+    #[test]
+    fn test_allow_short() {
+        for allow_short in [false, true] {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .allow_short(allow_short)
+                .build()
+                .unwrap();
+            let sell = Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                sym: "test".into(),
+                kind: DecisionKind::Sell,
+                time: chrono::Utc::now(),
+            };
+
+            let ord = p.allocate_order(&sell).unwrap();
+            if !allow_short {
+                assert!(ord.is_none());
+                assert!(p.update_from_fill(&build_test_fill(-10, 5.0, 0.0)).is_err());
+                continue;
+            }
+            assert_eq!(ord.unwrap().qty, -10);
+
+            p.update_from_fill(&build_test_fill(-10, 5.0, 0.0)).unwrap();
+            assert_eq!(p.positions["test"].qty, -10);
+            // adds to the short
+            assert_eq!(p.allocate_order(&sell).unwrap().unwrap().qty, -10);
+        }
+    }
+
+    #[test]
+    fn test_flat_on_reverse() {
+        for (flat_on_reverse, qty) in [(false, -10), (true, -5)] {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager {
+                    size: 10,
+                    oversell: crate::order::OversellPolicy::Short,
+                    ..Default::default()
+                })
+                .allow_short(true)
+                .flat_on_reverse(flat_on_reverse)
+                .build()
+                .unwrap();
+            let sell = Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                sym: "test".into(),
+                kind: DecisionKind::Sell,
+                time: chrono::Utc::now(),
+            };
+
+            p.update_from_fill(&build_test_fill(5, 5.0, 0.0)).unwrap();
+            let ord = p.allocate_order(&sell).unwrap().unwrap();
+            assert_eq!(ord.qty, qty);
+
+            // flat, the next sell does not open a short
+            p.update_from_fill(&build_test_fill(-5, 5.0, 0.0)).unwrap();
+            let ord = p.allocate_order(&sell).unwrap();
+            assert_eq!(ord.is_none(), flat_on_reverse);
+        }
+    }
+
+    #[test]
+    fn test_unrealized_pnl() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for (sym, close) in [("up", 12.0), ("down", 7.0)] {
+            let mut fill = build_test_fill(10, 10.0, 0.0);
+            fill.sym = sym.into();
+            p.update_from_fill(&fill).unwrap();
+            p.update_from_market(&Bar {
+                sym: sym.into(),
+                close,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        assert_eq!(p.unrealized_pnl(), 20.0 - 30.0);
+        assert_eq!(p.stats().unrealized_pnl, p.unrealized_pnl());
+    }
+
+    #[test]
+    fn test_apply_external_fill() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        p.apply_external_fill(&build_test_fill(10, 20.0, 1.0))
+            .unwrap();
+
+        assert_eq!(p.positions["test"].qty, 10);
+        assert_eq!(p.cash, 1000.0 - 200.0 - 1.0);
+        assert_eq!(p.fees_paid, 1.0);
+    }
+
+    #[test]
+    fn test_mark_spread() {
+        let equity = |mark_spread| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .mark_spread(mark_spread)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            let fill = build_test_fill(10, 10.0, 0.0);
+            p.pay((fill.qty * fill.price).0).unwrap();
+            p.update_from_fill(&fill).unwrap();
+            p.update_from_market(&Bar {
+                sym: "test".into(),
+                close: 12.0,
+                ..Default::default()
+            })
+            .unwrap();
+            assert_eq!(p.stats().reconcile(), Ok(()));
+            p.equity()
+        };
+
+        // 10 shares marked at the bid, half of the 0.2 spread below the close
+        assert!((equity(0.0) - equity(0.2) - 10.0 * 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_settings_reach_open_positions() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let fill = build_test_fill(10, 10.0, 0.0);
+        p.pay((fill.qty * fill.price).0).unwrap();
+        p.update_from_fill(&fill).unwrap();
+        p.update_from_market(&Bar {
+            sym: "test".into(),
+            close: 12.0,
+            ..Default::default()
+        })
+        .unwrap();
+        let before = p.equity();
+
+        p.mark_spread = 0.2;
+        assert!((before - p.equity() - 10.0 * 0.1).abs() < 1e-9);
+
+        p.allow_short = true;
+        let fill = build_test_fill(-20, 10.0, 0.0);
+        p.update_from_fill(&fill).unwrap();
+        assert_eq!(p.positions["test"].qty, -10);
+    }
+
+    #[test]
+    fn test_mark_price() {
+        let equity_curve = |mark_price| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .mark_price(mark_price)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            let fill = build_test_fill(10, 10.0, 0.0);
+            p.pay((fill.qty * fill.price).0).unwrap();
+            p.update_from_fill(&fill).unwrap();
+            let start = chrono::Utc::now();
+            for (i, (open, close)) in [(11.0, 12.0), (13.0, 9.0)].into_iter().enumerate() {
+                let time = start + chrono::Duration::days(i as i64);
+                p.update_from_market(&Bar {
+                    sym: "test".into(),
+                    time,
+                    open,
+                    close,
+                    ..Default::default()
+                })
+                .unwrap();
+                p.mark_timestamp(time);
+            }
+            p.equity_curve.iter().map(|x| x.1).collect::<Vec<_>>()
+        };
+
+        assert_eq!(equity_curve(MarkSource::Close), vec![1020.0, 990.0]);
+        assert_eq!(equity_curve(MarkSource::Open), vec![1010.0, 1030.0]);
+    }
+
+    #[test]
+    fn test_valuation() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .valuation(crate::position::Multiplier(100.0))
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let fill = build_test_fill(1, 10.0, 0.0);
+        p.pay((fill.qty * fill.price).0).unwrap();
+        p.update_from_fill(&fill).unwrap();
+        p.update_from_market(&Bar {
+            sym: "test".into(),
+            close: 11.5,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // a price change of 1.5 on one contract of 100
+        assert_eq!(p.unrealized_pnl(), 150.0);
+        assert_eq!(p.positions["test"].pnl(&p.position_settings()), 150.0);
+        assert_eq!(p.equity(), 1150.0);
+        assert_eq!(p.stats().reconcile(), Ok(()));
+
+        // closing books the gain of the contract, not of one unit
+        let fill = build_test_fill(-1, 11.5, 0.0);
+        p.cash += 11.5;
+        p.update_from_fill(&fill).unwrap();
+        assert_eq!(p.positions["test"].pnl(&p.position_settings()), 150.0);
+        assert_eq!(
+            p.positions["test"].realized_pnl(&p.position_settings()),
+            150.0
+        );
+        assert_eq!(p.positions["test"].exits[0].realized_pnl, 150.0);
+        assert_eq!(p.cash, 1150.0);
+        assert_eq!(p.equity(), 1150.0);
+        assert_eq!(p.stats().reconcile(), Ok(()));
+    }
+
+    #[test]
+    fn test_exposure() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .allow_short(true)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for (sym, qty) in [("long", 50), ("short", -50)] {
+            let mut fill = build_test_fill(qty, 10.0, 0.0);
+            fill.sym = sym.into();
+            p.update_from_fill(&fill).unwrap();
+            p.update_from_market(&Bar {
+                sym: sym.into(),
+                close: 10.0,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        assert_eq!(p.net_exposure(), 0.0);
+        assert_eq!(p.gross_exposure(), 1000.0);
+        assert_eq!(p.net_exposure_ratio(), 0.0);
+        assert_eq!(p.gross_exposure_ratio(), 1.0);
+
+        // wiped out, the ratios have nothing to divide by
+        p.cash = -500.0;
+        assert_eq!(p.equity(), -500.0);
+        assert_eq!(p.gross_exposure_ratio(), 0.0);
+        assert_eq!(p.net_exposure_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_cash_utilization_without_equity() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let time = chrono::Utc::now();
+        p.equity_curve = vec![(time, 1000.0), (time, 0.0), (time, -100.0)];
+        p.cash_curve = vec![(time, 500.0), (time, -50.0), (time, -200.0)];
+
+        let series = p.stats().cash_utilization_series();
+        assert_eq!(
+            series.iter().map(|x| x.1).collect::<Vec<_>>(),
+            vec![0.5, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for (qty, price) in [(10, 5.0), (-4, 6.0)] {
+            let fill = build_test_fill(qty, price, 1.0);
+            p.pay((fill.qty * fill.price).0 + fill.cost).unwrap();
+            p.update_from_fill(&fill).unwrap();
+        }
+        p.update_from_market(&Bar {
+            sym: "test".into(),
+            close: 7.0,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut stats = p.stats();
+        assert_eq!(stats.reconcile(), Ok(()));
+
+        // a commission charged twice
+        stats.cash -= 1.0;
+        assert!(stats.reconcile().is_err());
+    }
+
+    #[test]
+    fn test_vol_target() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(100000.0)
+            .vol_target(Some(VolTarget {
+                target: 0.01,
+                window: 4,
+                max_leverage: 3.0,
+                band: 0.1,
+            }))
+            .order_manager(crate::order::FixedSizeOrderManager {
+                size: 100,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let buy = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+            time: chrono::Utc::now(),
+        };
+        let start = chrono::Utc::now();
+        let moves = |p: &mut SimplePortfolio<_>, moves: [f64; 5]| {
+            let mut equity = 100000.0;
+            for (i, x) in moves.into_iter().enumerate() {
+                equity *= 1.0 + x;
+                p.equity_curve
+                    .push((start + chrono::Duration::days(i as i64), equity));
+            }
+        };
+        let calm = [0.0, 0.005, -0.005, 0.005, -0.005];
+        let wild = [0.03, -0.03, 0.03, -0.03, 0.03];
+
+        // too few returns yet
+        assert_eq!(p.vol_leverage(), 1.0);
+        // about 0.6% a day, levered up
+        moves(&mut p, calm);
+        let calm_leverage = p.vol_leverage();
+        assert!(calm_leverage > 1.0);
+        let qty = p.allocate_order(&buy).unwrap().unwrap().qty;
+        assert_eq!(qty, Qty::whole(100.0 * calm_leverage));
+        p.update_from_fill(&build_test_fill(qty.0, 10.0, 0.0))
+            .unwrap();
+        assert!(p.rebalance("test", buy.time).is_none());
+
+        // about 3.5% a day, the position held is levered down
+        moves(&mut p, wild);
+        assert!(p.vol_leverage() < 1.0);
+        let ord = p.rebalance("test", buy.time).unwrap();
+        let target = Qty::whole(f64::from(qty) * p.vol_leverage() / calm_leverage);
+        assert!(target < Qty(100));
+        assert_eq!(ord.qty, target - qty);
+        p.update_from_fill(&build_test_fill(ord.qty.0, 10.0, 0.0))
+            .unwrap();
+        assert!(p.rebalance("test", buy.time).is_none());
+
+        // calm again, levered back up
+        moves(&mut p, calm);
+        assert!(p.rebalance("test", buy.time).unwrap().qty > 0);
+    }
+
+    #[test]
+    fn test_max_leverage() {
+        for deleverage in [false, true] {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager {
+                    size: 10,
+                    ..Default::default()
+                })
+                .max_leverage(Some(0.6))
+                .deleverage(deleverage)
+                .build()
+                .unwrap();
+            let fill = build_test_fill(50, 10.0, 0.0);
+            p.pay((fill.qty * fill.price).0).unwrap();
+            p.update_from_fill(&fill).unwrap();
+            let mark = |p: &mut SimplePortfolio<_>, close| {
+                p.update_from_market(&Bar {
+                    sym: "test".into(),
+                    close,
+                    ..Default::default()
+                })
+                .unwrap();
+            };
+
+            // 500 / 1000
+            mark(&mut p, 10.0);
+            let buy = Decision {
+                strength: 1.0,
+                price: None,
+                signal: None,
+                stop: None,
+                time: chrono::Utc::now(),
+                sym: "test".into(),
+                kind: DecisionKind::Buy,
+            };
+            assert_eq!(p.allocate_order(&buy).unwrap().unwrap().qty, 10);
+
+            // 1500 / 2000
+            mark(&mut p, 30.0);
+            let ord = p.allocate_order(&buy).unwrap();
+            if deleverage {
+                // selling 10 takes it to 1200 / 2000
+                assert_eq!(ord.unwrap().qty, -10);
+            } else {
+                assert!(ord.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn test_deleverage_exit() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager {
+                size: 50,
+                ..Default::default()
+            })
+            .max_leverage(Some(0.6))
+            .deleverage(true)
+            .build()
+            .unwrap();
+        let fill = build_test_fill(50, 10.0, 0.0);
+        p.pay((fill.qty * fill.price).0).unwrap();
+        p.update_from_fill(&fill).unwrap();
+        // 1500 / 2000
+        p.update_from_market(&Bar {
+            sym: "test".into(),
+            close: 30.0,
+            ..Default::default()
+        })
+        .unwrap();
+        let sell = Decision {
+            strength: 1.0,
+            price: None,
+            signal: None,
+            stop: None,
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Sell,
+        };
+        // the whole position instead of the 10 trimmed back to the cap
+        assert_eq!(p.allocate_order(&sell).unwrap().unwrap().qty, -50);
+    }
+
+    #[test]
+    fn test_positions_sorted_by() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(100000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for (sym, qty, close) in [("a", 100, 10.0), ("b", 10, 20.0)] {
+            let mut fill = build_test_fill(qty, 10.0, 0.0);
+            fill.sym = sym.into();
+            p.update_from_fill(&fill).unwrap();
+            p.update_from_market(&Bar {
+                sym: sym.into(),
+                close,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        let syms = |key| {
+            p.positions_sorted_by(key)
+                .into_iter()
+                .map(|x| x.sym)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(syms(PositionKey::Exposure), vec!["a", "b"]);
+        assert_eq!(syms(PositionKey::PnlRatio), vec!["b", "a"]);
+        assert_eq!(syms(PositionKey::Symbol), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let time = chrono::Utc::now();
+        assert!(p.flatten("test", time).is_none());
+
+        p.update_from_fill(&build_test_fill(10, 5.0, 0.0)).unwrap();
+        let ord = p.flatten("test", time).unwrap();
+        assert_eq!(ord.qty, -10);
+        assert_eq!(ord.time, time);
+        assert!(matches!(ord.kind, crate::order::OrderKind::Market));
+
+        p.update_from_fill(&build_test_fill(ord.qty.0, 6.0, 0.0))
+            .unwrap();
+        assert_eq!(p.positions["test"].qty, 0);
+        assert!(p.flatten("test", time).is_none());
+    }
+
+    #[test]
+    fn test_dust_threshold() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .dust_threshold(Qty(5))
+            .build()
+            .unwrap();
+        for fill in [
+            build_test_fill(100, 5.0, 0.0),
+            build_test_fill(-97, 6.0, 0.0),
+        ] {
+            p.pay((fill.qty * fill.price).0 + fill.cost).unwrap();
+            p.update_from_fill(&fill).unwrap();
+        }
+        assert_eq!(p.positions["test"].qty, 0);
+        assert_eq!(p.cash, 1000.0 + 100.0);
+
+        // above the threshold the residual stays
+        let fill = build_test_fill(10, 5.0, 0.0);
+        p.pay((fill.qty * fill.price).0).unwrap();
+        p.update_from_fill(&fill).unwrap();
+        assert_eq!(p.positions["test"].qty, 10);
+
+        // a small new position is not dust
+        let mut fill = build_test_fill(3, 5.0, 0.0);
+        fill.sym = "other".into();
+        p.pay((fill.qty * fill.price).0).unwrap();
+        p.update_from_fill(&fill).unwrap();
+        assert_eq!(p.positions["other"].qty, 3);
+        // nor is adding to it
+        p.pay((fill.qty * fill.price).0).unwrap();
+        p.update_from_fill(&fill).unwrap();
+        assert_eq!(p.positions["other"].qty, 6);
+    }
+
+    #[test]
+    fn test_fill_policy() {
+        for policy in [FillPolicy::Reject, FillPolicy::Clamp] {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .fill_policy(policy)
+                .build()
+                .unwrap();
+            p.update_from_fill(&build_test_fill(10, 5.0, 0.0)).unwrap();
+
+            // a broker paying for 30 sold while only 10 are held
+            let fill = build_test_fill(-30, 6.0, 3.0);
+            p.pay((fill.qty * fill.price).0 + fill.cost).unwrap();
+            let res = p.update_from_fill(&fill);
+
+            match policy {
+                FillPolicy::Reject => assert!(res.is_err()),
+                FillPolicy::Clamp => {
+                    res.unwrap();
+                    assert_eq!(p.positions["test"].qty, 0);
+                    assert_eq!(p.cash, 1000.0 + 60.0 - 1.0);
+                    assert_eq!(p.fees_paid, 1.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cost_drag() {
+        let start = chrono::Utc::now();
+        let stats = |cost| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            for (i, qty) in [50, -50, 50, -50].into_iter().enumerate() {
+                let fill = build_test_fill(qty, 10.0, cost);
+                p.pay((fill.qty * fill.price).0 + fill.cost).unwrap();
+                p.update_from_fill(&fill).unwrap();
+                p.update_from_market(&Bar {
+                    sym: "test".into(),
+                    close: 10.0,
+                    ..Default::default()
+                })
+                .unwrap();
+                p.mark_timestamp(start + chrono::Duration::days(i as i64 * 243));
+            }
+            p.stats()
+        };
+
+        let (free, costly) = (stats(0.0), stats(5.0));
+        assert_eq!(free.cost_drag(), 0.0);
+        let years = 729.0 / 365.25;
+        let diff = (free.total_return - costly.total_return) / years * 100.0;
+        assert!((costly.cost_drag() - diff).abs() < 1e-9);
+        // 1000 traded both ways over about 2 years on roughly 1000 of equity
+        assert!((free.turnover() - 1000.0 / 1000.0 / years).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_consecutive_trades() {
+        let mut stats = PortfolioStats::default();
+        assert_eq!(stats.max_consecutive_wins(), 0);
+        assert_eq!(stats.max_consecutive_losses(), 0);
+
+        let start = chrono::Utc::now();
+        let trade = |i: i64, pnl| Trade {
+            sym: if i % 2 == 0 { "a" } else { "b" }.into(),
+            entry_time: start,
+            exit_time: start + chrono::Duration::days(i),
+            bars: 1,
+            qty: Qty(10),
+            pnl,
+            risk: None,
+        };
+        let pnls = [10.0, 5.0, -1.0, -2.0, -3.0, 4.0];
+        for sym in ["a", "b"] {
+            stats.positions.push(Position {
+                sym: sym.into(),
+                trades: pnls
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pnl)| trade(i as i64, *pnl))
+                    .filter(|x| x.sym == sym)
+                    .collect(),
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(stats.trades().len(), 6);
+        assert_eq!(stats.max_consecutive_wins(), 2);
+        assert_eq!(stats.max_consecutive_losses(), 3);
+    }
+
+    #[test]
+    fn test_unrealized_gain_discount() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .unrealized_gain_discount(0.5)
+            .build()
+            .unwrap();
+        p.pay(100.0).unwrap();
+        p.update_from_fill(&build_test_fill(10, 10.0, 0.0)).unwrap();
+        let start = chrono::Utc::now();
+        for (i, close) in [10.0, 30.0, 20.0].into_iter().enumerate() {
+            p.update_from_market(&Bar {
+                sym: "test".into(),
+                close,
+                ..Default::default()
+            })
+            .unwrap();
+            p.mark_timestamp(start + chrono::Duration::days(i as i64));
+        }
+
+        let stats = p.stats();
+        let values = |curve: &[(DateTime, f64)]| curve.iter().map(|x| x.1).collect::<Vec<_>>();
+        assert_eq!(values(&stats.equity_curve), vec![1000.0, 1200.0, 1100.0]);
+        // half of the paper gain of 200, then 100, is left out
+        assert_eq!(
+            values(&stats.risk_equity_curve),
+            vec![1000.0, 1100.0, 1050.0]
+        );
+        assert!((stats.max_drawdown - 100.0 / 1200.0).abs() < 1e-9);
+        assert!((stats.risk_max_drawdown - 50.0 / 1100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_trades_columnar() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for (qty, price) in [(10, 5.0), (-10, 6.0), (5, 5.0), (5, 5.0), (-10, 4.0)] {
+            p.update_from_fill(&build_test_fill(qty, price, 0.0))
+                .unwrap();
+        }
+
+        let stats = p.stats();
+        let cols = stats.trades_columnar();
+        let n = stats.trades().len();
+        assert_eq!(n, 2);
+        for len in [
+            cols.sym.len(),
+            cols.entry_time.len(),
+            cols.exit_time.len(),
+            cols.bars.len(),
+            cols.qty.len(),
+            cols.pnl.len(),
+        ] {
+            assert_eq!(len, n);
+        }
+        assert_eq!(cols.qty, vec![10, 10]);
+        assert_eq!(cols.pnl, vec![10.0, -10.0]);
+        for (i, trade) in stats.trades().into_iter().enumerate() {
+            assert_eq!(cols.exit_time[i], trade.exit_time);
+            assert_eq!(cols.pnl[i], trade.pnl);
+        }
+    }
+
+    #[test]
+    fn test_stale_price_policy() {
+        let start = chrono::Utc::now();
+        let run = |policy| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .stale_price_policy(policy)
+                .build()
+                .unwrap();
+            for sym in ["a", "b"] {
+                let mut fill = build_test_fill(10, 10.0, 0.0);
+                fill.sym = sym.into();
+                p.pay((fill.qty * fill.price).0).unwrap();
+                p.update_from_fill(&fill).unwrap();
+            }
+            // the data of b ends after the first day
+            for (day, syms) in [(0, vec!["a", "b"]), (1, vec!["a"]), (2, vec!["a"])] {
+                let time = start + chrono::Duration::days(day);
+                for sym in syms {
+                    p.update_from_market(&Bar {
+                        sym: sym.into(),
+                        time,
+                        close: 10.0,
+                        ..Default::default()
+                    })
+                    .unwrap();
+                }
+                p.mark_timestamp(time);
+            }
+            p.stats()
+        };
+        let stale = |stats: &PortfolioStats| {
+            stats
+                .positions
+                .iter()
+                .filter(|x| x.stale)
+                .map(|x| x.sym.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let carried = run(StalePricePolicy::Carry);
+        assert!(stale(&carried).is_empty());
+        let flagged = run(StalePricePolicy::Flag);
+        assert_eq!(stale(&flagged), ["b"]);
+        assert_eq!(flagged.pnl, carried.pnl);
+        let recovered = run(StalePricePolicy::Recover(0.5));
+        assert_eq!(stale(&recovered), ["b"]);
+        assert_eq!(recovered.pnl, -50.0);
+    }
+
+    #[test]
+    fn test_expectancy() {
+        let mut stats = PortfolioStats::default();
+        assert_eq!(stats.expectancy(), 0.0);
+
+        let now = chrono::Utc::now();
+        stats.positions.push(Position {
+            trades: [10.0, 20.0, -5.0, -15.0, 30.0]
+                .map(|pnl| Trade {
+                    sym: "test".into(),
+                    entry_time: now,
+                    exit_time: now,
+                    bars: 1,
+                    qty: Qty(10),
+                    pnl,
+                    risk: None,
+                })
+                .to_vec(),
+            ..Default::default()
+        });
+
+        // 3/5 * 20 - 2/5 * 10
+        assert!((stats.expectancy() - 8.0).abs() < 1e-9);
+        assert!((stats.expectancy_r(4.0) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_r_multiple() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(FixedRiskOrderManager {
+                risk: 100.0,
+                price_source: PriceSource::DecisionPrice,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let buy = Decision {
+            strength: 1.0,
+            price: Some(10.0),
+            signal: None,
+            stop: Some(8.0),
+            time: chrono::Utc::now(),
+            sym: "test".into(),
+            kind: DecisionKind::Buy,
+        };
+        let ord = p.allocate_order(&buy).unwrap().unwrap();
+        assert_eq!(ord.qty, 50);
+        // filled in two parts, each carrying its share of the risk
+        for _ in 0..2 {
+            p.update_from_fill(&Fill {
+                risk: Some(50.0),
+                ..build_test_fill(25, 10.0, 0.0)
+            })
+            .unwrap();
+        }
+        // 50 * $4 is twice the risk
+        p.update_from_fill(&build_test_fill(-50, 14.0, 0.0))
+            .unwrap();
+
+        // an entry that never fills leaves no risk behind for the next trade
+        p.allocate_order(&buy).unwrap().unwrap();
+        p.update_from_fill(&build_test_fill(10, 10.0, 0.0)).unwrap();
+        p.update_from_fill(&build_test_fill(-10, 12.0, 0.0))
+            .unwrap();
+
+        let stats = p.stats();
+        assert_eq!(stats.r_multiples(), vec![2.0]);
+        assert_eq!(stats.r_multiple_histogram(0.5), vec![(2.0, 1)]);
+        assert_eq!(stats.trades()[0].risk, Some(100.0));
+        assert_eq!(stats.trades()[1].risk, None);
+    }
+
+    #[test]
+    fn test_max_flat_streak() {
+        let start = chrono::Utc::now();
+        let day = |i| start + chrono::Duration::days(i);
+        let mut stats = PortfolioStats {
+            equity_curve: (0..40).map(|i| (day(i), 1000.0)).collect(),
+            ..Default::default()
+        };
+        assert_eq!(stats.max_flat_streak(), 40);
+
+        stats.positions.push(Position {
+            trades: vec![Trade {
+                sym: "test".into(),
+                entry_time: day(0),
+                exit_time: day(5),
+                bars: 5,
+                qty: Qty(10),
+                pnl: 0.0,
+                risk: None,
+            }],
+            // still open since day 25
+            qty: Qty(10),
+            entry_time: Some(day(25)),
+            ..Default::default()
+        });
+        // flat from day 5 to day 24
+        assert_eq!(stats.max_flat_streak(), 20);
+    }
+
+    #[test]
+    fn test_trade_pnl_stddev() {
+        let now = chrono::Utc::now();
+        let trades = |pnls: &[f64]| {
+            let position = Position {
+                trades: pnls
+                    .iter()
+                    .map(|pnl| Trade {
+                        sym: "test".into(),
+                        entry_time: now,
+                        exit_time: now,
+                        bars: 1,
+                        qty: Qty(10),
+                        pnl: *pnl,
+                        risk: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+            PortfolioStats {
+                positions: vec![position],
+                ..Default::default()
+            }
+        };
+
+        let stats = trades(&[]);
+        assert_eq!(stats.avg_trade_pnl(), 0.0);
+        assert_eq!(stats.trade_pnl_stddev(), 0.0);
+        let stats = trades(&[10.0]);
+        assert_eq!(stats.avg_trade_pnl(), 10.0);
+        assert_eq!(stats.trade_pnl_stddev(), 0.0);
+
+        // squared deviations from 8 sum to 1330
+        let stats = trades(&[10.0, 20.0, -5.0, -15.0, 30.0]);
+        assert!((stats.avg_trade_pnl() - 8.0).abs() < 1e-9);
+        assert!((stats.trade_pnl_stddev() - (1330.0f64 / 4.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_twr() {
+        let start = chrono::Utc::now();
+        let stats = |deposit| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .build()
+                .unwrap();
+            let fill = build_test_fill(100, 10.0, 0.0);
+            p.pay((fill.qty * fill.price).0).unwrap();
+            p.update_from_fill(&fill).unwrap();
+            for (i, close) in [10.0, 11.0, 11.0, 11.0].into_iter().enumerate() {
+                let time = start + chrono::Duration::days(i as i64);
+                if i == 2 && deposit {
+                    p.deposit(time, 1000.0);
+                }
+                p.update_from_market(&Bar {
+                    sym: "test".into(),
+                    close,
+                    ..Default::default()
+                })
+                .unwrap();
+                p.mark_timestamp(time);
+            }
+            p.stats()
+        };
+
+        let (base, deposited) = (stats(false), stats(true));
+        assert!((base.twr() - 0.1).abs() < 1e-12);
+        assert!((deposited.twr() - base.twr()).abs() < 1e-12);
+        assert!(deposited.total_return > base.total_return);
+        assert_eq!(deposited.reconcile(), Ok(()));
+    }
+
+    #[test]
+    fn test_cash_policy() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(100.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .cash_policy(CashPolicy::Clamp)
+            .build()
+            .unwrap();
+
+        // a broker paying for 20 without checking the cash
+        let fill = build_test_fill(20, 10.0, 0.0);
+        p.set_balance(p.balance() - (fill.qty * fill.price).0);
+        p.update_from_fill(&fill).unwrap();
+
+        assert_eq!(p.positions["test"].qty, 10);
+        assert_eq!(p.cash, 0.0);
+    }
+
+    #[test]
+    fn test_max_drawdown_abs() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(100.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        let start = chrono::Utc::now();
+        p.equity_curve = [100.0, 50.0, 1000.0, 800.0]
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| (start + chrono::Duration::days(i as i64), x))
+            .collect();
+
+        // the deepest decline in ratio is the smallest in currency
+        let stats = p.stats();
+        assert_eq!(stats.max_drawdown, 0.5);
+        assert_eq!(stats.max_drawdown_abs, 200.0);
+    }
+
+    #[test]
+    fn test_rolling_metrics() {
+        let curve = [100.0, 110.0, 90.0, 120.0, 120.0, 132.0];
+        let stats = PortfolioStats {
+            equity_curve: curve.iter().map(|x| (chrono::Utc::now(), *x)).collect(),
+            ..Default::default()
+        };
+
+        let rets = stats.rolling_return(3);
+        assert_eq!(rets.len(), curve.len() - 3 + 1);
+        assert!((rets[0] - (90.0 / 100.0 - 1.0)).abs() < 1e-9);
+        assert!((rets[3] - (132.0 / 120.0 - 1.0)).abs() < 1e-9);
+
+        let sharpe = stats.rolling_sharpe(3);
+        assert_eq!(sharpe.len(), curve.len() - 3 + 1);
+        assert!(sharpe[0] < 0.0);
+        assert!(sharpe[3] > 0.0);
+
+        assert!(stats.rolling_return(curve.len() + 1).is_empty());
+        assert!(stats.rolling_sharpe(curve.len() + 1).is_empty());
+    }
+
+    #[test]
+    fn test_pnl_by_symbol() {
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(10000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .build()
+            .unwrap();
+        for (sym, close) in [("a", 12.0), ("b", 9.0), ("c", 15.0)] {
+            let fill = Fill {
+                sym: sym.into(),
+                ..build_test_fill(10, 10.0, 1.0)
+            };
+            p.pay((fill.qty * fill.price).0 + fill.cost).unwrap();
+            p.update_from_fill(&fill).unwrap();
+            p.update_from_market(&Bar {
+                sym: sym.into(),
+                close,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        let stats = p.stats();
+        let pnls = stats.pnl_by_symbol();
+        assert_eq!(
+            pnls,
+            vec![("c".into(), 49.0), ("a".into(), 19.0), ("b".into(), -11.0)]
+        );
+        let total = pnls.iter().map(|x| x.1).sum::<f64>();
+        assert!((total - stats.pnl).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rounded() {
+        let stats = PortfolioStats {
+            pnl: 12.34567,
+            fees_paid: 0.005,
+            sharpe: 0.123456,
+            ..Default::default()
+        };
+        let rounded = stats.rounded(2);
+        assert_eq!(rounded.pnl, 12.35);
+        assert_eq!(rounded.fees_paid, 0.01);
+        // ratios are left alone, the original too
+        assert_eq!(rounded.sharpe, 0.123456);
+        assert_eq!(stats.pnl, 12.34567);
+    }
+
+    #[test]
+    fn test_kelly_leverage() {
+        let start = chrono::Utc::now();
+        let rets = [0.02, -0.01, 0.03, 0.0, -0.02, 0.04];
+        let mut equity = vec![(start, 1000.0)];
+        for (i, r) in rets.iter().enumerate() {
+            let time = start + chrono::Duration::days(i as i64 + 1);
+            equity.push((time, equity.last().unwrap().1 * (1.0 + r)));
+        }
+        let mut stats = PortfolioStats {
+            equity_curve: equity,
+            ..Default::default()
+        };
+
+        // mean 0.01, sample variance 0.0028 / 5
+        assert!((stats.kelly_leverage() - 0.01 / 0.00056).abs() < 1e-6);
+
+        stats.equity_curve.iter_mut().for_each(|x| x.1 = 1000.0);
+        assert_eq!(stats.kelly_leverage(), 0.0);
+    }
+
+    #[test]
+    fn test_martin_ratio() {
+        let start = chrono::Utc::now();
+        let mut stats = PortfolioStats {
+            total_return: 0.1,
+            equity_curve: [100.0, 90.0, 80.0, 100.0, 110.0]
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| (start + chrono::Duration::days(i as i64), x))
+                .collect(),
+            ..Default::default()
+        };
+        // drawdowns of 10% and 20%
+        let ui = (0.05f64 / 5.0).sqrt();
+        assert!((stats.ulcer_index() - ui).abs() < 1e-12);
+        assert!((stats.martin_ratio() - 0.1 / ui).abs() < 1e-9);
+
+        stats.equity_curve.truncate(1);
+        assert_eq!(stats.martin_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_pnl_by_period() {
+        let day = |y, m, d| {
+            chrono::NaiveDate::from_ymd_opt(y, m, d)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+        };
+        let stats = PortfolioStats {
+            init_cash: 1000.0,
+            equity_curve: vec![
+                (day(2020, 1, 2), 1010.0),
+                (day(2020, 6, 30), 1100.0),
+                (day(2020, 12, 31), 1050.0),
+                (day(2021, 3, 1), 1500.0),
+                (day(2021, 12, 31), 1450.0),
+            ],
+            // a deposit of 400 in 2021, not counted as pnl
+            cash_flows: vec![(day(2021, 3, 1), 400.0)],
+            ..Default::default()
+        };
+
+        let years = stats.pnl_by_period(Period::Year);
+        assert_eq!(years, vec![("2020".into(), 50.0), ("2021".into(), 0.0)]);
+        assert_eq!(
+            years.iter().map(|x| x.1).sum::<f64>(),
+            1450.0 - 1000.0 - 400.0
+        );
+
+        let quarters = stats.pnl_by_period(Period::Quarter);
+        assert_eq!(
+            quarters,
+            vec![
+                ("2020Q1".into(), 10.0),
+                ("2020Q2".into(), 90.0),
+                ("2020Q4".into(), -50.0),
+                ("2021Q1".into(), 50.0),
+                ("2021Q4".into(), -50.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_benchmark() {
+        let closes = crate::data::tests::test_data_iter()
+            .take(500)
+            .map(|x| (x.time, x.close))
+            .collect::<Vec<_>>();
+        let mut p = SimplePortfolioBuilder::default()
+            .cash(1000.0)
+            .order_manager(crate::order::FixedSizeOrderManager::default())
+            .benchmark(closes.clone())
+            .build()
+            .unwrap();
+        assert_eq!(p.stats().benchmark, None);
+
+        // an equity tracking the benchmark exactly
+        p.equity_curve = closes.iter().map(|x| (x.0, x.1 * 100.0)).collect();
+        let stats = p.stats().benchmark.unwrap();
+        assert!(stats.alpha.abs() < 1e-12);
+        assert!((stats.beta - 1.0).abs() < 1e-9);
+        assert!(stats.excess_return.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rolling_beta() {
+        let start = chrono::Utc::now();
+        let (mut bench, mut equity) = (vec![(start, 100.0)], vec![(start, 1000.0)]);
+        for i in 1..=20 {
+            let time = start + chrono::Duration::days(i);
+            let ret = [0.01, -0.02, 0.03, -0.01][i as usize % 4];
+            // twice as sensitive to the benchmark from the 10th period
+            let beta = if i <= 10 { 1.0 } else { 2.0 };
+            bench.push((time, bench.last().unwrap().1 * (1.0 + ret)));
+            equity.push((time, equity.last().unwrap().1 * (1.0 + beta * ret)));
+        }
+        let stats = PortfolioStats {
+            equity_curve: equity,
+            ..Default::default()
+        };
+
+        let betas = stats.rolling_beta(&bench, 4);
+        assert_eq!(betas.len(), 20 - 4 + 1);
+        assert!((betas[0].1 - 1.0).abs() < 1e-9);
+        assert!((betas.last().unwrap().1 - 2.0).abs() < 1e-9);
+        assert_eq!(betas[0].0, bench[4].0);
+        assert!(stats.rolling_beta(&bench, 21).is_empty());
+        assert!(stats.rolling_beta(&bench, 1).is_empty());
+    }
+
+    #[test]
+    fn test_risk_free_sharpe() {
+        let start = chrono::Utc::now();
+        let sharpe = |streaming, rate, series: bool| {
+            let mut p = SimplePortfolioBuilder::default()
+                .cash(1000.0)
+                .order_manager(crate::order::FixedSizeOrderManager::default())
+                .streaming_stats(streaming)
+                .risk_free_rate(if series { 0.0 } else { rate })
+                .risk_free(if series { vec![(start, rate)] } else { vec![] })
+                .build()
+                .unwrap();
+            p.update_from_fill(&build_test_fill(10, 10.0, 0.0)).unwrap();
+            for (i, close) in [10.0, 12.0, 11.0, 15.0, 14.0].into_iter().enumerate() {
+                p.update_from_market(&Bar {
+                    sym: "test".into(),
+                    close,
+                    ..Default::default()
+                })
+                .unwrap();
+                p.mark_timestamp(start + chrono::Duration::days(i as i64));
+            }
+            p.stats().sharpe
+        };
+
+        for streaming in [false, true] {
+            let base = sharpe(streaming, 0.0, false);
+            assert!(base > 0.0);
+            let flat = sharpe(streaming, 0.01, false);
+            assert!(flat < base);
+            assert!((sharpe(streaming, 0.01, true) - flat).abs() < 1e-12);
+        }
     }
 
     fn build_test_fill(qty: i32, price: f64, cost: f64) -> Fill {
         Fill {
+            order_id: 0,
+            risk: None,
             time: chrono::Utc::now(),
-            qty,
+            qty: Qty(qty),
             sym: "test".into(),
-            price,
+            price: Price(price),
             cost,
         }
     }
@@ -1,33 +1,164 @@
 use serde::Serialize;
+use std::{collections::VecDeque, sync::Arc};
 
 use crate::{
+    data::DateTime,
     data::{Bar, Symbol},
     errors::ErrorRepr,
+    metrics,
     order::Fill,
+    units::{Price, Qty},
 };
 
+/// value of a quantity at a price, e.g. with a contract multiplier
+pub trait Valuation: std::fmt::Debug + Send + Sync {
+    fn value(&self, qty: Qty, price: f64) -> f64;
+}
+
+/// `qty * price`, how positions are marked without a valuation
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Linear;
+
+impl Valuation for Linear {
+    fn value(&self, qty: Qty, price: f64) -> f64 {
+        f64::from(qty) * price
+    }
+}
+
+/// `qty * price * multiplier`, e.g. the point value of a futures contract
+#[derive(Debug, Clone, Copy)]
+pub struct Multiplier(pub f64);
+
+impl Valuation for Multiplier {
+    fn value(&self, qty: Qty, price: f64) -> f64 {
+        f64::from(qty) * price * self.0
+    }
+}
+
+/// which price of the latest bar positions are marked at
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum MarkSource {
+    Open,
+    #[default]
+    Close,
+}
+
+/// how positions are booked and marked, passed down by their portfolio on every update
+/// so that a change of the portfolio's settings reaches the open positions
+#[derive(Debug, Clone, Default)]
+pub struct PositionSettings {
+    /// negative quantities are allowed
+    pub allow_short: bool,
+    /// the open or close of the latest bar to mark at, the close before the first open
+    pub mark_source: MarkSource,
+    /// quoted spread around the close, longs are marked at the bid and shorts at the ask
+    pub mark_spread: f64,
+    /// how the price changes of the lots are valued, linearly if None. fills are settled
+    /// at `qty * price`, closing lots realizes the rest in `stats.valuation_pnl`
+    pub valuation: Option<Arc<dyn Valuation>>,
+}
+
 #[derive(Clone, Debug, Default, Serialize)]
 pub struct Position {
     pub sym: Symbol,
-    pub qty: i32,
+    pub qty: Qty,
+    /// number of orders buying into the long position since last flat, partial fills of
+    /// an order counted once
+    pub entries: usize,
+    /// order the latest entry was counted for
+    #[serde(skip)]
+    pub entry_order_id: Option<u64>,
+    /// number of bars seen
+    pub bars: usize,
+    /// index of the bar the current position was opened on
+    pub entry_bar: Option<usize>,
+    /// time of the fill that opened the current position
+    pub entry_time: Option<DateTime>,
+    /// pnl when the current position was opened
+    pub entry_pnl: f64,
+    /// loss of the current position if stopped out, see `Fill::risk`
+    pub risk: Option<f64>,
+    /// largest size of the current position, negative for shorts
+    pub peak_qty: Qty,
+    /// index of the bar the last losing round trip was closed on
+    pub last_loss_bar: Option<usize>,
     pub latest_market_close: Option<f64>,
+    /// open of the latest bar
+    pub latest_market_open: Option<f64>,
+    /// time of the latest bar
+    pub latest_market_time: Option<DateTime>,
+    /// the symbol stopped printing bars while the position is open
+    pub stale: bool,
+    /// open lots in FIFO order, all on the side of the position
+    #[serde(skip)]
+    pub lots: VecDeque<(Qty, Price)>,
+    /// every fill that reduced the position
+    pub exits: Vec<Exit>,
+    /// round trips closed so far
+    pub trades: Vec<Trade>,
+    /// the latest fill closed the last of `trades`, possibly reversing the position
+    #[serde(skip)]
+    pub trade_closed: bool,
     #[serde(flatten)]
     pub stats: PositionStats,
 }
 
+/// a fill closing (part of) a position, matched against the open lots in FIFO order
+#[derive(Debug, Clone, Serialize)]
+pub struct Exit {
+    pub time: DateTime,
+    pub qty: Qty,
+    pub price: Price,
+    /// pnl against the matched lots, net of the commission of the exit
+    pub realized_pnl: f64,
+}
+
+/// a round trip from flat back to flat
+#[derive(Debug, Clone, Serialize)]
+pub struct Trade {
+    pub sym: Symbol,
+    pub entry_time: DateTime,
+    pub exit_time: DateTime,
+    /// number of bars the position was held
+    pub bars: usize,
+    /// largest size held, negative for shorts
+    pub qty: Qty,
+    /// pnl of the round trip, net of costs
+    pub pnl: f64,
+    /// loss if stopped out, as planned when it was opened
+    pub risk: Option<f64>,
+}
+
+impl Trade {
+    /// pnl in multiples of the risk, None without a risk
+    pub fn r_multiple(&self) -> Option<f64> {
+        self.risk.filter(|x| *x > 0.0).map(|x| self.pnl / x)
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct PositionStats {
     pub pnl: f64,
     pub pnl_ratio: f64,
     pub max_pnl: f64,
     pub min_pnl: f64,
+    /// largest decline of the pnl from its running peak
+    pub max_position_drawdown: f64,
     pub qty_sold: i32,
     pub qty_bought: i32,
     pub value_sold: f64,
     pub value_bought: f64,
     pub cost: f64,
     pub max_cash: f64,
+    /// largest cost basis of the open lots ever held
+    pub max_invested: f64,
+    /// pnl of the closed lots under the valuation beyond their linear pnl, settled in cash
+    /// on top of `qty * price`
+    pub valuation_pnl: f64,
     pub transactions: Vec<Fill>,
+    /// change of the marked pnl over each bar the position was open
+    #[serde(skip)]
+    pub pnl_changes: Vec<f64>,
 }
 
 impl Default for PositionStats {
@@ -37,13 +168,17 @@ impl Default for PositionStats {
             pnl_ratio: 0.0,
             max_pnl: f64::MIN,
             min_pnl: f64::MAX,
+            max_position_drawdown: 0.0,
             qty_sold: 0,
             qty_bought: 0,
             value_sold: 0.0,
             value_bought: 0.0,
             cost: 0.0,
             max_cash: 0.0,
+            max_invested: 0.0,
+            valuation_pnl: 0.0,
             transactions: vec![],
+            pnl_changes: vec![],
         }
     }
 }
@@ -59,9 +194,9 @@ impl PositionStats {
 
     fn update_from_fill(&mut self, fill: &Fill) {
         self.transactions.push(fill.clone());
-        let (prc, qty, cost) = (fill.price, fill.qty, fill.cost);
+        let (qty, cost) = (fill.qty.0, fill.cost);
         self.cost += cost;
-        let cur_val = prc * qty as f64;
+        let cur_val = (fill.qty * fill.price).0;
 
         if qty < 0 {
             self.qty_sold += -qty;
@@ -73,10 +208,17 @@ impl PositionStats {
         }
     }
 
+    /// per-bar sharpe ratio of the pnl changes while open, the position's contribution
+    /// to the risk-adjusted return
+    pub fn sharpe(&self) -> f64 {
+        metrics::sharpe(&self.pnl_changes)
+    }
+
     fn update_pnl(&mut self, pnl: f64) {
         self.pnl = pnl;
         self.min_pnl = self.min_pnl.min(pnl);
         self.max_pnl = self.max_pnl.max(pnl);
+        self.max_position_drawdown = self.max_position_drawdown.max(self.max_pnl - pnl);
         if self.max_cash != 0.0 {
             self.pnl_ratio = self.pnl / self.max_cash
         }
@@ -84,55 +226,391 @@ impl PositionStats {
 }
 
 impl Position {
-    pub fn update_from_fill(&mut self, fill: &Fill) -> Result<(), ErrorRepr> {
+    pub fn update_from_fill(
+        &mut self,
+        fill: &Fill,
+        settings: &PositionSettings,
+    ) -> Result<(), ErrorRepr> {
         let qty = fill.qty;
-        if self.qty + qty < 0 {
+        if !settings.allow_short && self.qty + qty < 0 {
             return Err(ErrorRepr::OutOfBounds(format!(
                 "no enough quantity. current: {:.2}, need: {:.2}",
                 self.qty, qty
             )));
         }
+        let after = self.qty + qty;
+        if !self.qty.is_zero() && !after.is_zero() && after.signum() != self.qty.signum() {
+            // a reversal closes the trade at zero and opens the other side with the rest
+            let share = f64::from(after) / f64::from(qty);
+            let flatten = Fill {
+                qty: -self.qty,
+                cost: fill.cost * (1.0 - share),
+                risk: None,
+                ..fill.clone()
+            };
+            let open = Fill {
+                qty: after,
+                cost: fill.cost * share,
+                risk: fill.risk.map(|x| x * share),
+                ..fill.clone()
+            };
+            self.update_from_fill(&flatten, settings)?;
+            self.update_from_fill(&open, settings)?;
+            self.trade_closed = true;
+            return Ok(());
+        }
+        self.trade_closed = false;
+        if self.qty == 0 {
+            self.entry_bar = Some(self.bars);
+            self.entry_time = Some(fill.time);
+            self.entry_pnl = self.pnl(settings);
+            self.risk = None;
+        }
+        if let (Some(risk), true) = (fill.risk, (self.qty + qty).abs() > self.qty.abs()) {
+            self.risk = Some(self.risk.unwrap_or(0.0) + risk);
+        }
+        let adds = qty > 0 && self.qty >= 0;
         self.qty += qty;
+        if self.qty.abs() > self.peak_qty.abs() {
+            self.peak_qty = self.qty;
+        }
+        self.match_lots(fill, settings);
+        self.stats.max_invested = self.stats.max_invested.max(self.invested());
         self.stats.update_from_fill(fill);
-        self.stats.update_pnl(self.pnl());
+        self.stats.update_pnl(self.pnl(settings));
+        if self.qty == 0 {
+            let pnl = self.pnl(settings) - self.entry_pnl;
+            self.trades.push(Trade {
+                sym: fill.sym.clone(),
+                entry_time: self.entry_time.take().unwrap_or(fill.time),
+                exit_time: fill.time,
+                bars: self.bars_held().unwrap_or(0),
+                qty: std::mem::take(&mut self.peak_qty),
+                pnl,
+                risk: self.risk.take(),
+            });
+            self.trade_closed = true;
+            self.entries = 0;
+            self.entry_order_id = None;
+            self.entry_bar = None;
+            if pnl < 0.0 {
+                self.last_loss_bar = Some(self.bars);
+            }
+        } else if adds && (fill.order_id == 0 || self.entry_order_id != Some(fill.order_id)) {
+            // fills from outside of the simulation have no order to group them by
+            self.entries += 1;
+            self.entry_order_id = Some(fill.order_id);
+        }
         Ok(())
     }
 
-    pub fn update_from_market(&mut self, data: Bar) {
+    fn match_lots(&mut self, fill: &Fill, settings: &PositionSettings) {
+        let mut rem = fill.qty;
+        let mut closed = Qty(0);
+        let mut pnl = 0.0;
+        let mut adjustment = 0.0;
+        while let Some((lot_qty, lot_price)) = self.lots.front_mut() {
+            if rem.is_zero() || lot_qty.signum() == rem.signum() {
+                break;
+            }
+            let take = if lot_qty.abs() <= rem.abs() {
+                *lot_qty
+            } else {
+                -rem
+            };
+            pnl += (take * fill.price - take * *lot_price).0;
+            if let Some(v) = &settings.valuation {
+                let (exit, entry) = (fill.price.0, lot_price.0);
+                adjustment +=
+                    v.value(take, exit) - v.value(take, entry) - f64::from(take) * (exit - entry);
+            }
+            closed += take;
+            rem += take;
+            *lot_qty -= take;
+            if lot_qty.is_zero() {
+                self.lots.pop_front();
+            }
+        }
+        if !rem.is_zero() {
+            self.lots.push_back((rem, fill.price));
+        }
+        pnl += adjustment;
+        self.stats.valuation_pnl += adjustment;
+        if !closed.is_zero() {
+            self.exits.push(Exit {
+                time: fill.time,
+                qty: -closed,
+                price: fill.price,
+                realized_pnl: pnl - fill.cost,
+            });
+        }
+    }
+
+    /// cost basis of the open lots
+    pub fn invested(&self) -> f64 {
+        self.lots
+            .iter()
+            .map(|(qty, price)| (qty.abs() * *price).0)
+            .sum()
+    }
+
+    /// realized plus unrealized pnl over the peak capital deployed, 0 if nothing was
+    pub fn roic(&self, settings: &PositionSettings) -> f64 {
+        if self.stats.max_invested == 0.0 {
+            0.0
+        } else {
+            self.pnl(settings) / self.stats.max_invested
+        }
+    }
+
+    pub fn update_from_market(&mut self, data: Bar, settings: &PositionSettings) {
+        self.bars += 1;
+        let prev = self.latest_market_close.map(|_| self.pnl(settings));
         self.latest_market_close.replace(data.close);
-        self.stats.update_pnl(self.pnl());
+        self.latest_market_open.replace(data.open);
+        self.latest_market_time.replace(data.time);
+        self.stale = false;
+        let pnl = self.pnl(settings);
+        if let (Some(prev), false) = (prev, self.qty.is_zero()) {
+            self.stats.pnl_changes.push(pnl - prev);
+        }
+        self.stats.update_pnl(pnl);
+    }
+
+    /// flag the position as stale, marking it at `recovery` times the latest close if given
+    pub fn mark_stale(&mut self, recovery: Option<f64>, settings: &PositionSettings) {
+        self.stale = true;
+        if let (Some(r), Some(close)) = (recovery, self.latest_market_close) {
+            self.latest_market_close = Some(close * r);
+            self.latest_market_open = self.latest_market_open.map(|x| x * r);
+            self.stats.update_pnl(self.pnl(settings));
+        }
+    }
+
+    /// number of bars since the current position was opened
+    pub fn bars_held(&self) -> Option<usize> {
+        self.entry_bar.map(|x| self.bars - x)
     }
 
-    pub fn pnl(&self) -> f64 {
-        self.qty as f64 * self.latest_market_close.unwrap_or(self.stats.avg_price())
-            + self.stats.value_sold
+    /// number of bars since the last losing round trip was closed
+    pub fn bars_since_loss(&self) -> Option<usize> {
+        self.last_loss_bar.map(|x| self.bars - x)
+    }
+
+    /// price the position would be liquidated at: the latest close, or open by the
+    /// `mark_source` of `settings`, less half the spread for longs, plus half the spread for shorts
+    pub fn mark_price(&self, settings: &PositionSettings) -> Option<f64> {
+        let price = match settings.mark_source {
+            MarkSource::Open => self.latest_market_open.or(self.latest_market_close),
+            MarkSource::Close => self.latest_market_close,
+        }?;
+        Some(price - self.qty.signum() as f64 * settings.mark_spread / 2.0)
+    }
+
+    /// market value of the position at its mark price
+    pub fn market_value(&self, settings: &PositionSettings) -> f64 {
+        self.mark_price(settings).map_or(0.0, |price| {
+            f64::from(self.qty) * price + self.valuation_adjustment(price, settings)
+        })
+    }
+
+    /// pnl of the open lots at the mark price
+    pub fn unrealized_pnl(&self, settings: &PositionSettings) -> f64 {
+        let Some(close) = self.mark_price(settings) else {
+            return 0.0;
+        };
+        let linear = self
+            .lots
+            .iter()
+            .map(|(qty, price)| f64::from(*qty) * (close - price.0))
+            .sum::<f64>();
+        linear + self.valuation_adjustment(close, settings)
+    }
+
+    /// pnl of the open lots at `price` under the valuation of `settings` less their
+    /// linear pnl
+    fn valuation_adjustment(&self, price: f64, settings: &PositionSettings) -> f64 {
+        let Some(v) = &settings.valuation else {
+            return 0.0;
+        };
+        self.lots
+            .iter()
+            .map(|&(qty, entry)| {
+                v.value(qty, price) - v.value(qty, entry.0) - f64::from(qty) * (price - entry.0)
+            })
+            .sum()
+    }
+
+    /// close at which the position would be flat on pnl, commissions paid so far included.
+    /// None when flat
+    pub fn break_even_price(&self) -> Option<f64> {
+        if self.qty.is_zero() {
+            return None;
+        }
+        let s = &self.stats;
+        Some((s.value_bought + s.cost - s.value_sold) / f64::from(self.qty))
+    }
+
+    /// pnl of the closed quantity, net of all costs
+    pub fn realized_pnl(&self, settings: &PositionSettings) -> f64 {
+        self.pnl(settings) - self.unrealized_pnl(settings)
+    }
+
+    pub fn pnl(&self, settings: &PositionSettings) -> f64 {
+        let price = self.mark_price(settings).unwrap_or(self.stats.avg_price());
+        self.pnl_at(price, settings)
+    }
+
+    /// pnl as if marked at `price`, for scenario analysis
+    pub fn pnl_at(&self, price: f64, settings: &PositionSettings) -> f64 {
+        f64::from(self.qty) * price + self.stats.value_sold
             - self.stats.value_bought
             - self.stats.cost
+            + self.stats.valuation_pnl
+            + self.valuation_adjustment(price, settings)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::units::Price;
 
     fn build_test_fill(qty: i32, price: f64, cost: f64) -> Fill {
         Fill {
+            order_id: 0,
+            risk: None,
             time: chrono::Utc::now(),
-            qty,
+            qty: Qty(qty),
             sym: "test".into(),
-            price,
+            price: Price(price),
             cost,
         }
     }
 
     #[test]
-    fn test_position() {
+    fn test_exit_pnl() {
+        let (mut pos, s) = (Position::default(), PositionSettings::default());
+        pos.update_from_fill(&build_test_fill(100, 10.0, 0.0), &s)
+            .unwrap();
+        pos.update_from_fill(&build_test_fill(200, 13.0, 0.0), &s)
+            .unwrap();
+        for price in [11.0, 12.0, 14.0] {
+            pos.update_from_fill(&build_test_fill(-100, price, 0.0), &s)
+                .unwrap();
+        }
+
+        let pnl = pos.exits.iter().map(|x| x.realized_pnl).collect::<Vec<_>>();
+        assert_eq!(pnl, vec![100.0, -100.0, 100.0]);
+        assert!(pos.exits.iter().all(|x| x.qty == -100));
+        assert!(pos.lots.is_empty());
+        assert_eq!(pnl.iter().sum::<f64>(), pos.pnl(&s));
+    }
+
+    #[test]
+    fn test_break_even_price() {
+        let (mut pos, s) = (Position::default(), PositionSettings::default());
+        assert_eq!(pos.break_even_price(), None);
+
+        pos.update_from_fill(&build_test_fill(10, 10.0, 5.0), &s)
+            .unwrap();
+        assert_eq!(pos.break_even_price(), Some(10.5));
+        pos.latest_market_close = Some(10.5);
+        assert_eq!(pos.pnl(&s), 0.0);
+    }
+
+    #[test]
+    fn test_pnl_at() {
+        let (mut pos, s) = (Position::default(), PositionSettings::default());
+        pos.update_from_fill(&build_test_fill(10, 10.0, 0.0), &s)
+            .unwrap();
+        pos.latest_market_close = Some(12.0);
+
+        assert_eq!(pos.pnl_at(15.0, &s), 50.0);
+        assert_eq!(pos.pnl_at(8.0, &s), -20.0);
+        assert_eq!(pos.latest_market_close, Some(12.0));
+        assert_eq!(pos.pnl(&s), 20.0);
+    }
+
+    #[test]
+    fn test_sharpe() {
+        let run = |closes: &[f64]| {
+            let (mut pos, s) = (Position::default(), PositionSettings::default());
+            pos.update_from_fill(&build_test_fill(10, 10.0, 0.0), &s)
+                .unwrap();
+            for &close in closes {
+                pos.update_from_market(
+                    Bar {
+                        close,
+                        ..Default::default()
+                    },
+                    &s,
+                );
+            }
+            pos
+        };
+
+        let steady = run(&[10.0, 11.0, 12.1, 13.0, 14.1, 15.0]);
+        let volatile = run(&[10.0, 14.0, 11.0, 16.0, 12.0, 15.0]);
+        assert_eq!(steady.stats.pnl_changes.len(), 5);
+        assert!(steady.stats.sharpe() > volatile.stats.sharpe());
+        assert!(volatile.stats.sharpe() > 0.0);
+    }
+
+    #[test]
+    fn test_roic() {
+        let (mut pos, s) = (Position::default(), PositionSettings::default());
+        assert_eq!(pos.roic(&s), 0.0);
+
+        pos.update_from_fill(&build_test_fill(50, 10.0, 0.0), &s)
+            .unwrap();
+        pos.update_from_fill(&build_test_fill(50, 10.0, 0.0), &s)
+            .unwrap();
+        pos.update_from_fill(&build_test_fill(-100, 12.0, 0.0), &s)
+            .unwrap();
+
+        assert_eq!(pos.stats.max_invested, 1000.0);
+        assert!((pos.roic(&s) - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_reversal() {
         let mut pos = Position::default();
+        let s = PositionSettings {
+            allow_short: true,
+            ..Default::default()
+        };
+        pos.update_from_fill(&build_test_fill(10, 10.0, 0.0), &s)
+            .unwrap();
+        let reverse = Fill {
+            risk: Some(3.0),
+            ..build_test_fill(-15, 12.0, 1.5)
+        };
+        pos.update_from_fill(&reverse, &s).unwrap();
+
+        // the long leg is a closed trade, charged its share of the cost
+        assert_eq!(pos.trades.len(), 1);
+        assert!(pos.trade_closed);
+        assert_eq!(pos.trades[0].qty, 10);
+        assert!((pos.trades[0].pnl - 19.0).abs() < 1e-9);
+        assert_eq!(pos.trades[0].risk, None);
+        // the short leg starts afresh
+        assert_eq!(pos.qty, -5);
+        assert_eq!(pos.peak_qty, -5);
+        assert_eq!(pos.entry_time, Some(reverse.time));
+        assert_eq!(pos.risk, Some(1.0));
+        assert_eq!(pos.lots, [(Qty(-5), Price(12.0))]);
+        assert!((pos.stats.cost - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_position() {
+        let (mut pos, s) = (Position::default(), PositionSettings::default());
         let fill = build_test_fill(-1, 10.0, 1.0);
-        assert!(matches!(pos.update_from_fill(&fill), Err(_)));
+        assert!(pos.update_from_fill(&fill, &s).is_err());
 
         let fill = build_test_fill(10, 10.0, 1.0);
-        assert!(matches!(pos.update_from_fill(&fill), Ok(_)));
+        assert!(pos.update_from_fill(&fill, &s).is_ok());
         assert_eq!(pos.qty, 10);
         assert_eq!(pos.stats.qty_sold, 0);
         assert_eq!(pos.stats.qty_bought, 10);
@@ -142,9 +620,11 @@ mod tests {
         assert_eq!(pos.latest_market_close, None);
 
         let fill = build_test_fill(-5, 20.0, 2.0);
-        let mut bar = Bar::default();
-        bar.close = 20.0;
-        assert!(matches!(pos.update_from_fill(&fill), Ok(_)));
+        let bar = Bar {
+            close: 20.0,
+            ..Default::default()
+        };
+        assert!(pos.update_from_fill(&fill, &s).is_ok());
 
         pos.latest_market_close.replace(bar.close);
         assert_eq!(pos.qty, 5);
@@ -155,13 +635,13 @@ mod tests {
         assert_eq!(pos.stats.cost, 3.0);
         assert_eq!(pos.latest_market_close, Some(bar.close));
 
-        assert_eq!(pos.pnl(), 97.0);
+        assert_eq!(pos.pnl(&s), 97.0);
 
         let fill = build_test_fill(-6, 10.0, 1.0);
-        assert!(matches!(pos.update_from_fill(&fill), Err(_)));
+        assert!(pos.update_from_fill(&fill, &s).is_err());
 
         let fill = build_test_fill(-5, 8.0, 1.0);
-        assert!(matches!(pos.update_from_fill(&fill), Ok(_)));
+        assert!(pos.update_from_fill(&fill, &s).is_ok());
         assert_eq!(pos.qty, 0);
         assert_eq!(pos.stats.qty_sold, 10);
         assert_eq!(pos.stats.qty_bought, 10);
@@ -169,4 +649,13 @@ mod tests {
         assert_eq!(pos.stats.value_bought, 100.0);
         assert_eq!(pos.stats.cost, 4.0);
     }
+
+    #[test]
+    fn test_max_position_drawdown() {
+        let mut stats = PositionStats::default();
+        for pnl in [0.0, 50.0, 10.0, 40.0] {
+            stats.update_pnl(pnl);
+        }
+        assert_eq!(stats.max_position_drawdown, 40.0);
+    }
 }
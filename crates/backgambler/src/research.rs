@@ -0,0 +1,234 @@
+use crate::{
+    data::{returns, Bar},
+    metrics,
+    strategy::{Decision, DecisionMaker},
+};
+
+/// ranks of `xs` starting at 1, ties get the average of their ranks
+pub fn ranks(xs: &[f64]) -> Vec<f64> {
+    let mut idx = (0..xs.len()).collect::<Vec<_>>();
+    idx.sort_by(|a, b| xs[*a].total_cmp(&xs[*b]));
+    let mut ranks = vec![0.0; xs.len()];
+    let mut i = 0;
+    while i < idx.len() {
+        let mut j = i;
+        while j + 1 < idx.len() && xs[idx[j + 1]] == xs[idx[i]] {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        idx[i..=j].iter().for_each(|k| ranks[*k] = rank);
+        i = j + 1;
+    }
+    ranks
+}
+
+/// pearson correlation, 0 if either series has no variance
+pub fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let (sx, sy) = (metrics::std(xs), metrics::std(ys));
+    if xs.len() != ys.len() || sx == 0.0 || sy == 0.0 {
+        return 0.0;
+    }
+    let (mx, my) = (metrics::mean(xs), metrics::mean(ys));
+    let cov = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (x - mx) * (y - my))
+        .sum::<f64>()
+        / (xs.len() - 1) as f64;
+    cov / (sx * sy)
+}
+
+/// pairwise pearson correlations of the return series of a basket, e.g. the `returns`
+/// of bars aligned to a common axis. series of different lengths are compared over
+/// their common tail, as aligning leaves gaps only before the first bar of a symbol
+pub fn correlation_matrix(series: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let tail = |xs: &[f64], n| xs[xs.len() - n..].to_vec();
+    series
+        .iter()
+        .map(|xs| {
+            series
+                .iter()
+                .map(|ys| {
+                    let n = xs.len().min(ys.len());
+                    correlation(&tail(xs, n), &tail(ys, n))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// spearman rank correlation
+pub fn spearman(xs: &[f64], ys: &[f64]) -> f64 {
+    correlation(&ranks(xs), &ranks(ys))
+}
+
+/// information coefficient: rank correlation between the signal of the decision made on
+/// every bar and the return to the next bar. decisions without a signal are left out
+pub fn ic(bars: &[Bar], decisions: &[Decision]) -> f64 {
+    let (signals, forward): (Vec<_>, Vec<_>) = decisions
+        .iter()
+        .zip(returns(bars))
+        .filter_map(|(d, r)| Some((d.signal?, r)))
+        .unzip();
+    spearman(&signals, &forward)
+}
+
+/// decisions of `strategy` over `bars`, precomputed if it supports it
+fn decide(mut strategy: impl DecisionMaker, bars: &[Bar]) -> Vec<Decision> {
+    match strategy.precompute(bars) {
+        Some(decisions) => decisions,
+        None => bars.iter().map(|x| strategy.make_decision(x)).collect(),
+    }
+}
+
+/// run fresh strategies from `make` over `bars` and over a copy with the prices of the
+/// bars after `cutoff` reversed, returning the first bar up to `cutoff` whose decision
+/// differs. a strategy only looking back decides the same on those bars, so a
+/// difference means it peeked at the future, e.g. in `precompute`
+pub fn detect_lookahead<S, F>(make: F, bars: &[Bar], cutoff: usize) -> Option<usize>
+where
+    S: DecisionMaker,
+    F: Fn() -> S,
+{
+    let cutoff = cutoff.min(bars.len().saturating_sub(1));
+    let mut corrupted = bars.to_vec();
+    let future = &mut corrupted[cutoff + 1..];
+    let prices = future.iter().rev().cloned().collect::<Vec<_>>();
+    for (bar, price) in future.iter_mut().zip(prices) {
+        *bar = Bar {
+            sym: bar.sym.clone(),
+            time: bar.time,
+            ..price
+        };
+    }
+
+    let (truth, peek) = (decide(make(), bars), decide(make(), &corrupted));
+    let found =
+        truth.iter().zip(&peek).take(cutoff + 1).position(|(a, b)| {
+            a.kind != b.kind || a.strength != b.strength || a.signal != b.signal
+        });
+    if let Some(i) = found {
+        log::warn!(
+            "look-ahead bias: the decision on bar {} depends on later bars, {:?} vs {:?}",
+            i,
+            truth[i],
+            peek[i]
+        );
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::DecisionKind;
+
+    fn decision(bar: &Bar, up: bool) -> Decision {
+        Decision {
+            sym: bar.sym.clone(),
+            kind: if up {
+                DecisionKind::Buy
+            } else {
+                DecisionKind::Sell
+            },
+            time: bar.time,
+            strength: 1.0,
+            price: Some(bar.close),
+            signal: None,
+            stop: None,
+        }
+    }
+
+    #[test]
+    fn test_correlation_matrix() {
+        let a = vec![0.01, -0.02, 0.03, 0.0, -0.01];
+        let scaled = a.iter().map(|x| 2.0 * x).collect::<Vec<_>>();
+        let inverse = a.iter().map(|x| -x).collect::<Vec<_>>();
+        let m = correlation_matrix(&[a.clone(), scaled, inverse, a[2..].to_vec()]);
+        let expected = [
+            [1.0, 1.0, -1.0, 1.0],
+            [1.0, 1.0, -1.0, 1.0],
+            [-1.0, -1.0, 1.0, -1.0],
+            [1.0, 1.0, -1.0, 1.0],
+        ];
+        for (row, expected) in m.iter().zip(expected) {
+            for (x, y) in row.iter().zip(expected) {
+                assert!((x - y).abs() < 1e-9, "{:?}", m);
+            }
+        }
+    }
+
+    /// buys after an up bar
+    #[derive(Default)]
+    struct Momentum(Option<f64>);
+
+    impl DecisionMaker for Momentum {
+        fn make_decision(&mut self, bar: &Bar) -> Decision {
+            let up = self.0.replace(bar.close).is_some_and(|x| bar.close > x);
+            decision(bar, up)
+        }
+    }
+
+    /// buys before an up bar
+    struct Peeking;
+
+    impl DecisionMaker for Peeking {
+        fn make_decision(&mut self, bar: &Bar) -> Decision {
+            decision(bar, false)
+        }
+
+        fn precompute(&self, bars: &[Bar]) -> Option<Vec<Decision>> {
+            let mut decisions = bars
+                .windows(2)
+                .map(|w| decision(&w[0], w[1].close > w[0].close))
+                .collect::<Vec<_>>();
+            decisions.extend(bars.last().map(|x| decision(x, false)));
+            Some(decisions)
+        }
+    }
+
+    #[test]
+    fn test_detect_lookahead() {
+        let bars = crate::data::tests::test_data_iter()
+            .take(100)
+            .collect::<Vec<_>>();
+        assert_eq!(detect_lookahead(Momentum::default, &bars, 50), None);
+        assert_eq!(detect_lookahead(|| Peeking, &bars, 50), Some(50));
+    }
+
+    #[test]
+    fn test_ranks() {
+        assert_eq!(ranks(&[3.0, 1.0, 2.0, 1.0]), vec![4.0, 1.5, 3.0, 1.5]);
+    }
+
+    #[test]
+    fn test_ic() {
+        let bars = [10.0, 11.0, 9.9, 10.4, 10.4, 12.0].map(|close| Bar {
+            close,
+            ..Default::default()
+        });
+        let decision = |signal| Decision {
+            sym: "test".into(),
+            kind: DecisionKind::Hold,
+            time: chrono::Utc::now(),
+            strength: 1.0,
+            price: None,
+            signal,
+            stop: None,
+        };
+
+        // a perfect foresight of the next return
+        let mut decisions = returns(&bars)
+            .into_iter()
+            .map(|r| decision(Some(r)))
+            .collect::<Vec<_>>();
+        decisions.push(decision(Some(0.0)));
+        assert!((ic(&bars, &decisions) - 1.0).abs() < 1e-12);
+
+        let inverse = decisions
+            .iter()
+            .map(|d| decision(d.signal.map(|x| -x)))
+            .collect::<Vec<_>>();
+        assert!((ic(&bars, &inverse) + 1.0).abs() < 1e-12);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     data::{Bar, DateTime, Symbol},
     order::{Fill, Order},
+    position::Trade,
 };
 
 #[derive(Debug, Clone)]
@@ -8,19 +9,57 @@ pub struct Decision {
     pub sym: Symbol,
     pub kind: DecisionKind,
     pub time: DateTime,
+    /// confidence in `[0, 1]` scaling the order size
+    pub strength: f64,
+    /// reference price, e.g. the close the decision was made on
+    pub price: Option<f64>,
+    /// raw value of the signal behind the decision, for research, see `research::ic`
+    pub signal: Option<f64>,
+    /// price at which the trade would be stopped out, for risk-based sizing
+    pub stop: Option<f64>,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Decision {
+    pub fn clamped_strength(&self) -> f64 {
+        self.strength.clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DecisionKind {
     Hold,
     Buy,
     Sell,
     Close,
+    /// sell this fraction in `[0, 1]` of a long position, e.g. 0.5 to scale out by half
+    SellFraction(f64),
+    /// target exposure in `[-1, 1]` of the allocator's maximum, negative for shorts
+    Target(f64),
 }
 
+/// whether the strategy is still warming up, i.e. its decisions are discarded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WarmupPhase(pub bool);
+
 pub trait DecisionMaker {
+    /// called once per bar, after the portfolio has been marked to it and the orders
+    /// pending from the previous bar have been executed. on the first bar there is no
+    /// prior bar nor position, see `GamblerBuilder::skip_first_decision`
     fn make_decision(&mut self, data: &Bar) -> Decision;
+    /// decisions for every bar at once, for strategies whose signals only depend on the
+    /// bar history. implementations are free to compute them in parallel
+    fn precompute(&self, _: &[Bar]) -> Option<Vec<Decision>> {
+        None
+    }
     fn on_fill(&mut self, _: &Fill) {}
+    /// called when a fill takes a position back to flat
+    fn on_trade_closed(&mut self, _: &Trade) {}
     fn on_order(&mut self, _: &Order) {}
+    /// adjust an allocated order before it is sent, return false to veto it
+    fn on_order_pending(&mut self, _: &mut Order) -> bool {
+        true
+    }
     fn on_data(&mut self, _: &Bar) {}
+    /// called before every `on_data`, see `GamblerBuilder::warmup`
+    fn on_warmup(&mut self, _: WarmupPhase) {}
 }
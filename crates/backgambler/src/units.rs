@@ -0,0 +1,216 @@
+use serde::Serialize;
+
+use crate::TOLERANCE;
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+};
+
+/// price per unit
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Price(pub f64);
+
+/// signed number of units, positive for buys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Qty(pub i32);
+
+/// value of a quantity at a price
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Notional(pub f64);
+
+impl Qty {
+    /// whole units in `x`, rounded towards zero except within `TOLERANCE` of the next
+    /// integer, so that float noise such as `0.3 / 0.1 = 2.9999999999999996` counts as 3
+    pub fn whole(x: f64) -> Self {
+        Qty((x + TOLERANCE * x.signum()).trunc() as i32)
+    }
+
+    pub fn abs(self) -> Self {
+        Qty(self.0.abs())
+    }
+
+    pub fn signum(self) -> i32 {
+        self.0.signum()
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_buy(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_sell(self) -> bool {
+        self.0 < 0
+    }
+}
+
+impl From<i32> for Qty {
+    fn from(v: i32) -> Self {
+        Qty(v)
+    }
+}
+
+impl From<f64> for Price {
+    fn from(v: f64) -> Self {
+        Price(v)
+    }
+}
+
+impl From<Qty> for f64 {
+    fn from(v: Qty) -> Self {
+        v.0 as f64
+    }
+}
+
+impl PartialEq<i32> for Qty {
+    fn eq(&self, other: &i32) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialOrd<i32> for Qty {
+    fn partial_cmp(&self, other: &i32) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+impl PartialEq<f64> for Price {
+    fn eq(&self, other: &f64) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<f64> for Notional {
+    fn eq(&self, other: &f64) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for Qty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add for Qty {
+    type Output = Qty;
+    fn add(self, rhs: Qty) -> Qty {
+        Qty(self.0.checked_add(rhs.0).expect("qty overflow"))
+    }
+}
+
+impl Sub for Qty {
+    type Output = Qty;
+    fn sub(self, rhs: Qty) -> Qty {
+        Qty(self.0.checked_sub(rhs.0).expect("qty overflow"))
+    }
+}
+
+impl AddAssign for Qty {
+    fn add_assign(&mut self, rhs: Qty) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Qty {
+    fn sub_assign(&mut self, rhs: Qty) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for Qty {
+    type Output = Qty;
+    fn neg(self) -> Qty {
+        Qty(self.0.checked_neg().expect("qty overflow"))
+    }
+}
+
+impl Mul<Price> for Qty {
+    type Output = Notional;
+    fn mul(self, rhs: Price) -> Notional {
+        Notional(self.0 as f64 * rhs.0)
+    }
+}
+
+impl Mul<Qty> for Price {
+    type Output = Notional;
+    fn mul(self, rhs: Qty) -> Notional {
+        rhs * self
+    }
+}
+
+impl Add for Notional {
+    type Output = Notional;
+    fn add(self, rhs: Notional) -> Notional {
+        Notional(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Notional {
+    type Output = Notional;
+    fn sub(self, rhs: Notional) -> Notional {
+        Notional(self.0 - rhs.0)
+    }
+}
+
+impl AddAssign for Notional {
+    fn add_assign(&mut self, rhs: Notional) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Neg for Notional {
+    type Output = Notional;
+    fn neg(self) -> Notional {
+        Notional(-self.0)
+    }
+}
+
+/// average price of a notional over a quantity
+impl Div<Qty> for Notional {
+    type Output = Price;
+    fn div(self, rhs: Qty) -> Price {
+        Price(self.0 / rhs.0 as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        let n = Qty(-10) * Price(5.0);
+        assert_eq!(n, Notional(-50.0));
+        assert_eq!(Price(2.0) * Qty(3), Notional(6.0));
+        assert_eq!(n / Qty(-10), Price(5.0));
+        assert_eq!(Qty(3) + Qty(-5), Qty(-2));
+        assert_eq!(-Qty(3), -3);
+        assert!(Qty(1) > 0);
+    }
+
+    #[test]
+    fn test_whole() {
+        assert_eq!(Qty::whole(0.3 / 0.1), 3);
+        assert_eq!(Qty::whole(-0.3 / 0.1), -3);
+        assert_eq!(Qty::whole(2.5), 2);
+        assert_eq!(Qty::whole(-2.5), -2);
+    }
+
+    #[test]
+    #[should_panic(expected = "qty overflow")]
+    fn test_qty_overflow() {
+        let _ = Qty(i32::MAX) + Qty(1);
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::BTreeSet, path::Path};
 
 use backgambler::data::Bar;
 use chrono::Utc;
@@ -21,12 +21,89 @@ pub fn load_tushare_bar_from_csv(
     Ok(rdr
         .into_deserialize()
         .map(|x| x.unwrap())
-        .take_while(|x: &TushareBar| &x.time >= &start)
+        .take_while(|x: &TushareBar| x.time >= start)
         .filter(move |x| x.time >= start && x.time <= end)
         .map(|x| x.into())
         .collect())
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum AlignMode {
+    /// fill the gaps with the previous bar, flat and without volume
+    ForwardFill,
+    /// keep only the timestamps shared by all symbols
+    Skip,
+}
+
+/// align the bars of all symbols to a common date axis in ascending order
+pub fn align_bars(bars_list: Vec<Vec<Bar>>, mode: AlignMode) -> Vec<Vec<Bar>> {
+    let mut bars_list = bars_list
+        .into_iter()
+        .map(|mut bars| {
+            bars.sort_by_key(|x| x.time);
+            bars
+        })
+        .collect::<Vec<_>>();
+
+    let all = bars_list.iter().flatten().map(|x| x.time);
+    let axis: BTreeSet<_> = match mode {
+        AlignMode::ForwardFill => all.collect(),
+        AlignMode::Skip => all
+            .filter(|t| {
+                bars_list
+                    .iter()
+                    .all(|bars| bars.iter().any(|x| &x.time == t))
+            })
+            .collect(),
+    };
+
+    bars_list
+        .iter_mut()
+        .map(|bars| {
+            let mut src = bars.drain(..).peekable();
+            let mut res = Vec::with_capacity(axis.len());
+            for t in &axis {
+                while src.peek().is_some_and(|x| &x.time < t) {
+                    src.next();
+                }
+                match src.next_if(|x| &x.time == t) {
+                    Some(bar) => res.push(bar),
+                    None => {
+                        // nothing to fill before the first bar
+                        if let Some(prev) = res.last() {
+                            let close = prev.close;
+                            res.push(Bar {
+                                sym: prev.sym.clone(),
+                                time: *t,
+                                open: close,
+                                close,
+                                high: close,
+                                low: close,
+                                vol: 0.0,
+                                dividend: 0.0,
+                            });
+                        }
+                    }
+                }
+            }
+            res
+        })
+        .collect()
+}
+
+pub fn load_aligned(
+    paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    start_date: &str,
+    end_date: &str,
+    mode: AlignMode,
+) -> anyhow::Result<Vec<Vec<Bar>>> {
+    let bars_list = paths
+        .into_iter()
+        .map(|path| load_tushare_bar_from_csv(path, start_date, end_date))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(align_bars(bars_list, mode))
+}
+
 mod tushare {
     use backgambler::data::Bar;
     use chrono::Utc;
@@ -68,9 +145,9 @@ mod tushare {
         pub list_date: chrono::DateTime<Utc>,
     }
 
-    impl Into<Bar> for TushareBar {
-        fn into(self) -> Bar {
-            serde_json::from_str(&serde_json::to_string(&self).unwrap()).unwrap()
+    impl From<TushareBar> for Bar {
+        fn from(val: TushareBar) -> Self {
+            serde_json::from_str(&serde_json::to_string(&val).unwrap()).unwrap()
         }
     }
 
@@ -112,3 +189,38 @@ mod tushare {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_bars(sym: &str, days: &[u32]) -> Vec<Bar> {
+        days.iter()
+            .map(|d| Bar {
+                sym: sym.into(),
+                time: format!("2023-02-{:02} 00:00:00Z", d).parse().unwrap(),
+                close: *d as f64,
+                vol: 100.0,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_align_bars() {
+        let a = build_bars("a", &[4, 3, 2, 1]);
+        let b = build_bars("b", &[2, 4, 5]);
+
+        let res = align_bars(vec![a.clone(), b.clone()], AlignMode::Skip);
+        let days = |bars: &Vec<Bar>| bars.iter().map(|x| x.close as u32).collect::<Vec<_>>();
+        assert_eq!(days(&res[0]), vec![2, 4]);
+        assert_eq!(days(&res[1]), vec![2, 4]);
+
+        let res = align_bars(vec![a, b], AlignMode::ForwardFill);
+        assert_eq!(days(&res[0]), vec![1, 2, 3, 4, 4]);
+        assert_eq!(days(&res[1]), vec![2, 2, 4, 5]);
+        assert_eq!(res[0][4].vol, 0.0);
+        assert_eq!(res[0][4].time, res[1][3].time);
+        assert_eq!(res[1][1].time, res[0][2].time);
+    }
+}
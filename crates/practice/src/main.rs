@@ -37,6 +37,10 @@ impl strategy::DecisionMaker for MyStrategy {
         let ma2 = self.ma2.next(data.close);
 
         let mut d = strategy::Decision {
+            strength: 1.0,
+            price: Some(data.close),
+            signal: None,
+            stop: None,
             sym: data.sym.clone(),
             kind: strategy::DecisionKind::Hold,
             time: data.time,
@@ -71,7 +75,7 @@ impl strategy::DecisionMaker for MyStrategy {
     }
 
     fn on_fill(&mut self, f: &order::Fill) {
-        self.qty += f.qty;
+        self.qty += f.qty.0;
         info!("FILL EVENT: {:#?}", f);
     }
 }
@@ -95,18 +99,20 @@ async fn main() -> anyhow::Result<()> {
         );
 
         match res {
-            Ok(bars) if bars.len() > 0 => bars_list.push(bars),
+            Ok(bars) if !bars.is_empty() => bars_list.push(bars),
             Ok(_) => warn!("empty data: {}.csv", idx.sym),
             Err(err) => error!("load {}.csv fail: {}", idx.sym, err),
         }
     }
 
     let cash = 1_000_000_000.0;
+    let weights = bars_list.iter().map(|x| (x[0].sym.clone(), 1.0)).collect();
+    let mut basket = order::BasketAllocator::new(cash, weights);
+    basket.lot = order::LotRules::a_shares();
     let portfolio = portfolio::SimplePortfolioBuilder::default()
-        .order_manager(order::FixedValueOrderManager {
-            val: cash / bars_list.len() as f64,
-        })
+        .order_manager(basket)
         .cash(cash)
+        .max_open_positions(Some(20))
         .build()
         .unwrap();
 
@@ -125,10 +131,31 @@ async fn main() -> anyhow::Result<()> {
         let g = gambler::GamblerBuilder::default()
             .sym(bars[0].sym.clone())
             .strategy(MyStrategy::new())
-            .data(bars.into_iter().rev())
+            .data(bars.into_iter())
+            // tushare data is newest-first
+            .reverse(true)
             .broker(
                 broker::SimulatedBrokerBuilder::default()
                     .commission(0.001)
+                    // the minimum fee differs between the shanghai and shenzhen exchanges
+                    .venue_commission(vec![
+                        (
+                            ".SH".into(),
+                            broker::Commission::RatioMin {
+                                ratio: 0.001,
+                                min: 5.0,
+                            },
+                        ),
+                        (
+                            ".SZ".into(),
+                            broker::Commission::RatioMin {
+                                ratio: 0.001,
+                                min: 1.0,
+                            },
+                        ),
+                    ])
+                    // tushare counts volume in lots of 100 shares
+                    .volume_unit(broker::VolumeUnit::Lots(100))
                     .build()
                     .unwrap(),
             )
@@ -140,9 +167,17 @@ async fn main() -> anyhow::Result<()> {
     }
 
     let mut casino = gambler::Casino::new(gamblers);
-    casino.run().await;
+    casino.run_ordered().await;
 
     let p = portfolio.lock();
-    p.stats().printstd();
+    let stats = p.stats();
+    stats.printstd();
+
+    let pnls = stats.pnl_by_symbol();
+    info!("top contributors: {:?}", &pnls[..pnls.len().min(10)]);
+    info!(
+        "bottom contributors: {:?}",
+        &pnls[pnls.len().saturating_sub(10)..]
+    );
     Ok(())
 }